@@ -0,0 +1,188 @@
+//! Domain switch benchmarks.
+//!
+//! Compares the cost of changing access rights with `mprotect`, `pkey_mprotect`
+//! and `WRPKRU`, and the overhead the guard types add on top of them.
+//!
+//! Run with `cargo bench`. Pass a substring to run only matching benchmarks,
+//! e.g. `cargo bench -- regionguard`.
+
+use mprotect_rs::*;
+
+use std::hint::black_box;
+use std::time::{ Duration, Instant };
+
+/// Minimum measuring time per benchmark.
+const TARGET_TIME: Duration = Duration::from_millis(200);
+
+/// Region counts each multi-region benchmark is run with.
+const REGION_COUNTS: [usize; 3] = [1, 8, 64];
+
+/// Minimal benchmark runner reporting nanoseconds per operation.
+struct Runner {
+    filter: Option<String>,
+}
+
+impl Runner {
+    fn from_args() -> Self {
+        // cargo passes `--bench`; anything else that is not a flag is a name filter.
+        let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+        Runner { filter }
+    }
+
+    /// Runs `op` repeatedly for at least [`TARGET_TIME`] and prints ns/op.
+    fn bench<F: FnMut()>(&self, name: &str, mut op: F) {
+        if self.filter.as_deref().is_some_and(|filter| !name.contains(filter)) {
+            return;
+        }
+
+        // Warm up and find an iteration count that takes a measurable amount of time.
+        let mut iterations: u64 = 1;
+        loop {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                op();
+            }
+            if start.elapsed() >= TARGET_TIME / 10 {
+                break;
+            }
+            iterations *= 2;
+        }
+
+        let mut total_iterations = 0u64;
+        let start = Instant::now();
+        while start.elapsed() < TARGET_TIME {
+            for _ in 0..iterations {
+                op();
+            }
+            total_iterations += iterations;
+        }
+        let ns_per_op = start.elapsed().as_nanos() as f64 / total_iterations as f64;
+        println!("{:<48} {:>12} iters {:>12.1} ns/op", name, total_iterations, ns_per_op);
+    }
+}
+
+fn new_regions<const N: usize>(count: usize) -> Vec<UnsafeProtectedRegion<allocator::Mmap, [u8; N]>> {
+    (0..count)
+        .map(|_| unsafe { UnsafeProtectedRegion::new(AccessRights::READ_WRITE).expect("mmap failed") })
+        .collect()
+}
+
+fn bench_mprotect<const N: usize>(runner: &Runner) {
+    for count in REGION_COUNTS {
+        let regions = new_regions::<N>(count);
+        runner.bench(&format!("mprotect/{}B/x{}", N, count), || {
+            for region in &regions {
+                unsafe {
+                    region.set_access(AccessRights::READ).unwrap();
+                    region.set_access(AccessRights::READ_WRITE).unwrap();
+                }
+            }
+        });
+    }
+}
+
+fn bench_pkey_mprotect<const N: usize>(runner: &Runner) {
+    let Ok(pkey) = (unsafe { PKey::new(PkeyAccessRights::EnableAccessWrite) }) else {
+        println!("pkey_mprotect/{}B: skipped, no protection key available", N);
+        return;
+    };
+    for count in REGION_COUNTS {
+        let regions = new_regions::<N>(count);
+        runner.bench(&format!("pkey_mprotect/{}B/x{}", N, count), || {
+            for region in &regions {
+                unsafe {
+                    pkey.associate(region, AccessRights::READ).unwrap();
+                    pkey.associate(region, AccessRights::READ_WRITE).unwrap();
+                }
+            }
+        });
+        for region in &regions {
+            unsafe { pkey.disassociate(region, AccessRights::READ_WRITE).unwrap() };
+        }
+    }
+}
+
+fn bench_wrpkru(runner: &Runner) {
+    let Ok(pkey) = (unsafe { PKey::new(PkeyAccessRights::EnableAccessWrite) }) else {
+        println!("wrpkru: skipped, no protection key available");
+        return;
+    };
+
+    let pkru_value = unsafe { pkru::rdpkru() };
+    runner.bench("wrpkru/raw", || unsafe {
+        pkru::wrpkru(black_box(pkru_value));
+    });
+    runner.bench("wrpkru/PKey::set_access_rights", || unsafe {
+        pkey.set_access_rights(PkeyAccessRights::DisableWrite).unwrap();
+        pkey.set_access_rights(PkeyAccessRights::EnableAccessWrite).unwrap();
+    });
+}
+
+fn bench_regionguard<const N: usize>(runner: &Runner) {
+    let mut region = RegionGuard::<allocator::Mmap, [u8; N]>::new([0; N], AccessPermissions::NoAccess)
+        .expect("mmap failed");
+    runner.bench(&format!("regionguard/read/{}B", N), || {
+        let guard = region.read().unwrap();
+        black_box(guard[0]);
+    });
+    runner.bench(&format!("regionguard/write/{}B", N), || {
+        let mut guard = region.write().unwrap();
+        guard[0] = black_box(1);
+    });
+
+    let mut region = RegionGuard::<allocator::Mmap, [u8; N]>::new([0; N], AccessPermissions::ReadWrite)
+        .expect("mmap failed");
+    runner.bench(&format!("regionguard/write-default-rw/{}B", N), || {
+        let mut guard = region.write().unwrap();
+        guard[0] = black_box(1);
+    });
+}
+
+fn bench_pkeyguard<const N: usize>(runner: &Runner) {
+    for count in REGION_COUNTS {
+        let Ok(pkey) = PkeyGuard::<allocator::Mmap, [u8; N]>::new(PkeyPermissions::NoAccess) else {
+            println!("pkeyguard/{}B: skipped, no protection key available", N);
+            return;
+        };
+        let mut regions: Vec<_> = (0..count)
+            .map(|_| RegionGuard::<allocator::Mmap, [u8; N]>::new([0; N], AccessPermissions::ReadWrite).expect("mmap failed"))
+            .collect();
+        let mut handlers: Vec<_> = regions
+            .iter_mut()
+            .map(|region| pkey.associate::<PkeyPermissions::NoAccess>(region).unwrap())
+            .collect();
+
+        runner.bench(&format!("pkeyguard/nested-scopes/{}B/x{}", N, count), || {
+            let (outer, inner) = handlers.split_first_mut().unwrap();
+            let rw = outer.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+            {
+                let mut value = rw.mut_ref_guard().unwrap();
+                value[0] = black_box(1);
+            }
+            for handler in inner {
+                let ro = handler.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+                black_box(ro.ref_guard().unwrap()[0]);
+            }
+        });
+    }
+}
+
+fn main() {
+    let runner = Runner::from_args();
+
+    bench_wrpkru(&runner);
+
+    bench_mprotect::<4096>(&runner);
+    bench_mprotect::<65536>(&runner);
+    bench_mprotect::<1048576>(&runner);
+
+    bench_pkey_mprotect::<4096>(&runner);
+    bench_pkey_mprotect::<65536>(&runner);
+    bench_pkey_mprotect::<1048576>(&runner);
+
+    bench_regionguard::<4096>(&runner);
+    bench_regionguard::<65536>(&runner);
+
+    bench_pkeyguard::<4096>(&runner);
+    bench_pkeyguard::<65536>(&runner);
+}
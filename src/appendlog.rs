@@ -0,0 +1,225 @@
+use std::mem::ManuallyDrop;
+
+use crate::mprotect::*;
+use crate::probe;
+use crate::MprotectError;
+
+/// Size of one page of an [`AppendOnlyLog`], and the largest record it accepts.
+pub const LOG_PAGE_SIZE: usize = 4096;
+
+type Page = [u8; LOG_PAGE_SIZE];
+
+/// One page of the log. Sealed pages cannot be unmapped, so they are never dropped.
+struct LogPage {
+    region: ManuallyDrop<UnsafeProtectedRegion<allocator::Mmap, Page>>,
+    sealed: bool,
+}
+
+impl LogPage {
+    fn new() -> Result<Self, MprotectError> {
+        Ok(LogPage {
+            region: ManuallyDrop::new(UnsafeProtectedRegion::new_initialized([0; LOG_PAGE_SIZE], AccessRights::READ_WRITE)?),
+            sealed: false,
+        })
+    }
+
+    fn bytes(&self) -> &Page {
+        unsafe { self.region.as_ref() }
+    }
+
+    /// Makes the page read-only and, if `seal` is set, seals it with `mseal`.
+    ///
+    /// On failure the page is left writable, so it can stay the tail of the log.
+    fn complete(&mut self, seal: bool) -> Result<(), MprotectError> {
+        unsafe { self.region.set_access(AccessRights::READ)? };
+        if seal {
+            let ret = unsafe { libc::syscall(libc::SYS_mseal, self.region.ptr() as usize, LOG_PAGE_SIZE, 0usize) };
+            if ret != 0 {
+                let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+                unsafe { self.region.set_access(AccessRights::READ_WRITE)? };
+                return Err(MprotectError::MsealFailed(err_no));
+            }
+            self.sealed = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LogPage {
+    fn drop(&mut self) {
+        if !self.sealed {
+            unsafe { ManuallyDrop::drop(&mut self.region) };
+        }
+    }
+}
+
+/// Position of a record: page index, offset within the page, and length.
+#[derive(Clone, Copy)]
+struct Record {
+    page: usize,
+    offset: usize,
+    len: usize,
+}
+
+/// An append-only (write once, read many) log of byte records in protected memory.
+///
+/// Records are written to the current tail page, the only page of the log that is
+/// writable. When a record does not fit, or on [`commit()`](Self::commit), the tail
+/// page is completed: it is made read-only and, if the kernel supports `mseal`, sealed
+/// so that not even `mprotect` can make it writable again. Records never span pages.
+///
+/// This is meant for tamper-evident audit trails: once a page is completed, a stray
+/// write from a memory-corruption bug faults instead of rewriting a past entry.
+///
+/// # Sealing
+///
+/// Sealed pages cannot be unmapped, so they stay mapped after the log is dropped, until
+/// the process exits. Use [`without_sealing()`](Self::without_sealing) for logs that are
+/// created and dropped repeatedly.
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::AppendOnlyLog;
+///
+/// let mut log = AppendOnlyLog::new()?;
+/// log.append(b"user=alice action=login")?;
+/// log.append(b"user=alice action=export")?;
+/// log.commit()?;
+///
+/// for record in log.iter() {
+///     println!("{}", String::from_utf8_lossy(record));
+/// }
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct AppendOnlyLog {
+    pages: Vec<LogPage>,
+    /// Bytes used in the last page.
+    tail_used: usize,
+    records: Vec<Record>,
+    seal: bool,
+}
+
+impl AppendOnlyLog {
+    /// Creates an empty log that seals completed pages if the kernel supports `mseal`.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(AppendOnlyLog)`: On success.
+    /// - `Err(MprotectError)`: If the first page cannot be allocated.
+    pub fn new() -> Result<Self, MprotectError> {
+        Self::with_sealing(probe::mseal_supported())
+    }
+
+    /// Creates an empty log that only makes completed pages read-only.
+    pub fn without_sealing() -> Result<Self, MprotectError> {
+        Self::with_sealing(false)
+    }
+
+    fn with_sealing(seal: bool) -> Result<Self, MprotectError> {
+        Ok(AppendOnlyLog {
+            pages: vec![LogPage::new()?],
+            tail_used: 0,
+            records: Vec::new(),
+            seal,
+        })
+    }
+
+    /// Returns `true` if completed pages are sealed with `mseal`.
+    pub fn is_sealing(&self) -> bool {
+        self.seal
+    }
+
+    /// Completes the tail page and starts a new one.
+    ///
+    /// The new page is allocated first and only becomes the tail once the old one is
+    /// completed, so records are never reported as committed on a writable page.
+    fn advance(&mut self) -> Result<(), MprotectError> {
+        let page = LogPage::new()?;
+        if let Some(tail) = self.pages.last_mut() {
+            tail.complete(self.seal)?;
+        }
+        self.pages.push(page);
+        self.tail_used = 0;
+        Ok(())
+    }
+
+    /// Appends a record and returns its index.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(usize)`: The index of the record.
+    /// - `Err(MprotectError::LogRecordTooLarge)`: If the record is longer than [`LOG_PAGE_SIZE`].
+    /// - `Err(MprotectError)`: If completing the tail page or allocating a new one fails.
+    pub fn append(&mut self, record: &[u8]) -> Result<usize, MprotectError> {
+        if record.len() > LOG_PAGE_SIZE {
+            return Err(MprotectError::LogRecordTooLarge(record.len()));
+        }
+        if self.tail_used + record.len() > LOG_PAGE_SIZE {
+            self.advance()?;
+        }
+        let page = self.pages.len() - 1;
+        let offset = self.tail_used;
+        let tail = unsafe { self.pages[page].region.as_mut() };
+        tail[offset..offset + record.len()].copy_from_slice(record);
+
+        self.tail_used += record.len();
+        self.records.push(Record { page, offset, len: record.len() });
+        Ok(self.records.len() - 1)
+    }
+
+    /// Completes the tail page now, so every record appended so far becomes immutable.
+    ///
+    /// The rest of the tail page is left unused. Does nothing if the tail page is empty.
+    pub fn commit(&mut self) -> Result<(), MprotectError> {
+        if self.tail_used == 0 {
+            return Ok(());
+        }
+        self.advance()
+    }
+
+    /// Returns the number of records that are on completed pages and can no longer change.
+    pub fn committed(&self) -> usize {
+        let tail = self.pages.len() - 1;
+        self.records.iter().take_while(|record| record.page < tail).count()
+    }
+
+    /// Returns the committed record at `index`.
+    ///
+    /// Records that are still on the writable tail page are not returned; see
+    /// [`pending()`](Self::pending).
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.committed() {
+            return None;
+        }
+        self.record(index)
+    }
+
+    /// Returns an iterator over the committed records, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (0..self.committed()).filter_map(|index| self.record(index))
+    }
+
+    /// Returns an iterator over the records that are not committed yet, oldest first.
+    ///
+    /// These are on the writable tail page, so unlike committed records they can
+    /// still be overwritten by a stray write.
+    pub fn pending(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (self.committed()..self.records.len()).filter_map(|index| self.record(index))
+    }
+
+    fn record(&self, index: usize) -> Option<&[u8]> {
+        let record = self.records.get(index)?;
+        Some(&self.pages[record.page].bytes()[record.offset..record.offset + record.len])
+    }
+
+    /// Returns the number of records, committed or not.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns `true` if the log holds no records.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
@@ -0,0 +1,426 @@
+use std::mem::ManuallyDrop;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{ AtomicBool, AtomicI32, AtomicPtr, AtomicU64, AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex, Weak };
+
+use crate::mprotect::*;
+use crate::signals::SEGV_ACCERR;
+use crate::MprotectError;
+
+/// Maximum number of [`DirtyStrategy::WriteProtect`] trackers alive at the same time.
+pub const MAX_WRITE_PROTECT_TRACKERS: usize = 64;
+
+/// Bit of a pagemap entry that holds the soft-dirty flag.
+const PAGEMAP_SOFT_DIRTY: u64 = 1 << 55;
+/// Bit of the x86 page-fault error code set for write accesses.
+const PF_WRITE: i64 = 1 << 1;
+
+/// How a [`DirtyTracker`] detects writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirtyStrategy {
+    /// Removes write permission from every page and records the first write to each
+    /// page from a `SIGSEGV` handler, which then makes the page writable again.
+    ///
+    /// Works on any kernel, but the first write to a page after each checkpoint costs
+    /// a fault.
+    WriteProtect,
+    /// Reads the kernel's soft-dirty bits from `/proc/self/pagemap` and resets them by
+    /// writing `4` to `/proc/self/clear_refs`.
+    ///
+    /// Writes cost nothing extra, but the kernel must be built with
+    /// `CONFIG_MEM_SOFT_DIRTY` (see [`probe::soft_dirty_supported`](crate::probe::soft_dirty_supported)).
+    /// Resetting the bits applies to the whole process and is more expensive than
+    /// write-protecting one region.
+    SoftDirty,
+}
+
+/// One bit per page of a tracked region, set when the page is written.
+struct DirtyBits {
+    /// Page-aligned start address of the region.
+    start: usize,
+    pages: usize,
+    words: Box<[AtomicU64]>,
+}
+
+impl DirtyBits {
+    fn new(start: usize, pages: usize) -> Self {
+        DirtyBits { start, pages, words: (0..pages.div_ceil(64)).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    fn set(&self, page: usize) {
+        self.words[page / 64].fetch_or(1 << (page % 64), Ordering::Relaxed);
+    }
+
+    /// Returns the indices of the set bits, clearing them if `take` is set.
+    fn collect(&self, take: bool) -> Vec<usize> {
+        let mut pages = Vec::new();
+        for (index, word) in self.words.iter().enumerate() {
+            let mut bits = if take { word.swap(0, Ordering::Relaxed) } else { word.load(Ordering::Relaxed) };
+            while bits != 0 {
+                pages.push(index * 64 + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+        pages
+    }
+}
+
+/// A region registered with the write fault handler.
+struct Slot {
+    claimed: AtomicBool,
+    /// Start address, or 0 while the slot is not ready.
+    start: AtomicUsize,
+    end: AtomicUsize,
+    /// The rights a written page is given back.
+    rights: AtomicI32,
+    words: AtomicPtr<AtomicU64>,
+    /// Number of fault handlers using the slot, which keep `words` alive.
+    in_flight: AtomicUsize,
+}
+
+static SLOTS: [Slot; MAX_WRITE_PROTECT_TRACKERS] = [const {
+    Slot {
+        claimed: AtomicBool::new(false),
+        start: AtomicUsize::new(0),
+        end: AtomicUsize::new(0),
+        rights: AtomicI32::new(0),
+        words: AtomicPtr::new(std::ptr::null_mut()),
+        in_flight: AtomicUsize::new(0),
+    }
+}; MAX_WRITE_PROTECT_TRACKERS];
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// The `SIGSEGV` action that was installed before the write fault handler, or null.
+///
+/// Replaced when the handler is installed again; the old action is leaked, since a
+/// fault handler may still be reading it.
+static PREVIOUS_ACTION: AtomicPtr<libc::sigaction> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Soft-dirty trackers, whose bits must be saved before any of them resets the kernel's.
+static SOFT_DIRTY: Mutex<Vec<Weak<DirtyBits>>> = Mutex::new(Vec::new());
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Records a write fault at `addr` and makes the page writable again.
+///
+/// Returns `false` if `addr` is not in a tracked region whose rights allow writing.
+unsafe fn unprotect(addr: usize) -> bool {
+    let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+    for slot in &SLOTS {
+        // Announce the access before reading `start`, so `release_slot` either waits
+        // for it or has already cleared `start`.
+        slot.in_flight.fetch_add(1, Ordering::SeqCst);
+        let start = slot.start.load(Ordering::SeqCst);
+        if start == 0 || addr < start || addr >= slot.end.load(Ordering::Relaxed) {
+            slot.in_flight.fetch_sub(1, Ordering::Release);
+            continue;
+        }
+        let rights = slot.rights.load(Ordering::Relaxed);
+        let handled = rights & libc::PROT_WRITE != 0 && {
+            let page = (addr - start) / page_size;
+            let word = &*slot.words.load(Ordering::Relaxed).add(page / 64);
+            word.fetch_or(1 << (page % 64), Ordering::Relaxed);
+            let page_addr = (start + page * page_size) as *mut libc::c_void;
+            libc::mprotect(page_addr, page_size, rights) == 0
+        };
+        slot.in_flight.fetch_sub(1, Ordering::Release);
+        return handled;
+    }
+    false
+}
+
+extern "C" fn on_write_fault(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    unsafe {
+        let context = context as *mut libc::ucontext_t;
+        let is_write = (*context).uc_mcontext.gregs[libc::REG_ERR as usize] & PF_WRITE != 0;
+        if (*info).si_code == SEGV_ACCERR && is_write && unprotect((*info).si_addr() as usize) {
+            return;
+        }
+        let previous = PREVIOUS_ACTION.load(Ordering::Acquire);
+        if !previous.is_null() {
+            chain(signal, info, context as *mut libc::c_void, &*previous);
+        }
+    }
+}
+
+/// Passes a fault the write fault handler does not own to the previous action.
+///
+/// The write fault handler stays installed, unless the previous action is the default
+/// or ignored one, or asked for `SA_RESETHAND`: then the default action is restored, as
+/// the kernel would have done, so the process terminates if the fault happens again.
+unsafe fn chain(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void, previous: &libc::sigaction) {
+    let handler = previous.sa_sigaction;
+    if handler == libc::SIG_DFL || handler == libc::SIG_IGN || previous.sa_flags & libc::SA_RESETHAND != 0 {
+        let mut default: libc::sigaction = std::mem::zeroed();
+        default.sa_sigaction = libc::SIG_DFL;
+        libc::sigaction(signal, &default, std::ptr::null_mut());
+    }
+    if handler == libc::SIG_DFL || handler == libc::SIG_IGN {
+        // Returning executes the faulting access again, which now terminates the process.
+        return;
+    }
+    if previous.sa_flags & libc::SA_SIGINFO != 0 {
+        let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) = std::mem::transmute(handler);
+        handler(signal, info, context);
+    } else {
+        let handler: extern "C" fn(libc::c_int) = std::mem::transmute(handler);
+        handler(signal);
+    }
+}
+
+/// Installs the write fault handler for `SIGSEGV`, unless it is already installed.
+///
+/// Another handler may have replaced it since the last tracker was created, e.g. through
+/// [`signals::install_handler`](crate::signals::install_handler). It is then installed
+/// again, and the handler that replaced it becomes the previous action.
+fn install_fault_handler() -> Result<(), MprotectError> {
+    static INSTALL: Mutex<()> = Mutex::new(());
+    let _install = INSTALL.lock().unwrap_or_else(|e| e.into_inner());
+    PAGE_SIZE.store(page_size(), Ordering::Relaxed);
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_write_fault as *const () as usize;
+        // SA_ONSTACK lets a stack overflow reach std's handler on the alternate stack.
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut current: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGSEGV, std::ptr::null(), &mut current) != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(MprotectError::SignalSetupFailed(err_no));
+        }
+        if current.sa_sigaction == action.sa_sigaction {
+            return Ok(());
+        }
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGSEGV, &action, &mut previous) != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(MprotectError::SignalSetupFailed(err_no));
+        }
+        PREVIOUS_ACTION.store(Box::into_raw(Box::new(previous)), Ordering::Release);
+    }
+    Ok(())
+}
+
+/// Registers `bits` with the write fault handler and returns its slot.
+fn claim_slot(bits: &DirtyBits, rights: AccessRights) -> Result<usize, MprotectError> {
+    let index = SLOTS.iter()
+        .position(|slot| slot.claimed.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok())
+        .ok_or(MprotectError::TooManyDirtyTrackers)?;
+    let slot = &SLOTS[index];
+    slot.end.store(bits.start + bits.pages * page_size(), Ordering::Relaxed);
+    slot.rights.store(rights.to_i32(), Ordering::Relaxed);
+    slot.words.store(bits.words.as_ptr() as *mut AtomicU64, Ordering::Relaxed);
+    slot.start.store(bits.start, Ordering::Release);
+    Ok(index)
+}
+
+/// Unregisters a slot and waits until no fault handler uses its bits, which the
+/// caller may then free.
+fn release_slot(index: usize) {
+    let slot = &SLOTS[index];
+    slot.start.store(0, Ordering::SeqCst);
+    while slot.in_flight.load(Ordering::SeqCst) != 0 {
+        std::hint::spin_loop();
+    }
+    slot.claimed.store(false, Ordering::Release);
+}
+
+/// Reads the soft-dirty bits of `pages` pages starting at `start` from `/proc/self/pagemap`.
+pub(crate) fn read_soft_dirty(start: usize, pages: usize) -> Result<Vec<bool>, MprotectError> {
+    let pagemap = std::fs::File::open("/proc/self/pagemap")
+        .map_err(|e| MprotectError::PagemapAccessFailed(e.raw_os_error().unwrap_or(-1)))?;
+    let mut entries = vec![0u8; pages * 8];
+    pagemap.read_exact_at(&mut entries, (start / page_size() * 8) as u64)
+        .map_err(|e| MprotectError::PagemapAccessFailed(e.raw_os_error().unwrap_or(-1)))?;
+    Ok(entries
+        .chunks_exact(8)
+        .map(|entry| u64::from_ne_bytes(entry.try_into().unwrap()) & PAGEMAP_SOFT_DIRTY != 0)
+        .collect())
+}
+
+/// Saves the soft-dirty bits of every soft-dirty tracker and resets them in the kernel.
+fn clear_soft_dirty() -> Result<(), MprotectError> {
+    let mut trackers = SOFT_DIRTY.lock().unwrap_or_else(|e| e.into_inner());
+    trackers.retain(|bits| bits.strong_count() > 0);
+    for bits in trackers.iter().filter_map(Weak::upgrade) {
+        let dirty = read_soft_dirty(bits.start, bits.pages)?;
+        dirty.iter().enumerate().filter(|(_, dirty)| **dirty).for_each(|(page, _)| bits.set(page));
+    }
+    std::fs::write("/proc/self/clear_refs", "4")
+        .map_err(|e| MprotectError::PagemapAccessFailed(e.raw_os_error().unwrap_or(-1)))
+}
+
+/// Reports which pages of a protected region were written since the last checkpoint.
+///
+/// The tracker takes ownership of the region for as long as it tracks it. Write to the
+/// region through [`region_mut()`](Self::region_mut) or pointers into it as usual, then
+/// call [`checkpoint()`](Self::checkpoint) to get the indices of the written pages and
+/// start a new interval. Copying only those pages gives an incremental snapshot.
+///
+/// Writes are detected with one of two [`DirtyStrategy`] values. With
+/// [`WriteProtect`](DirtyStrategy::WriteProtect) the region's pages lose write permission
+/// at every checkpoint, so [`access_rights()`](UnsafeProtectedRegion::access_rights) and
+/// [`verify()`](UnsafeProtectedRegion::verify) of the region report the write-protected
+/// rights while it is tracked. The region's rights must not be changed while it is tracked.
+///
+/// Page indices count from the page that contains the start of the region.
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::{allocator, AccessRights, DirtyStrategy, DirtyTracker, UnsafeProtectedRegion};
+///
+/// let region = UnsafeProtectedRegion::<allocator::Mmap, [u8; 16384]>::new_initialized([0; 16384], AccessRights::READ_WRITE)?;
+/// let mut tracker = DirtyTracker::new(region, DirtyStrategy::WriteProtect)?;
+///
+/// unsafe { tracker.region_mut().as_mut()[8192] = 1 };
+/// assert_eq!(tracker.checkpoint()?, vec![2]);
+/// assert!(tracker.checkpoint()?.is_empty());
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct DirtyTracker<A: allocator::Allocator<T>, T> {
+    region: ManuallyDrop<UnsafeProtectedRegion<A, T>>,
+    strategy: DirtyStrategy,
+    /// The rights of the region before tracking started.
+    access_rights: AccessRights,
+    bits: Arc<DirtyBits>,
+    /// Slot of a [`DirtyStrategy::WriteProtect`] tracker.
+    slot: Option<usize>,
+}
+
+impl<A: allocator::Allocator<T>, T> DirtyTracker<A, T> {
+    /// Starts tracking writes to `region`. All pages start out clean.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(DirtyTracker)`: On success.
+    /// - `Err(MprotectError::TooManyDirtyTrackers)`: If [`MAX_WRITE_PROTECT_TRACKERS`]
+    ///   write-protect trackers are already alive.
+    /// - `Err(MprotectError::SignalSetupFailed)`: If the write fault handler cannot be installed.
+    /// - `Err(MprotectError::SoftDirtyUnsupported)`: If soft-dirty bits are requested but
+    ///   the kernel does not provide them.
+    /// - `Err(MprotectError)`: If write-protecting the region or resetting the soft-dirty
+    ///   bits fails. The region is dropped.
+    pub fn new(region: UnsafeProtectedRegion<A, T>, strategy: DirtyStrategy) -> Result<Self, MprotectError> {
+        let (start, end) = region.page_range();
+        let bits = Arc::new(DirtyBits::new(start, (end - start) / page_size()));
+        let mut tracker = DirtyTracker {
+            access_rights: region.access_rights(),
+            region: ManuallyDrop::new(region),
+            strategy,
+            bits,
+            slot: None,
+        };
+        match strategy {
+            DirtyStrategy::WriteProtect => {
+                install_fault_handler()?;
+                tracker.slot = Some(claim_slot(&tracker.bits, tracker.access_rights)?);
+                tracker.write_protect()?;
+            }
+            DirtyStrategy::SoftDirty => {
+                if !crate::probe::soft_dirty_supported() {
+                    return Err(MprotectError::SoftDirtyUnsupported);
+                }
+                SOFT_DIRTY.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::downgrade(&tracker.bits));
+                clear_soft_dirty()?;
+                tracker.bits.collect(true);
+            }
+        }
+        Ok(tracker)
+    }
+
+    fn write_protect(&self) -> Result<(), MprotectError> {
+        unsafe { self.region.set_access(self.access_rights.minus(AccessRights::WRITE)) }
+    }
+
+    /// Returns the strategy used to detect writes.
+    pub fn strategy(&self) -> DirtyStrategy {
+        self.strategy
+    }
+
+    /// Returns the number of pages the region spans.
+    pub fn page_count(&self) -> usize {
+        self.bits.pages
+    }
+
+    /// Returns the tracked region.
+    pub fn region(&self) -> &UnsafeProtectedRegion<A, T> {
+        &self.region
+    }
+
+    /// Returns the tracked region for writing.
+    pub fn region_mut(&mut self) -> &mut UnsafeProtectedRegion<A, T> {
+        &mut self.region
+    }
+
+    /// Returns the indices of the pages written since the last checkpoint, in
+    /// ascending order, without starting a new interval.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(Vec<usize>)`: The written pages.
+    /// - `Err(MprotectError::PagemapAccessFailed)`: If the soft-dirty bits cannot be read.
+    pub fn dirty_pages(&self) -> Result<Vec<usize>, MprotectError> {
+        if self.strategy == DirtyStrategy::SoftDirty {
+            let dirty = read_soft_dirty(self.bits.start, self.bits.pages)?;
+            dirty.iter().enumerate().filter(|(_, dirty)| **dirty).for_each(|(page, _)| self.bits.set(page));
+        }
+        Ok(self.bits.collect(false))
+    }
+
+    /// Returns the indices of the pages written since the last checkpoint, in
+    /// ascending order, and marks every page clean.
+    ///
+    /// With [`DirtyStrategy::SoftDirty`], a write made by another thread while the
+    /// checkpoint runs may be missed; stop writers first.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(Vec<usize>)`: The written pages.
+    /// - `Err(MprotectError)`: If write-protecting the region, or reading or resetting
+    ///   the soft-dirty bits fails.
+    pub fn checkpoint(&mut self) -> Result<Vec<usize>, MprotectError> {
+        // Write-protect first: a write that races with the checkpoint faults again and
+        // is reported now, never lost.
+        match self.strategy {
+            DirtyStrategy::WriteProtect => self.write_protect()?,
+            DirtyStrategy::SoftDirty => clear_soft_dirty()?,
+        }
+        Ok(self.bits.collect(true))
+    }
+
+    /// Stops tracking and restores the region's original rights.
+    fn stop(&mut self) -> Result<(), MprotectError> {
+        if let Some(slot) = self.slot.take() {
+            // Restore the rights before releasing the slot, so no write faults unhandled.
+            let restored = unsafe { self.region.set_access(self.access_rights) };
+            release_slot(slot);
+            restored?;
+        }
+        Ok(())
+    }
+
+    /// Stops tracking and returns the region with its original rights.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(UnsafeProtectedRegion)`: The region.
+    /// - `Err(MprotectError)`: If the original rights cannot be restored. The region is dropped.
+    pub fn into_inner(self) -> Result<UnsafeProtectedRegion<A, T>, MprotectError> {
+        let mut tracker = ManuallyDrop::new(self);
+        let stopped = tracker.stop();
+        let region = unsafe { ManuallyDrop::take(&mut tracker.region) };
+        drop(unsafe { std::ptr::read(&tracker.bits) });
+        stopped.map(|()| region)
+    }
+}
+
+impl<A: allocator::Allocator<T>, T> Drop for DirtyTracker<A, T> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+        unsafe { ManuallyDrop::drop(&mut self.region) };
+    }
+}
@@ -0,0 +1,380 @@
+use std::alloc::{ GlobalAlloc, Layout };
+use std::cell::UnsafeCell;
+use std::fmt::Display;
+use std::sync::Once;
+use std::sync::atomic::{ AtomicBool, AtomicPtr, AtomicUsize, Ordering };
+use std::time::Duration;
+
+use crate::allocator::Mmap;
+
+/// Default quarantine budget of [`GuardedHeap::new`].
+pub const DEFAULT_QUARANTINE_SIZE: usize = 16 << 20;
+/// Default quarantine time of [`GuardedHeap::new`].
+pub const DEFAULT_QUARANTINE_TIME: Duration = Duration::from_secs(10);
+
+/// Number of allocations that can be quarantined at once, regardless of their size.
+const QUARANTINE_CAPACITY: usize = 4096;
+/// Number of live and quarantined allocations fault diagnostics can describe.
+const TABLE_CAPACITY: usize = 1 << 16;
+
+/// Markers in [`Entry::base`].
+const EMPTY: usize = 0;
+const REMOVED: usize = 1;
+const BUSY: usize = 2;
+
+/// A heap bug detected by [`GuardedHeap`], as described by [`GuardedHeap::describe_fault`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapFault {
+    /// An access ran past the end of a live allocation into its guard page.
+    Overflow {
+        /// Address of the allocation.
+        allocation: usize,
+        /// Size of the allocation in bytes.
+        size: usize,
+    },
+    /// An access hit an allocation that was freed and is still quarantined.
+    UseAfterFree {
+        /// Address of the allocation.
+        allocation: usize,
+        /// Size of the allocation in bytes.
+        size: usize,
+    },
+}
+
+impl Display for HeapFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeapFault::Overflow { allocation, size } => write!(f, "heap overflow past the {}-byte allocation at {:#x}", size, allocation),
+            HeapFault::UseAfterFree { allocation, size } => write!(f, "use after free of the {}-byte allocation at {:#x}", size, allocation),
+        }
+    }
+}
+
+/// One mapping made by a [`GuardedHeap`], recorded for fault diagnostics.
+struct Entry {
+    base: AtomicUsize,
+    len: AtomicUsize,
+    ptr: AtomicUsize,
+    size: AtomicUsize,
+    freed: AtomicBool,
+}
+
+/// Open-addressing table of every live and quarantined mapping, keyed by `base`.
+///
+/// It is mapped on first use and updated without locks, so the fault handler can read it.
+static TABLE: AtomicPtr<Entry> = AtomicPtr::new(std::ptr::null_mut());
+
+fn table() -> Option<&'static [Entry]> {
+    let mut table = TABLE.load(Ordering::Acquire);
+    if table.is_null() {
+        let len = TABLE_CAPACITY * std::mem::size_of::<Entry>();
+        let mapped = unsafe { Mmap::map(len, libc::PROT_READ | libc::PROT_WRITE).ok()? } as *mut Entry;
+        table = match TABLE.compare_exchange(std::ptr::null_mut(), mapped, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => mapped,
+            Err(winner) => {
+                let _ = unsafe { Mmap::unmap(mapped as *mut libc::c_void, len) };
+                winner
+            }
+        };
+    }
+    // Zeroed memory is a table of empty entries.
+    Some(unsafe { std::slice::from_raw_parts(table, TABLE_CAPACITY) })
+}
+
+fn slots(base: usize) -> impl Iterator<Item = usize> {
+    let start = (base / page_size()).wrapping_mul(0x9e37_79b9_7f4a_7c15) % TABLE_CAPACITY;
+    (0..TABLE_CAPACITY).map(move |i| (start + i) % TABLE_CAPACITY)
+}
+
+/// Records a new mapping. A full table only loses diagnostics.
+fn record(base: usize, len: usize, ptr: usize, size: usize) {
+    let Some(table) = table() else { return };
+    for slot in slots(base) {
+        let entry = &table[slot];
+        let current = entry.base.load(Ordering::Relaxed);
+        if current != EMPTY && current != REMOVED {
+            continue;
+        }
+        if entry.base.compare_exchange(current, BUSY, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            entry.len.store(len, Ordering::Relaxed);
+            entry.ptr.store(ptr, Ordering::Relaxed);
+            entry.size.store(size, Ordering::Relaxed);
+            entry.freed.store(false, Ordering::Relaxed);
+            entry.base.store(base, Ordering::Release);
+            return;
+        }
+    }
+}
+
+fn find(base: usize) -> Option<&'static Entry> {
+    let table = table()?;
+    for slot in slots(base) {
+        match table[slot].base.load(Ordering::Acquire) {
+            EMPTY => return None,
+            current if current == base => return Some(&table[slot]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Sizes of the data pages and of the whole mapping for an allocation.
+fn mapping_len(layout: Layout) -> (usize, usize) {
+    let page_size = page_size();
+    let data_len = (layout.size() + layout.align() - 1).div_ceil(page_size) * page_size;
+    (data_len, data_len + page_size)
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn now_nanos() -> u64 {
+    let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Serialises quarantine updates of every [`GuardedHeap`].
+///
+/// A spin lock rather than a `Mutex`, so `fork` can take it in `pthread_atfork` and
+/// release it in both processes: a child forked while another thread frees memory
+/// must not inherit a held lock.
+static LOCK: AtomicBool = AtomicBool::new(false);
+
+fn lock() {
+    static ATFORK: Once = Once::new();
+    ATFORK.call_once(|| unsafe {
+        libc::pthread_atfork(Some(acquire), Some(release), Some(release));
+    });
+    unsafe { acquire() };
+}
+
+unsafe extern "C" fn acquire() {
+    while LOCK.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+        std::hint::spin_loop();
+    }
+}
+
+unsafe extern "C" fn release() {
+    LOCK.store(false, Ordering::Release);
+}
+
+/// A freed mapping kept inaccessible.
+#[derive(Clone, Copy)]
+struct Quarantined {
+    base: usize,
+    len: usize,
+    freed_at: u64,
+}
+
+/// FIFO of quarantined mappings.
+struct Quarantine {
+    entries: [Quarantined; QUARANTINE_CAPACITY],
+    head: usize,
+    count: usize,
+    bytes: usize,
+}
+
+impl Quarantine {
+    fn push(&mut self, entry: Quarantined) {
+        self.entries[(self.head + self.count) % QUARANTINE_CAPACITY] = entry;
+        self.count += 1;
+        self.bytes += entry.len;
+    }
+
+    fn oldest(&self) -> Option<Quarantined> {
+        (self.count > 0).then(|| self.entries[self.head])
+    }
+
+    /// Unmaps the oldest mapping.
+    fn release_oldest(&mut self) {
+        let entry = self.entries[self.head];
+        self.head = (self.head + 1) % QUARANTINE_CAPACITY;
+        self.count -= 1;
+        self.bytes -= entry.len;
+        if let Some(recorded) = find(entry.base) {
+            recorded.base.store(REMOVED, Ordering::Release);
+        }
+        let _ = unsafe { Mmap::unmap(entry.base as *mut libc::c_void, entry.len) };
+    }
+}
+
+/// An electric-fence style debugging allocator that gives every allocation its own pages.
+///
+/// Each allocation is mapped with [`Mmap`](crate::allocator::Mmap) and placed at the
+/// end of its pages, right before a `PROT_NONE` guard page, so reading or writing past
+/// its end faults immediately. Freed memory is made `PROT_NONE` and quarantined instead
+/// of unmapped, so a dangling pointer faults too, until the quarantine exceeds its size
+/// budget or the memory has been quarantined for longer than the quarantine time.
+/// [`describe_fault`](Self::describe_fault) tells which allocation a faulting address
+/// belongs to, and the `testing` harness (behind the `testing` feature) reports it in
+/// `Fault::heap`.
+///
+/// Every allocation costs at least two pages and two kernel mappings, so this allocator
+/// is meant for tests. Limitations:
+/// - An overflow by fewer bytes than the allocation's alignment is not detected.
+/// - Accesses before the start of an allocation are not detected.
+/// - Allocations aligned to more than a page fail.
+///
+/// # Example
+///
+/// Install it as the global allocator of test builds:
+///
+/// ```no_run
+/// use mprotect_rs::GuardedHeap;
+///
+/// #[cfg(test)]
+/// #[global_allocator]
+/// static HEAP: GuardedHeap = GuardedHeap::new().with_quarantine_size(64 << 20);
+/// ```
+pub struct GuardedHeap {
+    quarantine_size: usize,
+    quarantine_time: Duration,
+    quarantine: UnsafeCell<Quarantine>,
+}
+
+// The quarantine is only accessed while `LOCK` is held.
+unsafe impl Sync for GuardedHeap {}
+
+impl GuardedHeap {
+    /// Creates a heap with [`DEFAULT_QUARANTINE_SIZE`] and [`DEFAULT_QUARANTINE_TIME`].
+    pub const fn new() -> Self {
+        GuardedHeap {
+            quarantine_size: DEFAULT_QUARANTINE_SIZE,
+            quarantine_time: DEFAULT_QUARANTINE_TIME,
+            quarantine: UnsafeCell::new(Quarantine {
+                entries: [Quarantined { base: 0, len: 0, freed_at: 0 }; QUARANTINE_CAPACITY],
+                head: 0,
+                count: 0,
+                bytes: 0,
+            }),
+        }
+    }
+
+    /// Sets how many bytes of freed memory, including guard pages, may stay quarantined.
+    ///
+    /// `0` disables the quarantine: freed memory is unmapped immediately.
+    pub const fn with_quarantine_size(mut self, bytes: usize) -> Self {
+        self.quarantine_size = bytes;
+        self
+    }
+
+    /// Sets how long freed memory stays quarantined at most.
+    ///
+    /// Expired memory is released on the next deallocation.
+    pub const fn with_quarantine_time(mut self, time: Duration) -> Self {
+        self.quarantine_time = time;
+        self
+    }
+
+    /// Returns the number of bytes currently quarantined, including guard pages.
+    pub fn quarantined_bytes(&self) -> usize {
+        lock();
+        let bytes = unsafe { (*self.quarantine.get()).bytes };
+        unsafe { release() };
+        bytes
+    }
+
+    /// Unmaps all quarantined memory.
+    pub fn flush_quarantine(&self) {
+        lock();
+        let quarantine = unsafe { &mut *self.quarantine.get() };
+        while quarantine.count > 0 {
+            quarantine.release_oldest();
+        }
+        unsafe { release() };
+    }
+
+    /// Describes the heap bug behind a fault at `addr`, if it hit a guard page or a
+    /// quarantined allocation of any `GuardedHeap`.
+    ///
+    /// This function is async-signal-safe, so it can be called from a fault handler.
+    pub fn describe_fault(addr: usize) -> Option<HeapFault> {
+        let table = unsafe { TABLE.load(Ordering::Acquire).as_ref() }?;
+        let table = unsafe { std::slice::from_raw_parts(table, TABLE_CAPACITY) };
+        table.iter().find_map(|entry| {
+            let base = entry.base.load(Ordering::Acquire);
+            if base <= BUSY || addr < base || addr >= base + entry.len.load(Ordering::Relaxed) {
+                return None;
+            }
+            let (allocation, size) = (entry.ptr.load(Ordering::Relaxed), entry.size.load(Ordering::Relaxed));
+            if entry.freed.load(Ordering::Relaxed) {
+                Some(HeapFault::UseAfterFree { allocation, size })
+            } else if addr >= base + entry.len.load(Ordering::Relaxed) - page_size() {
+                Some(HeapFault::Overflow { allocation, size })
+            } else {
+                None
+            }
+        })
+    }
+
+    unsafe fn map(&self, len: usize) -> Option<*mut libc::c_void> {
+        match Mmap::map(len, libc::PROT_READ | libc::PROT_WRITE) {
+            Ok(base) => Some(base),
+            // Running out of mappings is the likely cause; retry after releasing the quarantine.
+            Err(_) => {
+                self.flush_quarantine();
+                Mmap::map(len, libc::PROT_READ | libc::PROT_WRITE).ok()
+            }
+        }
+    }
+}
+
+impl Default for GuardedHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for GuardedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > page_size() {
+            return std::ptr::null_mut();
+        }
+        let (data_len, len) = mapping_len(layout);
+        let Some(base) = self.map(len) else {
+            return std::ptr::null_mut();
+        };
+        let guard = base as usize + data_len;
+        if libc::mprotect(guard as *mut libc::c_void, page_size(), libc::PROT_NONE) != 0 {
+            let _ = Mmap::unmap(base, len);
+            return std::ptr::null_mut();
+        }
+        let ptr = (guard - layout.size()) & !(layout.align() - 1);
+        record(base as usize, len, ptr, layout.size());
+        ptr as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (data_len, len) = mapping_len(layout);
+        let guard = (ptr as usize + layout.size()).div_ceil(page_size()) * page_size();
+        let base = guard - data_len;
+        let recorded = find(base);
+
+        if self.quarantine_size == 0 || libc::mprotect(base as *mut libc::c_void, data_len, libc::PROT_NONE) != 0 {
+            if let Some(recorded) = recorded {
+                recorded.base.store(REMOVED, Ordering::Release);
+            }
+            let _ = Mmap::unmap(base as *mut libc::c_void, len);
+            return;
+        }
+        if let Some(recorded) = recorded {
+            recorded.freed.store(true, Ordering::Release);
+        }
+
+        lock();
+        let quarantine = &mut *self.quarantine.get();
+        let now = now_nanos();
+        let max_age = self.quarantine_time.as_nanos() as u64;
+        quarantine.push(Quarantined { base, len, freed_at: now });
+        while let Some(oldest) = quarantine.oldest() {
+            let full = quarantine.bytes > self.quarantine_size || quarantine.count == QUARANTINE_CAPACITY;
+            if !full && now - oldest.freed_at <= max_age {
+                break;
+            }
+            quarantine.release_oldest();
+        }
+        release();
+    }
+}
@@ -23,6 +23,35 @@ pub use pkeyguard::*;
 mod regionguard;
 pub use regionguard::*;
 
+mod typedregion;
+pub use typedregion::*;
+
+mod appendlog;
+pub use appendlog::*;
+
+mod dirty;
+pub use dirty::*;
+
+mod reserved;
+pub use reserved::*;
+
+mod linearmemory;
+pub use linearmemory::*;
+
+mod guardedheap;
+pub use guardedheap::*;
+
+pub mod probe;
+
+pub mod quarantine;
+
+pub mod signals;
+
+pub mod smaps;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
 /// Type alias for system error numbers.
 pub type Errno = i32;
 
@@ -42,6 +71,14 @@ pub enum MprotectError {
     /// - All available protection keys are already allocated
     /// - Invalid flags or access rights
     PkeyAllocFailed(Errno),
+
+    /// Protection key release failed.
+    /// 
+    /// This error occurs when the `pkey_free` system call fails.
+    /// Common causes include:
+    /// - The key was not allocated by this process
+    /// - The key was already freed
+    PkeyFreeFailed(Errno),
     
     /// Memory allocation failed.
     /// 
@@ -84,17 +121,131 @@ pub enum MprotectError {
     /// This error occurs when attempting to perform an operation that requires
     /// a protection key, but the memory region has no associated key.
     NoPkeyAssociated,
+
+    /// Reading `/proc/<pid>/smaps` failed.
+    /// 
+    /// This error occurs when inspecting the kernel's view of a memory region or process.
+    /// Common causes include:
+    /// - `/proc` is not mounted
+    /// - The process lacks permission to read the file
+    SmapsReadFailed(Errno),
+
+    /// The memory region was not found in `/proc/self/smaps`.
+    /// 
+    /// This error occurs when none of the kernel's mappings overlap the region,
+    /// for example because it was unmapped behind the crate's back.
+    RegionNotMapped,
+
+    /// A newly allocated protection key is still in use by existing pages.
+    /// 
+    /// This error is reported by the stale-key check of [`PKey::new`] when memory is
+    /// still tagged with the key number the kernel just handed out, typically because
+    /// a previous owner freed the key without re-tagging its pages. The key is kept
+    /// allocated and handed out again only once its stale pages are gone.
+    StalePkeyReuse(u32),
+
+    /// Listing the threads of the process failed.
+    /// 
+    /// This error occurs when `/proc/self/task` cannot be read, e.g. because `/proc`
+    /// is not mounted.
+    ThreadEnumerationFailed(Errno),
+
+    /// Installing a signal handler or signalling a thread failed.
+    /// 
+    /// This error occurs when `sigaction` or `rt_tgsigqueueinfo` fails.
+    SignalSetupFailed(Errno),
+
+    /// Some threads did not acknowledge a cross-thread revocation in time.
+    /// 
+    /// The value is the number of threads that did not respond. Common causes include:
+    /// - The thread blocks the revocation signal
+    /// - The thread's signal frame holds no PKRU state to rewrite
+    RevocationTimedOut(usize),
+
+    /// The signal frame of an interrupted context holds no PKRU state.
+    /// 
+    /// This error occurs when a signal handler tries to change the PKRU the interrupted
+    /// code resumes with, but the CPU or kernel does not save PKRU in signal frames.
+    SavedPkruUnavailable,
+
+    /// A record does not fit in a page of an [`AppendOnlyLog`].
+    /// 
+    /// Records never span pages, so a record can hold at most [`LOG_PAGE_SIZE`] bytes.
+    /// The value is the length of the rejected record.
+    LogRecordTooLarge(usize),
+
+    /// Sealing memory failed.
+    /// 
+    /// This error occurs when the `mseal` system call fails.
+    MsealFailed(Errno),
+
+    /// Every write-protect slot of the dirty-page fault handler is in use.
+    /// 
+    /// At most [`MAX_WRITE_PROTECT_TRACKERS`] [`DirtyTracker`]s using
+    /// [`DirtyStrategy::WriteProtect`] can be alive at the same time.
+    TooManyDirtyTrackers,
+
+    /// The kernel does not track soft-dirty pages.
+    /// 
+    /// This error occurs when [`DirtyStrategy::SoftDirty`] is requested on a kernel built
+    /// without `CONFIG_MEM_SOFT_DIRTY`.
+    SoftDirtyUnsupported,
+
+    /// Reading `/proc/self/pagemap` or writing `/proc/self/clear_refs` failed.
+    /// 
+    /// Common causes include:
+    /// - `/proc` is not mounted
+    /// - The process lacks permission to access the file
+    PagemapAccessFailed(Errno),
+
+    /// A range passed to [`ReservedRegion`] is not valid.
+    /// 
+    /// The values are the offset and length of the range. Common causes include:
+    /// - The range is empty or not page-aligned
+    /// - The range extends past the end of the reservation
+    /// - The range to commit overlaps an already committed range
+    InvalidReservedRange(usize, usize),
+
+    /// The `madvise` system call failed.
+    /// 
+    /// This error occurs when discarding the contents of decommitted pages fails.
+    MadviseFailed(Errno),
+
+    /// Every slot of a [`LinearMemoryPool`] holds a memory.
+    PoolExhausted,
+
+    /// A [`LinearMemory`] cannot grow past the maximum memory size of its pool.
+    /// 
+    /// The value is the size in bytes that was requested.
+    MemoryLimitExceeded(usize),
 }
 
 impl Display for MprotectError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             MprotectError::PkeyAllocFailed(errno) => write!(f, "pkey allocation failed with errno {}", errno),
+            MprotectError::PkeyFreeFailed(errno) => write!(f, "pkey free failed with errno {}", errno),
             MprotectError::MemoryAllocationFailed(errno) => write!(f, "memory allocation failed with errno {}", errno),
             MprotectError::MemoryDeallocationFailed(errno) => write!(f, "memory deallocation failed with errno {}", errno),
             MprotectError::MprotectFailed(errno) => write!(f, "mprotect failed with errno {}", errno),
             MprotectError::PkeyMprotectFailed(errno) => write!(f, "pkey mprotect failed with errno {}", errno),
             MprotectError::NoPkeyAssociated => write!(f, "no protection key associated with the memory region"),
+            MprotectError::SmapsReadFailed(errno) => write!(f, "reading smaps failed with errno {}", errno),
+            MprotectError::RegionNotMapped => write!(f, "memory region not found in /proc/self/smaps"),
+            MprotectError::StalePkeyReuse(key) => write!(f, "pkey {} is still in use by stale pages", key),
+            MprotectError::ThreadEnumerationFailed(errno) => write!(f, "listing threads failed with errno {}", errno),
+            MprotectError::SignalSetupFailed(errno) => write!(f, "signal setup failed with errno {}", errno),
+            MprotectError::RevocationTimedOut(threads) => write!(f, "{} thread(s) did not acknowledge the revocation", threads),
+            MprotectError::SavedPkruUnavailable => write!(f, "signal frame holds no saved PKRU"),
+            MprotectError::LogRecordTooLarge(len) => write!(f, "log record of {} bytes does not fit in a page", len),
+            MprotectError::MsealFailed(errno) => write!(f, "mseal failed with errno {}", errno),
+            MprotectError::TooManyDirtyTrackers => write!(f, "too many write-protect dirty trackers"),
+            MprotectError::SoftDirtyUnsupported => write!(f, "kernel does not track soft-dirty pages"),
+            MprotectError::PagemapAccessFailed(errno) => write!(f, "accessing pagemap failed with errno {}", errno),
+            MprotectError::InvalidReservedRange(offset, len) => write!(f, "invalid range of {} bytes at offset {} in reserved region", len, offset),
+            MprotectError::MadviseFailed(errno) => write!(f, "madvise failed with errno {}", errno),
+            MprotectError::PoolExhausted => write!(f, "no free slot in the linear memory pool"),
+            MprotectError::MemoryLimitExceeded(len) => write!(f, "linear memory of {} bytes exceeds the pool's maximum", len),
         }
     }
 }
@@ -0,0 +1,286 @@
+use std::cell::{ Cell, RefCell };
+
+use crate::signals::PkruState;
+use crate::{ AccessRights, MprotectError, PKey, PkeyAccessRights, ReservedRegion };
+
+/// Layout of a [`LinearMemoryPool`].
+///
+/// Sizes are in bytes and are rounded up to whole pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinearMemoryConfig {
+    /// Number of memories the pool can hold at the same time.
+    pub slots: usize,
+    /// Largest size a memory can grow to.
+    pub max_memory_size: usize,
+    /// Inaccessible bytes after each slot.
+    pub guard_size: usize,
+    /// Number of protection keys to stripe the slots with. `0` disables striping, so
+    /// only the guard regions separate the memories.
+    pub stripes: usize,
+}
+
+/// A pool of linear memories, such as WebAssembly memories, packed into one reservation.
+///
+/// Every memory lives in its own slot of `max_memory_size` bytes followed by a guard
+/// region of `guard_size` bytes. The slots are assigned round-robin to `stripes`
+/// protection keys, so neighbouring slots always have different keys. While a memory is
+/// [activated](LinearMemory::activate), the calling thread can only access pages with its
+/// key, and the slots of the other stripes act as guard regions as well: an access up to
+/// [`effective_guard_size()`](Self::effective_guard_size) bytes past the end of a slot
+/// faults, while only `guard_size` bytes of address space are spent per slot. The
+/// reservation ends with an inaccessible tail of `stripes - 1` strides, so this also
+/// holds for the last slots.
+///
+/// Slots are committed on demand, starting with the initial size of a memory. The keys
+/// are allocated with [`PkeyAccessRights::DisableAccess`], so no memory is accessible
+/// to the calling thread until it is activated.
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::{LinearMemoryConfig, LinearMemoryPool};
+///
+/// let pool = LinearMemoryPool::new(LinearMemoryConfig {
+///     slots: 1000,
+///     max_memory_size: 4 << 30,
+///     guard_size: 64 << 10,
+///     stripes: 15,
+/// })?;
+/// let mut memory = pool.allocate(64 << 10)?;
+/// memory.grow(64 << 10)?;
+///
+/// let _active = memory.activate();
+/// unsafe { memory.ptr().write(1) };
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct LinearMemoryPool {
+    // Dropped before the keys, so freeing them does not re-tag unmapped pages.
+    reservation: RefCell<ReservedRegion>,
+    keys: Vec<PKey>,
+    slot_size: usize,
+    stride: usize,
+    in_use: Vec<Cell<bool>>,
+}
+
+impl LinearMemoryPool {
+    /// Reserves address space for `config.slots` memories and allocates the stripe keys.
+    ///
+    /// If fewer than `config.stripes` protection keys are available, the pool uses as
+    /// many as it can get.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(LinearMemoryPool)`: On success.
+    /// - `Err(MprotectError::MemoryAllocationFailed)`: If the address space cannot be reserved.
+    /// - `Err(MprotectError)`: If striping was requested but no protection key can be allocated.
+    pub fn new(config: LinearMemoryConfig) -> Result<Self, MprotectError> {
+        let slot_size = round_to_pages(config.max_memory_size);
+        let stride = slot_size + round_to_pages(config.guard_size);
+        // The last slots have no slots of other stripes after them, so the tail stands
+        // in for them.
+        let tail_slots = config.stripes.min(config.slots).saturating_sub(1);
+        let len = config.slots.checked_add(tail_slots)
+            .and_then(|slots| slots.checked_mul(stride))
+            .ok_or(MprotectError::MemoryAllocationFailed(libc::ENOMEM))?;
+        let reservation = ReservedRegion::new(len)?;
+
+        let mut keys = Vec::with_capacity(config.stripes);
+        while keys.len() < config.stripes.min(config.slots) {
+            match unsafe { PKey::new(PkeyAccessRights::DisableAccess) } {
+                Ok(key) => keys.push(key),
+                Err(err) if keys.is_empty() => return Err(err),
+                Err(_) => break,
+            }
+        }
+
+        Ok(LinearMemoryPool {
+            reservation: RefCell::new(reservation),
+            keys,
+            slot_size,
+            stride,
+            in_use: (0..config.slots).map(|_| Cell::new(false)).collect(),
+        })
+    }
+
+    /// Returns the number of slots.
+    pub fn slots(&self) -> usize {
+        self.in_use.len()
+    }
+
+    /// Returns the number of slots not holding a memory.
+    pub fn available(&self) -> usize {
+        self.in_use.iter().filter(|in_use| !in_use.get()).count()
+    }
+
+    /// Returns the number of protection keys the slots are striped with.
+    pub fn stripes(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns the largest size a memory can grow to.
+    pub fn max_memory_size(&self) -> usize {
+        self.slot_size
+    }
+
+    /// Returns the distance between the starts of two neighbouring slots.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns how many bytes past the end of its slot an activated memory is guaranteed
+    /// to fault on.
+    ///
+    /// This is the distance to the next slot with the same key, minus the slot itself.
+    /// For the last slots, the reservation's tail covers the same distance.
+    pub fn effective_guard_size(&self) -> usize {
+        self.keys.len().max(1) * self.stride - self.slot_size
+    }
+
+    /// Allocates a memory in a free slot and commits its first `initial_size` bytes.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(LinearMemory)`: The new memory, zero-filled.
+    /// - `Err(MprotectError::PoolExhausted)`: If every slot holds a memory.
+    /// - `Err(MprotectError::MemoryLimitExceeded)`: If `initial_size` exceeds the maximum memory size.
+    /// - `Err(MprotectError)`: If committing or tagging the pages fails.
+    pub fn allocate(&self, initial_size: usize) -> Result<LinearMemory<'_>, MprotectError> {
+        let slot = self.in_use.iter().position(|in_use| !in_use.get()).ok_or(MprotectError::PoolExhausted)?;
+        self.in_use[slot].set(true);
+        let mut memory = LinearMemory { pool: self, slot, len: 0 };
+        memory.grow(initial_size)?;
+        Ok(memory)
+    }
+
+    fn key(&self, slot: usize) -> Option<&PKey> {
+        (!self.keys.is_empty()).then(|| &self.keys[slot % self.keys.len()])
+    }
+}
+
+/// A memory in a slot of a [`LinearMemoryPool`].
+///
+/// Dropping it decommits its pages and frees the slot.
+pub struct LinearMemory<'p> {
+    pool: &'p LinearMemoryPool,
+    slot: usize,
+    len: usize,
+}
+
+impl<'p> LinearMemory<'p> {
+    /// Returns the index of the memory's slot.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Returns a pointer to the start of the memory.
+    pub fn ptr(&self) -> *mut u8 {
+        unsafe { self.pool.reservation.borrow().ptr().add(self.offset()) }
+    }
+
+    /// Returns the number of accessible bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no byte of the memory is accessible.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the protection key of the memory's stripe, or `None` if the pool is not striped.
+    pub fn pkey(&self) -> Option<u32> {
+        self.pool.key(self.slot).map(PKey::key)
+    }
+
+    fn offset(&self) -> usize {
+        self.slot * self.pool.stride
+    }
+
+    /// Makes `additional` more bytes, rounded up to whole pages, accessible.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: On success. The new bytes are zero-filled.
+    /// - `Err(MprotectError::MemoryLimitExceeded)`: If the memory would exceed the
+    ///   pool's maximum memory size. The value is the size that was requested.
+    /// - `Err(MprotectError)`: If committing or tagging the pages fails.
+    pub fn grow(&mut self, additional: usize) -> Result<(), MprotectError> {
+        let additional = round_to_pages(additional);
+        let new_len = self.len + additional;
+        if new_len > self.pool.slot_size {
+            return Err(MprotectError::MemoryLimitExceeded(new_len));
+        }
+        if additional == 0 {
+            return Ok(());
+        }
+        let offset = self.offset() + self.len;
+        let mut reservation = self.pool.reservation.borrow_mut();
+        unsafe {
+            let committed = reservation.commit(offset, additional, AccessRights::READ_WRITE)?;
+            if let Some(key) = self.pool.key(self.slot) {
+                if let Err(err) = key.associate(committed, AccessRights::READ_WRITE) {
+                    let _ = reservation.decommit(offset, additional);
+                    return Err(err);
+                }
+            }
+        }
+        self.len = new_len;
+        Ok(())
+    }
+
+    /// Allows the calling thread to access this memory and denies it every other stripe.
+    ///
+    /// Memories with the same key as this one become accessible too, but they are at
+    /// least [`effective_guard_size()`](LinearMemoryPool::effective_guard_size) bytes away.
+    /// The previous rights of the pool's keys are restored when the returned
+    /// [`Activation`] is dropped. Does nothing if the pool is not striped.
+    pub fn activate(&self) -> Activation<'_> {
+        let previous = unsafe { PkruState::current() };
+        let mut pkru = previous;
+        for key in &self.pool.keys {
+            let access = if Some(key.key()) == self.pkey() { PkeyAccessRights::EnableAccessWrite } else { PkeyAccessRights::DisableAccess };
+            pkru = pkru.with_access(key.key(), access);
+        }
+        if !self.pool.keys.is_empty() {
+            unsafe { pkru.apply() };
+        }
+        Activation { pool: self.pool, previous }
+    }
+}
+
+impl Drop for LinearMemory<'_> {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            let _ = unsafe { self.pool.reservation.borrow_mut().decommit(self.offset(), self.len) };
+        }
+        self.pool.in_use[self.slot].set(false);
+    }
+}
+
+/// The calling thread's access to an activated [`LinearMemory`].
+///
+/// Restores the rights the thread had for the pool's keys when dropped.
+pub struct Activation<'a> {
+    pool: &'a LinearMemoryPool,
+    previous: PkruState,
+}
+
+impl Drop for Activation<'_> {
+    fn drop(&mut self) {
+        if self.pool.keys.is_empty() {
+            return;
+        }
+        unsafe {
+            let mut pkru = PkruState::current();
+            for key in &self.pool.keys {
+                pkru = pkru.with_access(key.key(), self.previous.access(key.key()));
+            }
+            pkru.apply();
+        }
+    }
+}
+
+fn round_to_pages(len: usize) -> usize {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+    len.div_ceil(page_size) * page_size
+}
@@ -1,370 +1,172 @@
 use mprotect_rs::*;
+use mprotect_rs::probe::Capabilities;
+use mprotect_rs::smaps::Mapping;
 
-use std::process::Command;
-use std::os::unix::process::ExitStatusExt;
+use std::collections::BTreeMap;
+
+const USAGE: &str = "\
+Usage: mprotect-rs <COMMAND> [OPTIONS]
+
+Commands:
+  inspect <pid>   List the mappings of a process grouped by protection key
+  probe           Report PKU and memory hardening support of this host
+
+Options:
+  --json          Print machine-readable JSON instead of a table
+  -h, --help      Print this help";
 
 #[derive(Debug)]
 enum RuntimeError {
     MprotectError(MprotectError),
-    GuardError(GuardError),
-    PkeyGuardError(PkeyGuardError),
-    UnexpectedSuccess,  // For cases where we expect a failure but got success
+    InvalidArguments(String),
 }
 impl std::fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RuntimeError::MprotectError(e) => write!(f, "MprotectError: {}", e),
-            RuntimeError::GuardError(e) => write!(f, "GuardError: {}", e),
-            RuntimeError::PkeyGuardError(e) => write!(f, "PkeyGuardError: {:?}", e),
-            RuntimeError::UnexpectedSuccess => write!(f, "Operation succeeded unexpectedly"),
+            RuntimeError::InvalidArguments(msg) => write!(f, "{}\n\n{}", msg, USAGE),
         }
     }
 }
 
-fn child_pkey_workloads() -> Result<(), RuntimeError> {
-    let pkey = unsafe {
-        PKey::new(PkeyAccessRights::EnableAccessWrite).map_err(RuntimeError::MprotectError)?
-    };
-    let mut protected_mem = unsafe {
-        UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).map_err(RuntimeError::MprotectError)?
-    };
-    unsafe {
-        pkey.associate(&protected_mem, AccessRights::READ_WRITE).map_err(RuntimeError::MprotectError)?;
-    }
-
-    //let mut protected_mem = ProtectedMemory::<u32>::without_pkey(AccessRights::Read)?;
-
-    // Write to the protected memory
-    println!("\tAttempt to write the value 42");
-    unsafe {
-        *protected_mem.as_mut() = 42;
-        println!("\t\tValue written: {}", *protected_mem.as_ref());
-    }
-    println!("\t\tWriting succeeded");
-
-    // Set the pkey to read-only
-    //protected_mem.pkey_mprotect(AccessRights::Read)?;
-    println!("\tSet pkey {} to read-only", pkey.key());
-    unsafe {
-        pkey.set_access_rights(PkeyAccessRights::DisableWrite).map_err(RuntimeError::MprotectError)?;
-    }
-
-    // Read from the protected memory
-    println!("\tAttempt to read the value");
-    unsafe {
-        println!("\t\tValue read: {}", *protected_mem.as_ref());
-    }
-    println!("\t\tReading succeeded");
-
-    // Create another pkey and allocate another memory region with it
-    println!("\tAttempt to create another pkey and allocate memory with it");
-    let pkey2 = unsafe {
-        PKey::new(PkeyAccessRights::EnableAccessWrite).map_err(RuntimeError::MprotectError)?
-    };
-
-    println!("\t\tCreated another pkey {}", pkey2.key());
-    let mut new_memory = unsafe {
-        UnsafeProtectedRegion::<allocator::Jmalloc, u32>::new(AccessRights::READ_WRITE).map_err(RuntimeError::MprotectError)?
-    };
-    unsafe {
-        pkey2.associate(&new_memory, AccessRights::READ_WRITE).map_err(RuntimeError::MprotectError)?;
-    }
-    println!("\tSet the value in pkey {} memory to 100", pkey2.key());
-    unsafe {
-        *new_memory.as_mut() = 100;
-        println!("\t\tValue in new memory: {}", *new_memory.as_ref());
-    }
-    println!("\t\tWriting to new memory succeeded");
-
-    // Create another pkey and switch the pkey of the existing memory region to it
-    println!("\tAttempt to create another pkey and switch the existing memory to it");
-    let pkey3 = unsafe {
-        PKey::new(PkeyAccessRights::DisableWrite).map_err(RuntimeError::MprotectError)?
-    };
-    println!("\t\tCreated another pkey {}", pkey3.key());
-    unsafe {
-        pkey3.associate(&protected_mem, AccessRights::READ).map_err(RuntimeError::MprotectError)?;
-    }
-    println!("\tSwitched the existing memory (before: pkey {}, now: pkey {})", pkey.key(), pkey3.key());
-
-    // Read from the memory region (should succeed)
-    println!("\tAttempt to read the value");
-    unsafe {
-        println!("\t\tValue read: {}", *protected_mem.as_ref());
-    }
-    println!("\t\tReading succeeded");
-
-    // Write to the memory region (should fail)
-    println!("\tAttempt to write the value 84 (this will likely cause a segmentation fault!)");
-    unsafe {
-        *protected_mem.as_mut() = 84;
-        println!("\t\tValue written: {}", *protected_mem.as_ref());
-        println!("\t\tWriting succeeded (this is unexpected!)");
-    }
-    println!("\t\tWriting succeeded (this is unexpected!)");
-
-    // Set the pkey 1 to no access
-    //protected_mem.pkey_mprotect(AccessRights::None)?;
-    println!("\tSet pkey {} to no access", pkey.key());
-    unsafe {
-        pkey3.set_access_rights(PkeyAccessRights::DisableAccess).map_err(RuntimeError::MprotectError)?;
-    }
-
-    // This will likely cause a segmentation fault
-    println!("\tAttempt to read the value again (this will likely cause a segmentation fault!)");
-    unsafe {
-        println!("\t\tValue read: {}", *protected_mem.as_ref());
+/// Escapes `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
     }
-    println!("\t\tReading succeeded (this is unexpected!)");
-
-    Err(RuntimeError::UnexpectedSuccess)
+    out.push('"');
+    out
 }
 
-fn child_regionguard_workloads() -> Result<(), RuntimeError> {
-    let mut safe_mem = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).map_err(RuntimeError::MprotectError)?;
-
-    {
-        println!("\tCreated RegionGuard with ReadWrite access (should succeed)");
-        let read_guard = safe_mem.read().map_err(RuntimeError::GuardError)?;
-        println!("\t\tRead value: {}", *read_guard);
-    }
-
-    {
-        println!("\tAttempt to write the value 42 (should succeed)");
-        let mut write_guard = safe_mem.write().map_err(RuntimeError::GuardError)?;
-        *write_guard = 42;
-        println!("\t\tWrote value: {}", *write_guard);
-    }
-
-    {
-        println!("\tAttempt to read the value again (should succeed)");
-        let read_guard = safe_mem.read().map_err(RuntimeError::GuardError)?;
-        println!("\t\tRead value: {}", *read_guard);
-
-        // At below, we reuse the previous write_guard after invalidation.
-        // This will cause compile-time error because it is also borrowed as mutable before.
-        //println!("\tReuse previous write_guard after invalidation (should fail at compile time)");
-        //*write_guard = 84;  // This line should cause compile-time error
-    }
-
-    {
-        println!("\tAttempt to read the value (should succeed)");
-        let read_guard = safe_mem.read().map_err(RuntimeError::GuardError)?;
-        println!("\t\tRead value: {}", *read_guard);
-        println!("\tDropping read_guard to release the immutable borrow");
-        drop(read_guard);  // Explicitly drop to release the immutable borrow
-
-        println!("\tAttempt to write the value 84 after dropping read_guard (should succeed)");
-        let mut write_guard = safe_mem.write().map_err(RuntimeError::GuardError)?;
-        *write_guard = 84;
-        println!("\t\tWrote value: {}", *write_guard);
-        println!("\tAttempt to rewrite the value 168 using the same write_guard (should succeed)");
-        *write_guard = 168;
-        println!("\t\tRewrote value: {}", *write_guard);
-    }
-
-    {
-        println!("\tUsing with() method to get the closure access (should succeed)");
-        let value = safe_mem.read().map_err(RuntimeError::GuardError)?.with(|v| *v).map_err(RuntimeError::GuardError)?;
-        println!("\t\tValue read via with(): {}", value);
+fn pkey_label(pkey: Option<u32>) -> String {
+    match pkey {
+        Some(key) => format!("pkey {}", key),
+        None => "no pkey reported".to_string(),
     }
-
-    {
-        println!("\tUsing with() method to modify the value (should succeed)");
-        safe_mem.write().map_err(RuntimeError::GuardError)?.with(|v| *v = 256).map_err(RuntimeError::GuardError)?;
-        println!("\t\tGet the value after modification via with() (should succeed)");
-        let value = safe_mem.read().map_err(RuntimeError::GuardError)?.with(|v| *v).map_err(RuntimeError::GuardError)?;
-        println!("\t\tValue after modification via with(): {}", value);
-    }
-
-    {
-        println!("\tUsing dereference to read the value (should succeed)");
-        let value = safe_mem.deref(AccessPermissions::ReadOnly).map_err(RuntimeError::GuardError)?;
-        println!("\t\tValue read via deref(): {}", *value);
-        drop(value);
-
-        println!("\tUsing dereference to write the value 512 (should succeed)");
-        let mut value = safe_mem.deref_mut(AccessPermissions::ReadWrite).map_err(RuntimeError::GuardError)?;
-        *value = 512;
-        println!("\t\tValue written via deref(): {}", *value);
-        drop(value);
-    }
-
-    Ok(())
 }
 
-fn child_regionguard_with_pkey_workloads() -> Result<(), RuntimeError> {
-    let mut safe_mem = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).map_err(RuntimeError::MprotectError)?;
-    let mut safe_mem2 = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).map_err(RuntimeError::MprotectError)?;
-    let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).map_err(RuntimeError::MprotectError)?;
-
-    let mut assoc_for_mem = pkey.associate::<PkeyPermissions::NoAccess>(&mut safe_mem).map_err(RuntimeError::MprotectError)?;
-    let mut assoc2_for_mem2 = pkey.associate::<PkeyPermissions::NoAccess>(&mut safe_mem2).map_err(RuntimeError::MprotectError)?;
-
-    {
-        let assoc_rw = assoc_for_mem.set_access_rights::<PkeyPermissions::ReadWrite>().map_err(RuntimeError::MprotectError)?;
-        let mut value = assoc_rw.mut_ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-        *value = 42;
-        println!("\tValue written via associated region deref(): {}", *value);
-        *value = 84;
-        println!("\tValue rewritten via associated region deref(): {}", *value);
-        drop(value);        // drop here to release the mutable borrow for the next read
-
-        let value = assoc_rw.ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-        println!("\tValue read via associated region deref(): {}", *value);
-    }
-    {
-        let assoc_r = assoc_for_mem.set_access_rights::<PkeyPermissions::ReadOnly>().map_err(RuntimeError::MprotectError)?;
-        // This will be a compile-time error because the set_access_rights() returns a associated region with ReadOnly rights.
-        //let mut value = assoc.write().map_err(RuntimeError::GuardError)?;
-        // *value = 42;
-        //println!("\tValue written via associated region deref(): {}", *value);
-
-        let value = assoc_r.ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-        println!("\tValue read via associated region deref(): {}", *value);
-    }
-    {
-        let assoc_rw = assoc_for_mem.set_access_rights::<PkeyPermissions::ReadWrite>().map_err(RuntimeError::MprotectError)?;
-        let mut value1 = assoc_rw.mut_ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-        *value1 = 84;
-        println!("\tValue written via associated region deref(): {}", *value1);
-    }
-    {
-        let assoc_rw = assoc_for_mem.set_access_rights::<PkeyPermissions::ReadWrite>().map_err(RuntimeError::MprotectError)?;
-        let mut value = assoc_rw.mut_ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-        println!("\tValue read via associated region deref(): {}", *value);
-
-        {
-            let assoc2_rw = assoc2_for_mem2.set_access_rights::<PkeyPermissions::ReadWrite>().map_err(RuntimeError::MprotectError)?;
-            let value = assoc2_rw.mut_ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-            println!("\tValue read via second associated region deref(): {}", *value);
+fn inspect(pid: u32, json: bool) -> Result<(), RuntimeError> {
+    let mappings = smaps::read(pid)
+        .map_err(|errno| RuntimeError::MprotectError(MprotectError::SmapsReadFailed(errno)))?;
+
+    let mut groups: BTreeMap<Option<u32>, Vec<&Mapping>> = BTreeMap::new();
+    for m in &mappings {
+        groups.entry(m.protection_key).or_default().push(m);
+    }
+
+    if json {
+        let groups: Vec<String> = groups.iter().map(|(pkey, entries)| {
+            let size_kb: u64 = entries.iter().map(|m| m.size_kb).sum();
+            let rss_kb: u64 = entries.iter().map(|m| m.rss_kb).sum();
+            let entries: Vec<String> = entries.iter().map(|m| format!(
+                "{{\"start\":\"{:#x}\",\"end\":\"{:#x}\",\"perms\":{},\"size_kb\":{},\"rss_kb\":{},\"path\":{}}}",
+                m.start, m.end, json_string(&m.perms()), m.size_kb, m.rss_kb,
+                m.pathname.as_deref().map_or("null".to_string(), json_string),
+            )).collect();
+            format!(
+                "{{\"pkey\":{},\"mappings\":{},\"size_kb\":{},\"rss_kb\":{},\"entries\":[{}]}}",
+                pkey.map_or("null".to_string(), |k| k.to_string()),
+                entries.len(),
+                size_kb,
+                rss_kb,
+                entries.join(","),
+            )
+        }).collect();
+        println!("{{\"pid\":{},\"groups\":[{}]}}", pid, groups.join(","));
+        return Ok(());
+    }
+
+    println!("PID {}: {} mappings, {} protection key group(s)", pid, mappings.len(), groups.len());
+    for (pkey, entries) in &groups {
+        let size_kb: u64 = entries.iter().map(|m| m.size_kb).sum();
+        let rss_kb: u64 = entries.iter().map(|m| m.rss_kb).sum();
+        println!();
+        println!("{}: {} mappings, {} kB size, {} kB rss", pkey_label(*pkey), entries.len(), size_kb, rss_kb);
+        println!("  {:<33} {:<5} {:>10} {:>10}  PATH", "RANGE", "PERMS", "SIZE(kB)", "RSS(kB)");
+        for m in entries {
+            println!(
+                "  {:016x}-{:016x} {:<5} {:>10} {:>10}  {}",
+                m.start, m.end, m.perms(), m.size_kb, m.rss_kb, m.pathname.as_deref().unwrap_or(""),
+            );
         }
-        {
-            // /let assoc2_r = assoc2_for_mem2.set_access_rights::<PkeyPermissions::ReadOnly>().map_err(RuntimeError::MprotectError)?;   // <- segmentation fault occurs here
-            let assoc2_r = assoc2_for_mem2.set_access_rights::<PkeyPermissions::ReadOnly>().map_err(RuntimeError::MprotectError)?;
-            let value = assoc2_r.ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-            println!("\tValue read via second associated region deref(): {}", *value);
-        }
-
-        *value = 168;
     }
-    {
-        let assoc_r = assoc_for_mem.set_access_rights::<PkeyPermissions::ReadWrite>().map_err(RuntimeError::MprotectError)?;
-        let value = assoc_r.ref_guard().map_err(RuntimeError::PkeyGuardError)?;
-        println!("\tValue read via associated region deref(): {}", *value);
-    }
-
     Ok(())
 }
 
-fn sample_for_pkeyguard() -> Result<(), RuntimeError> {
-    // mprotect で確保したメモリ領域を持つ RegionGuard を生成
-    // デフォルトアクセス権は ReadWrite. 後に Intel PKU によりアクセス権を制御する
-    let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).map_err(RuntimeError::MprotectError)?;
-    // Intel PKU の Protection Key を生成
-    let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).map_err(RuntimeError::MprotectError)?;
-    // RegionGuard と Protection Key を関連付ける
-    // 以降、RegionGuard のアクセス権は Protection Key により制御される
-    // 初期状態は NoAccess (-/-). アクセス不可
-    let mut associated_region = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).map_err(RuntimeError::MprotectError)?;
-
-    // mutable な参照を取得し値を書き込む. read/write 可
-    {
-        // 参照を取得
-        // このとき、Protection Key のアクセス権が ReadWrite に変更され、RegionGuard への read/write アクセスが許可される
-        let write_guard = associated_region.set_access_rights::<PkeyPermissions::ReadWrite>().map_err(RuntimeError::MprotectError)?;
-        let mut mut_ref_guard = write_guard.mut_ref_guard().map_err(|e| RuntimeError::PkeyGuardError(e))?;
-        // write
-        *mut_ref_guard = 123;
-        // read
-        println!("Value written via associated region deref(): {}", *mut_ref_guard);
-    }
-    // immutable な参照を取得し値を読む. read のみ可
-    {
-        // 参照を取得
-        // このとき、Protection Key のアクセス権が ReadOnly に変更され、RegionGuard への read アクセスのみが許可される
-        let read_guard = associated_region.set_access_rights::<PkeyPermissions::ReadOnly>().map_err(RuntimeError::MprotectError)?;
-        let ref_guard = read_guard.ref_guard().map_err(|e| RuntimeError::PkeyGuardError(e))?;
-        // read
-        println!("Value read via associated region deref(): {}", *ref_guard);
-        // write は borrow checker によりコンパイルエラーになる
-        //*ref_guard = 456;
-
-        // unsafe code で無理やり mutable な参照を取得しようとすると、実行時に segmentation fault になる
-        unsafe {
-            let mut_ref = ref_guard.ptr() as *const u32 as *mut u32;
-            println!("Attempt to write via unsafe mutable reference...");
-            *mut_ref = 789;  // ここで segmentation fault になる
-        }
-
-        Err(RuntimeError::UnexpectedSuccess)
-    }
-}
-
-fn handle_child_exit(flag: String) {
-    // Do workloads in a child process
-    let status = Command::new(std::env::current_exe().unwrap())
-        .arg(flag)
-        .status()
-        .expect("Failed to execute child process");
-
-    if let Some(signal) = status.signal() {
-        eprintln!("Child process exited with: {}", signal);
-        if signal == 11 {
-            eprintln!("Segmentation fault occurred");
-            return;
+/// Counts the protection keys this process can still allocate.
+///
+/// The kernel does not expose the count, so every free key is allocated with a raw
+/// `pkey_alloc` and then released. While this runs, `pkey_alloc` fails on any other
+/// thread, which is why it lives in this single-threaded tool and not in the library.
+fn free_pkeys() -> usize {
+    let mut keys = Vec::new();
+    loop {
+        let key = unsafe { libc::syscall(libc::SYS_pkey_alloc, 0, 0) };
+        if key < 0 {
+            break;
         }
-        eprintln!("Child process exited due to signal: {}", signal);
+        keys.push(key);
     }
-    else if let Some(code) = status.code() {
-        if code != 0 {
-            eprintln!("Child process exited with non-zero status: {}", code);
-        }
-    }
-    else {
-        eprintln!("Child process exited in an unexpected way");
+    for &key in &keys {
+        unsafe { libc::syscall(libc::SYS_pkey_free, key) };
     }
-
-    println!("Main thread finished");
+    keys.len()
 }
 
-fn parent_main() {
-    println!("Parent process started with PID {}", std::process::id());
-    
-    println!("--- Testing Protection Key Workloads ---");
-    handle_child_exit("--pkeys".to_string());
-
-    println!("--- Testing RegionGuard Workloads ---");
-    handle_child_exit("--regionguard".to_string());
+fn probe(json: bool) {
+    let caps = Capabilities::probe();
+    let free_pkeys = free_pkeys();
+    if json {
+        println!(
+            "{{\"pku\":{},\"ospke\":{},\"free_pkeys\":{},\"kernel\":{},\"mseal\":{},\"memfd_secret\":{},\"soft_dirty\":{},\"thp\":{}}}",
+            caps.pku, caps.ospke, free_pkeys, json_string(&caps.kernel), caps.mseal, caps.memfd_secret, caps.soft_dirty,
+            caps.thp.as_deref().map_or("null".to_string(), json_string),
+        );
+        return;
+    }
+
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    println!("{:<14} {}", "PKU:", yes_no(caps.pku));
+    println!("{:<14} {}", "OSPKE:", yes_no(caps.ospke));
+    println!("{:<14} {}", "Free pkeys:", free_pkeys);
+    println!("{:<14} {}", "Kernel:", caps.kernel);
+    println!("{:<14} {}", "mseal:", yes_no(caps.mseal));
+    println!("{:<14} {}", "memfd_secret:", yes_no(caps.memfd_secret));
+    println!("{:<14} {}", "Soft-dirty:", yes_no(caps.soft_dirty));
+    println!("{:<14} {}", "THP:", caps.thp.as_deref().unwrap_or("unavailable"));
+}
 
-    println!("--- Testing RegionGuard with PKey Workloads ---");
-    handle_child_exit("--regionguard-pkey".to_string());
+fn run() -> Result<(), RuntimeError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let positional: Vec<&str> = args.iter().map(String::as_str).filter(|a| *a != "--json").collect();
 
-    let ret = sample_for_pkeyguard();
-    if let Err(e) = ret {
-        eprintln!("sample_for_pkeyguard() failed, but this is expected if segmentation fault occurred: {}", e);
+    match positional.as_slice() {
+        ["inspect", pid] => {
+            let pid = pid.parse()
+                .map_err(|_| RuntimeError::InvalidArguments(format!("invalid pid: {}", pid)))?;
+            inspect(pid, json)?;
+        }
+        ["inspect"] => return Err(RuntimeError::InvalidArguments("inspect requires a pid".to_string())),
+        ["probe"] => probe(json),
+        [] | ["-h"] | ["--help"] | ["help"] => println!("{}", USAGE),
+        [other, ..] => return Err(RuntimeError::InvalidArguments(format!("unknown command: {}", other))),
     }
 
-    println!("Parent process finished");
+    Ok(())
 }
 
-fn main() -> Result<(), RuntimeError> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 && args[1] == "--pkeys" {
-        println!("Child process started with PID {}", std::process::id());
-        child_pkey_workloads()?;      // This function handles its own errors and panics on failure
-        println!("Child process finished without segmentation fault (this is unexpected!)");
-    } else if args.len() > 1 && args[1] == "--regionguard" {
-        println!("Child process started with PID {}", std::process::id());
-        child_regionguard_workloads()?;      // This function handles its own errors and panics
-        println!("Child process finished successfully");
-    } else if args.len() > 1 && args[1] == "--regionguard-pkey" {
-        println!("Child process started with PID {}", std::process::id());
-        child_regionguard_with_pkey_workloads()?;      // This function handles its own errors and panics
-        println!("Child process finished successfully");
-    } else {
-        parent_main();
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
-
-    Ok(())
 }
@@ -1,10 +1,13 @@
 use std::fmt::Display;
+use std::ops::Deref;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
 
-mod pkru;
+pub mod pkru;
+pub(crate) mod registry;
 
 use crate::AccessRights;
-use crate::allocator;
-use crate::UnsafeProtectedRegion;
+use crate::ProtectedMemory;
 
 /// Access rights for a protection key.
 /// 
@@ -24,6 +27,17 @@ pub enum PkeyAccessRights {
     DisableWrite = 0x2,
 }
 
+impl PkeyAccessRights {
+    /// Decodes the two PKRU bits of a key. Both bits set denies all access.
+    pub(crate) const fn from_pkru_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b00 => PkeyAccessRights::EnableAccessWrite,
+            0b10 => PkeyAccessRights::DisableWrite,
+            _ => PkeyAccessRights::DisableAccess,
+        }
+    }
+}
+
 impl Display for PkeyAccessRights {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -43,6 +57,18 @@ impl Display for PkeyAccessRights {
 /// Protection keys are a limited resource (typically 15 keys available on x86-64).
 /// Keys are allocated by `new()` and automatically freed when the `PKey` instance is dropped.
 /// 
+/// `PKey` owns its key and is therefore move-only: a copy would free the key a second
+/// time while pages are still tagged with it. To use one key from several places,
+/// convert it into a reference-counted [`SharedPKey`] with [`PKey::into_shared`].
+/// 
+/// # Teardown
+/// 
+/// A key number released with `pkey_free` is handed out again by the next `pkey_alloc`.
+/// Before releasing the key, `PKey` therefore re-tags every region still associated with
+/// it (through [`PKey::associate`] or [`crate::PkeyGuard::associate`]) back to the default
+/// key 0, keeping each region's page-level rights. If a region cannot be re-tagged, the
+/// key is not freed and the failure is reported instead.
+/// 
 /// # How Protection Keys Work
 /// 
 /// 1. A memory region is associated with a protection key using `pkey_mprotect`
@@ -70,7 +96,6 @@ impl Display for PkeyAccessRights {
 /// }
 /// # Ok::<(), mprotect_rs::MprotectError>(())
 /// ```
-#[derive(Clone)]
 pub struct PKey {
     key: u32,
 }
@@ -118,10 +143,56 @@ impl PKey {
 
         if key < 0 {
             let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
-            Err(super::MprotectError::PkeyAllocFailed(err_no))
-        } else {
-            Ok(PKey { key: key as u32 })
+            if let Some(pkey) = Self::reclaim_quarantined() {
+                pkey.set_access_rights(access)?;
+                return Ok(pkey);
+            }
+            return Err(super::MprotectError::PkeyAllocFailed(err_no));
+        }
+
+        let key = key as u32;
+        if STALE_KEY_CHECK.load(Ordering::Relaxed) && Self::has_stale_pages(key) {
+            // The key stays allocated on purpose: freeing it would only hand the
+            // same number, and the same stale pages, to the next caller.
+            QUARANTINED_KEYS.lock().unwrap_or_else(|e| e.into_inner()).push(key);
+            return Err(super::MprotectError::StalePkeyReuse(key));
         }
+        Ok(PKey { key })
+    }
+
+    /// Takes a key held back by the stale-key check whose stale pages are gone.
+    ///
+    /// Only called once `pkey_alloc` has run out of keys, so the smaps read stays off
+    /// the common path.
+    fn reclaim_quarantined() -> Option<PKey> {
+        let mut quarantined = QUARANTINED_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+        let index = quarantined.iter().position(|&key| !Self::has_stale_pages(key))?;
+        Some(PKey { key: quarantined.swap_remove(index) })
+    }
+
+    /// Enables or disables the stale-key check performed by [`PKey::new`].
+    /// 
+    /// When enabled, every newly allocated key is checked for pages that are still
+    /// tagged with it, which happens if a previous owner freed the key without
+    /// re-tagging its pages (e.g. through a raw `pkey_free`). Such a key is reported as
+    /// [`MprotectError::StalePkeyReuse`](super::MprotectError::StalePkeyReuse) instead of
+    /// being returned, and kept aside: once every other key is taken, `PKey::new` hands
+    /// it out again if its stale pages are gone by then.
+    ///
+    /// The check reads `/proc/self/smaps` on every allocation, so it is disabled by default.
+    pub fn set_stale_key_check(enabled: bool) {
+        STALE_KEY_CHECK.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if memory is still tagged with the freshly allocated `key`.
+    fn has_stale_pages(key: u32) -> bool {
+        if !registry::tagged_with(&registry::lock(), key).is_empty() {
+            return true;
+        }
+        // A process without a readable smaps cannot be checked; treat it as clean.
+        crate::smaps::read_self()
+            .map(|mappings| mappings.iter().any(|m| m.protection_key == Some(key)))
+            .unwrap_or(false)
     }
 
     /// Retrieves the current access rights of the protection key from the PKRU register.
@@ -148,20 +219,13 @@ impl PKey {
     /// let pkey = PKey::new(PkeyAccessRights::DisableAccess)?;
     /// let rights = pkey.get_access_rights();
     /// assert_eq!(rights, PkeyAccessRights::DisableAccess);
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
     pub unsafe fn get_access_rights(&self) -> PkeyAccessRights {
         let pkru_value = pkru::rdpkru();
 
-        let rights_bits = (pkru_value >> (self.key * 2)) & 0b11;
-        match rights_bits {
-            0b00 => PkeyAccessRights::EnableAccessWrite,
-            0b01 => PkeyAccessRights::DisableAccess,
-            0b10 => PkeyAccessRights::DisableWrite,
-            0b11 => PkeyAccessRights::DisableAccess,
-            _ => { unreachable!() }
-        }
+        PkeyAccessRights::from_pkru_bits(pkru::key_bits(pkru_value, self.key))
     }
 
     /// Sets the access rights of the protection key by modifying the PKRU register.
@@ -172,9 +236,14 @@ impl PKey {
     /// with this key in the current thread.
     /// 
     /// **Note**: PKRU is a thread-local register, so changes only affect the current thread.
-    /// 
+    ///
+    /// The register is written through [`pkru::wrpkru`], which aborts the process if the
+    /// value left in `eax` after `WRPKRU` is not the one computed here. The value is
+    /// computed at runtime, so see the [`pkru`] module for what that check can and
+    /// cannot catch.
+    ///
     /// # Safety
-    /// 
+    ///
     /// This function is unsafe because it directly modifies CPU registers and can affect
     /// the accessibility of memory regions across the program.
     /// 
@@ -196,8 +265,8 @@ impl PKey {
     /// let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite)?;
     /// // Later, disable write access
     /// pkey.set_access_rights(PkeyAccessRights::DisableWrite)?;
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
     pub unsafe fn set_access_rights(&self, access: PkeyAccessRights) -> Result<(), super::MprotectError> {
         let pkru_value = pkru::rdpkru();
@@ -206,9 +275,11 @@ impl PKey {
             PkeyAccessRights::EnableAccessWrite => 0b00,
             PkeyAccessRights::DisableAccess => 0b01,
             PkeyAccessRights::DisableWrite => 0b10,
-        } << (self.key * 2);
+        };
         
-        let new_pkru_value = pkru_value & !(0b11 << (self.key * 2)) | new_pkru_bits;
+        // The write goes through the hardened gate, which aborts if the value
+        // actually written differs from `new_pkru_value`.
+        let new_pkru_value = pkru::with_key_bits(pkru_value, self.key, new_pkru_bits);
         pkru::wrpkru(new_pkru_value);
         
         Ok(())
@@ -285,7 +356,8 @@ impl PKey {
     /// 
     /// # Arguments
     /// 
-    /// - `region`: A reference to the memory region to be associated with this protection key.
+    /// - `region`: A reference to the memory region to be associated with this protection key:
+    ///   an [`UnsafeProtectedRegion`](crate::UnsafeProtectedRegion) or a committed part of a [`crate::ReservedRegion`].
     /// - `access_rights`: The page-level access rights to be set for the memory region.
     /// 
     /// # Returns
@@ -301,11 +373,13 @@ impl PKey {
     /// let pkey = PKey::new(PkeyAccessRights::DisableWrite)?;
     /// let region = UnsafeProtectedRegion::<Mmap, i32>::new(AccessRights::READ_WRITE)?;
     /// pkey.associate(&region, AccessRights::READ_WRITE)?;
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
-    pub unsafe fn associate<A: allocator::Allocator<T>, T>(&self, region: &UnsafeProtectedRegion<A, T>, access_rights: AccessRights) -> Result<(), super::MprotectError> {
-        Self::impl_pkey_mprotect(access_rights, region.ptr() as *mut libc::c_void, region.len(), self.key)?;
+    pub unsafe fn associate<R: ProtectedMemory + ?Sized>(&self, region: &R, access_rights: AccessRights) -> Result<(), super::MprotectError> {
+        let tag = region.tag();
+        Self::impl_pkey_mprotect(access_rights, tag.start() as *mut libc::c_void, tag.len(), self.key)?;
+        registry::set_pkey(tag, Some(self.key), access_rights);
         Ok(())
     }
 
@@ -341,11 +415,73 @@ impl PKey {
     /// pkey.associate(&region, AccessRights::READ_WRITE)?;
     /// // Later, remove the protection key association
     /// pkey.disassociate(&region, AccessRights::READ_WRITE)?;
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
-    pub unsafe fn disassociate<A: allocator::Allocator<T>, T>(&self, region: &UnsafeProtectedRegion<A, T>, access_rights: AccessRights) -> Result<(), super::MprotectError> {
-        Self::impl_pkey_mprotect(access_rights, region.ptr() as *mut libc::c_void, region.len(), 0)?;
+    pub unsafe fn disassociate<R: ProtectedMemory + ?Sized>(&self, region: &R, access_rights: AccessRights) -> Result<(), super::MprotectError> {
+        let tag = region.tag();
+        Self::impl_pkey_mprotect(access_rights, tag.start() as *mut libc::c_void, tag.len(), 0)?;
+        registry::set_pkey(tag, None, access_rights);
+        Ok(())
+    }
+
+    /// Returns the number of live regions currently associated with this key.
+    /// 
+    /// These are the regions that will be re-tagged to the default key when the key
+    /// is freed. Regions that have been dropped or disassociated are not counted.
+    pub fn associated_regions(&self) -> usize {
+        registry::tagged_with(&registry::lock(), self.key).len()
+    }
+
+    /// Converts this key into a reference-counted handle that can be cloned.
+    /// 
+    /// The key is freed when the last [`SharedPKey`] handle is dropped.
+    pub fn into_shared(self) -> SharedPKey {
+        SharedPKey { inner: Arc::new(self) }
+    }
+
+    /// Re-tags the key's regions to the default key and frees the key.
+    /// 
+    /// Dropping a `PKey` does the same, but a destructor cannot return an error.
+    /// Call this method where a failure to release the key must be handled.
+    /// 
+    /// # Returns
+    /// 
+    /// - `Ok(())`: If the key was released.
+    /// - `Err(MprotectError::PkeyMprotectFailed)`: If a region could not be re-tagged.
+    ///   The key is not freed, so no stale page can be handed to its next owner.
+    /// - `Err(MprotectError::PkeyFreeFailed)`: If the `pkey_free` system call fails.
+    pub fn free(self) -> Result<(), super::MprotectError> {
+        let key = self.key;
+        std::mem::forget(self);
+        unsafe { Self::release(key) }
+    }
+
+    /// Re-tags every live region associated with `key` to key 0, then frees `key`.
+    /// 
+    /// The registry stays locked throughout, so a region cannot be unmapped (and its
+    /// address reused) between being looked up and being re-tagged.
+    unsafe fn release(key: u32) -> Result<(), super::MprotectError> {
+        let mut registry = registry::lock();
+        for tag in registry::tagged_with(&registry, key) {
+            Self::impl_pkey_mprotect(tag.access_rights(), tag.start() as *mut libc::c_void, tag.len(), 0)?;
+            registry::clear_locked(&mut registry, &tag);
+        }
+        Self::impl_pkey_free(key)
+    }
+
+    /// Internal implementation of `pkey_free` system call.
+    unsafe fn impl_pkey_free(key: u32) -> Result<(), super::MprotectError> {
+        let ret = libc::syscall(
+            libc::SYS_pkey_free,
+            key,                // The protection key to be freed
+        );
+
+        if ret != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(super::MprotectError::PkeyFreeFailed(err_no));
+        }
+
         Ok(())
     }
 }
@@ -353,19 +489,93 @@ impl PKey {
 impl Drop for PKey {
     /// Automatically frees the protection key when the `PKey` instance is dropped.
     /// 
-    /// This destructor ensures that the protection key is properly released back to the system
-    /// using the `pkey_free` system call. After the key is freed, it can be reallocated
-    /// by other parts of the program or by other processes.
+    /// This destructor re-tags the key's remaining regions to the default key and releases
+    /// the key back to the system using the `pkey_free` system call. After the key is freed,
+    /// it can be reallocated by other parts of the program.
     /// 
-    /// **Note**: If freeing the key fails, the error is silently ignored as there is no
-    /// safe way to handle errors in a destructor. However, failures are rare and typically
-    /// only occur with invalid key IDs.
+    /// **Note**: A destructor cannot report errors, so if re-tagging or freeing fails, the
+    /// error is printed to stderr and the key stays allocated: if a region could not be
+    /// re-tagged, freeing the key would hand its pages to the key's next owner. Use
+    /// [`PKey::free`] to handle the error instead.
     fn drop(&mut self) {
-        unsafe {
-            libc::syscall(
-                libc::SYS_pkey_free,
-                self.key,           // The protection key to be freed
-            );
+        if let Err(e) = unsafe { Self::release(self.key) } {
+            eprintln!("Failed to free protection key {}: {}", self.key, e);
+        }
+    }
+}
+
+/// Whether [`PKey::new`] checks new keys for stale pages. See [`PKey::set_stale_key_check`].
+static STALE_KEY_CHECK: AtomicBool = AtomicBool::new(false);
+
+/// Keys the stale-key check held back, still allocated, until their stale pages are gone.
+static QUARANTINED_KEYS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// A reference-counted handle to a protection key.
+/// 
+/// `SharedPKey` lets several owners (for example multiple `PkeyGuard`s or threads) use
+/// the same hardware key. Cloning a handle only increments a reference count; the key
+/// is released with `pkey_free` when the last handle is dropped, so it can never be freed
+/// while another handle still uses it.
+/// 
+/// All [`PKey`] methods are available through `Deref`.
+/// 
+/// # Example
+/// 
+/// ```no_run
+/// use mprotect_rs::{PKey, PkeyAccessRights};
+/// 
+/// let pkey = unsafe { PKey::new(PkeyAccessRights::DisableAccess)? }.into_shared();
+/// let other = pkey.clone();
+/// drop(pkey);             // The key is still allocated
+/// assert_eq!(other.handle_count(), 1);
+/// other.free()?;          // Last handle: the key is released here
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+#[derive(Clone)]
+pub struct SharedPKey {
+    inner: Arc<PKey>,
+}
+
+impl SharedPKey {
+    /// Returns the number of live handles to this key.
+    pub fn handle_count(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+
+    /// Returns the owned `PKey` if this is the last handle.
+    /// 
+    /// # Returns
+    /// 
+    /// - `Ok(PKey)`: If no other handle exists.
+    /// - `Err(SharedPKey)`: This handle, if other handles are still alive.
+    pub fn try_unwrap(self) -> Result<PKey, SharedPKey> {
+        Arc::try_unwrap(self.inner).map_err(|inner| SharedPKey { inner })
+    }
+
+    /// Drops this handle and frees the key if it was the last one.
+    /// 
+    /// # Returns
+    /// 
+    /// - `Ok(())`: If other handles remain, or the key was released.
+    /// - `Err(MprotectError)`: If this was the last handle and [`PKey::free`] fails.
+    pub fn free(self) -> Result<(), super::MprotectError> {
+        match self.try_unwrap() {
+            Ok(pkey) => pkey.free(),
+            Err(_) => Ok(()),
         }
     }
 }
+
+impl Deref for SharedPKey {
+    type Target = PKey;
+
+    fn deref(&self) -> &PKey {
+        &self.inner
+    }
+}
+
+impl From<PKey> for SharedPKey {
+    fn from(pkey: PKey) -> Self {
+        pkey.into_shared()
+    }
+}
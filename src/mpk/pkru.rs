@@ -1,3 +1,26 @@
+//! Raw access to the PKRU register.
+//!
+//! Every PKRU write performed by this crate goes through the gates in this module.
+//! Following ERIM, each `WRPKRU` is immediately followed by a check that `eax` still
+//! holds the value the caller intended to write; if it does not, the process is
+//! terminated with `ud2` before any code can run with the unintended rights.
+//!
+//! How much this protects depends on where the intended value comes from:
+//!
+//! - [`wrpkru_const`] loads and checks an immediate, so a control-flow hijack that
+//!   jumps onto its `WRPKRU` cannot leave any other value in PKRU.
+//! - [`wrpkru`] checks against a second register holding the runtime value. It only
+//!   catches a jump onto `WRPKRU` that sets `eax` but not that register; a hijack that
+//!   controls both registers can write any value. It is a consistency check, not a
+//!   security boundary against such an attacker.
+//!
+//! Call sites writing a value known at compile time, such as
+//! [`PkruState::DENY_ALL`](crate::signals::PkruState::DENY_ALL), therefore use
+//! [`wrpkru_const`].
+//!
+//! The gates are public so that code built on top of this crate can reuse them
+//! instead of emitting its own unchecked `WRPKRU`.
+
 use std::arch::asm;
 
 /// Reads the current value of the PKRU register.
@@ -16,7 +39,9 @@ use std::arch::asm;
 ///
 /// # Example
 ///
-/// ```
+/// ```no_run
+/// use mprotect_rs::pkru::rdpkru;
+///
 /// unsafe {
 ///     let pkru = rdpkru();
 ///     println!("PKRU = 0x{:08x}", pkru);
@@ -33,15 +58,22 @@ pub unsafe fn rdpkru() -> u32 {
     value
 }
 
-/// Writes a value to the PKRU register.
+/// Writes a value to the PKRU register through a hardened gate.
 ///
 /// Executes the `WRPKRU` instruction to update access rights
-/// for memory pages managed by Intel MPK.
+/// for memory pages managed by Intel MPK, then compares `eax` against a
+/// second copy of the intended value. On mismatch the process aborts with
+/// `ud2` (`SIGILL`) instead of continuing with the written rights.
+///
+/// The intended value is held in a general-purpose register, so this gate only
+/// catches jumps onto the `WRPKRU` instruction that do not also control that
+/// register; it does not stop a hijack that chooses both. When the value is known
+/// at compile time, use [`wrpkru_const`], which compares against an immediate.
 ///
 /// # Safety
 ///
 /// This function is **unsafe** because it changes memory access rights
-/// process-wide. Incorrect values may cause access violations.
+/// of the calling thread. Incorrect values may cause access violations.
 ///
 /// # Arguments
 ///
@@ -49,7 +81,9 @@ pub unsafe fn rdpkru() -> u32 {
 ///
 /// # Example
 ///
-/// ```
+/// ```no_run
+/// use mprotect_rs::pkru::{rdpkru, wrpkru};
+///
 /// unsafe {
 ///     let old = rdpkru();
 ///     wrpkru(old | 0b10); // Disable write for key 0
@@ -59,7 +93,82 @@ pub unsafe fn rdpkru() -> u32 {
 pub unsafe fn wrpkru(pkru: u32) {
     asm!(
         "wrpkru",
+        "cmp eax, {expected:e}",
+        "je 2f",
+        "ud2",
+        "2:",
+        expected = in(reg) pkru,
         in("ecx") 0, in("edx") 0, in("eax") pkru,
-        options(nostack, preserves_flags)
+        options(nostack)
+    );
+}
+
+/// Writes a compile-time constant to the PKRU register through a hardened gate.
+///
+/// This is the ERIM call-gate shape: the value loaded into `eax` and the value
+/// checked after `WRPKRU` are both immediates encoded in the instruction stream,
+/// so no register state at the time of a hijacked jump can make the gate leave
+/// a different value in PKRU. On mismatch the process aborts with `ud2`.
+///
+/// # Safety
+///
+/// This function is **unsafe** because it changes memory access rights
+/// of the calling thread. Incorrect values may cause access violations.
+///
+/// # Type Parameters
+///
+/// * `PKRU` — The new 32-bit PKRU value.
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::pkru::wrpkru_const;
+///
+/// unsafe {
+///     // Enable every key except key 1.
+///     wrpkru_const::<0b0100>();
+/// }
+/// ```
+#[inline]
+pub unsafe fn wrpkru_const<const PKRU: u32>() {
+    asm!(
+        "mov eax, {value}",
+        "wrpkru",
+        "cmp eax, {value}",
+        "je 2f",
+        "ud2",
+        "2:",
+        value = const PKRU,
+        in("ecx") 0, in("edx") 0, out("eax") _,
+        options(nostack)
     );
 }
+
+/// Returns `pkru` with the two rights bits of `key` replaced by `bits`.
+///
+/// # Arguments
+///
+/// * `pkru` — The PKRU value to modify.
+/// * `key` — The protection key whose bits are replaced.
+/// * `bits` — The new access-disable / write-disable bits (`0b00`..=`0b11`).
+///
+/// # Returns
+///
+/// The updated 32-bit PKRU value.
+#[inline]
+pub const fn with_key_bits(pkru: u32, key: u32, bits: u32) -> u32 {
+    (pkru & !(0b11 << (key * 2))) | ((bits & 0b11) << (key * 2))
+}
+
+/// Returns the two rights bits of `key` in `pkru`.
+///
+/// Bit 0 is access-disable (AD) and bit 1 is write-disable (WD).
+///
+/// # Arguments
+///
+/// * `pkru` — The PKRU value to inspect.
+/// * `key` — The protection key whose bits are returned.
+#[inline]
+pub const fn key_bits(pkru: u32, key: u32) -> u32 {
+    (pkru >> (key * 2)) & 0b11
+}
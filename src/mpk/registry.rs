@@ -0,0 +1,109 @@
+//! Process-wide record of which memory ranges are tagged with which protection key.
+//!
+//! A freed key number is handed out again by the next `pkey_alloc`, possibly with
+//! different rights. Pages still tagged with the old key would silently become
+//! governed by the new owner, so [`crate::PKey`] consults this registry on release
+//! and re-tags every live range back to the default key first.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{ AtomicI32, AtomicU32, Ordering };
+use std::sync::{ Arc, Mutex, MutexGuard, Weak };
+
+use crate::AccessRights;
+
+/// Sentinel stored in [`PkeyTag::pkey`] when the range is on the default key.
+const NO_PKEY: u32 = u32::MAX;
+
+/// Protection state of one memory region, shared between the region and the registry.
+pub struct PkeyTag {
+    start: usize,
+    len: usize,
+    pkey: AtomicU32,
+    access_rights: AtomicI32,
+}
+
+impl PkeyTag {
+    pub(crate) fn new(start: usize, len: usize, access_rights: AccessRights) -> Arc<Self> {
+        Arc::new(PkeyTag {
+            start,
+            len,
+            pkey: AtomicU32::new(NO_PKEY),
+            access_rights: AtomicI32::new(access_rights.bits()),
+        })
+    }
+
+    pub(crate) fn start(&self) -> usize {
+        self.start
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn pkey(&self) -> Option<u32> {
+        match self.pkey.load(Ordering::Acquire) {
+            NO_PKEY => None,
+            key => Some(key),
+        }
+    }
+
+    pub(crate) fn access_rights(&self) -> AccessRights {
+        AccessRights::from_bits_truncate(self.access_rights.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn set_access_rights(&self, access_rights: AccessRights) {
+        self.access_rights.store(access_rights.bits(), Ordering::Release);
+    }
+}
+
+type Registry = BTreeMap<u32, Vec<Weak<PkeyTag>>>;
+
+static REGISTRY: Mutex<Registry> = Mutex::new(BTreeMap::new());
+
+/// Locks the registry. A poisoned lock is recovered: the map stays structurally valid.
+pub(crate) fn lock() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn detach(registry: &mut Registry, tag: &Arc<PkeyTag>) {
+    if let Some(key) = tag.pkey() {
+        if let Some(tags) = registry.get_mut(&key) {
+            tags.retain(|t| t.strong_count() > 0 && !std::ptr::eq(t.as_ptr(), Arc::as_ptr(tag)));
+            if tags.is_empty() {
+                registry.remove(&key);
+            }
+        }
+    }
+}
+
+/// Records that `tag`'s range is now tagged with `pkey` (`None` for the default key).
+pub(crate) fn set_pkey(tag: &Arc<PkeyTag>, pkey: Option<u32>, access_rights: AccessRights) {
+    let mut registry = lock();
+    detach(&mut registry, tag);
+    tag.pkey.store(pkey.unwrap_or(NO_PKEY), Ordering::Release);
+    tag.set_access_rights(access_rights);
+    if let Some(key) = pkey {
+        registry.entry(key).or_default().push(Arc::downgrade(tag));
+    }
+}
+
+/// Removes `tag` from the registry, e.g. before its range is unmapped.
+pub(crate) fn untrack(tag: &Arc<PkeyTag>) {
+    let mut registry = lock();
+    detach(&mut registry, tag);
+    tag.pkey.store(NO_PKEY, Ordering::Release);
+}
+
+/// Returns the live ranges tagged with `key`.
+pub(crate) fn tagged_with(registry: &Registry, key: u32) -> Vec<Arc<PkeyTag>> {
+    registry
+        .get(&key)
+        .map(|tags| tags.iter().filter_map(Weak::upgrade).filter(|t| t.pkey() == Some(key)).collect())
+        .unwrap_or_default()
+}
+
+/// Marks `tag` as moved to the default key while the registry is locked.
+pub(crate) fn clear_locked(registry: &mut Registry, tag: &Arc<PkeyTag>) {
+    detach(registry, tag);
+    tag.pkey.store(NO_PKEY, Ordering::Release);
+}
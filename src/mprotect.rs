@@ -1,15 +1,40 @@
 use core::panic;
 
+use std::mem::ManuallyDrop;
 use std::ptr::NonNull;
+use std::sync::Arc;
+
+use crate::pkru;
+use crate::mpk::registry::{ self, PkeyTag };
+use crate::quarantine;
+use crate::smaps;
 
 pub mod allocator;
 
+mod kernel_state;
+pub use kernel_state::{ KernelState, ProtectionDrift };
+
 pub mod access_rights;
 pub use access_rights::AccessRights;
 pub use access_rights::access_permissions as AccessPermissions;
 pub use AccessPermissions::{ ReadAllowed, WriteAllowed, ExecuteAllowed, NoAccessAllowed, AllAccesses };
 pub use AccessPermissions::{ ReadAllowedTrait, WriteAllowedTrait, ExecuteAllowedTrait, NoAccessAllowedTrait, AllAccessesTrait };
 
+/// Memory whose pages can be tagged with a protection key by [`crate::PKey::associate`].
+///
+/// Implemented by [`UnsafeProtectedRegion`] and by the committed parts of a
+/// [`crate::ReservedRegion`]. This trait is sealed.
+pub trait ProtectedMemory: sealed::Sealed {}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Sealed {
+        /// Returns the protection state of the memory, shared with the key registry.
+        fn tag(&self) -> &Arc<PkeyTag>;
+    }
+}
+
 /// A low-level memory region protected by `mprotect` or `pkey_mprotect` system calls.
 /// 
 /// This struct represents a memory region with controlled access permissions. It uses
@@ -28,7 +53,8 @@ pub use AccessPermissions::{ ReadAllowedTrait, WriteAllowedTrait, ExecuteAllowed
 /// 
 /// The memory region is:
 /// - Allocated on creation using the specified allocator
-/// - Automatically deallocated when the `UnsafeProtectedRegion` instance is dropped
+/// - Automatically deallocated when the `UnsafeProtectedRegion` instance is dropped, or
+///   kept inaccessible first while the [`quarantine`](crate::quarantine) is enabled
 /// - Page-aligned (for `Mmap` allocator) to work with `mprotect` system calls
 /// 
 /// # Type Parameters
@@ -40,7 +66,8 @@ pub use AccessPermissions::{ ReadAllowedTrait, WriteAllowedTrait, ExecuteAllowed
 /// 
 /// - `ptr`: A non-null pointer to the allocated memory region
 /// - `len`: The length of the allocated memory region in bytes
-/// - `pkey_id`: An optional protection key ID if associated with a pkey
+/// - `tag`: The protection key and page-level access rights last applied to the region,
+///   shared with the key registry so a freed [`crate::PKey`] can re-tag the region
 /// - `allocator`: The allocator instance used to manage the memory region
 /// 
 /// # Example
@@ -70,8 +97,8 @@ pub use AccessPermissions::{ ReadAllowedTrait, WriteAllowedTrait, ExecuteAllowed
 pub struct UnsafeProtectedRegion<A: allocator::Allocator<T>, T> {
     ptr: NonNull<T>,
     len: usize,
-    pkey_id: Option<u32>,
-    allocator: allocator::MemoryRegion<A, T>,
+    tag: Arc<PkeyTag>,
+    allocator: ManuallyDrop<allocator::MemoryRegion<A, T>>,
     initialized: bool,
 }
 
@@ -116,11 +143,13 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
                 allocator::AllocatorError::MunmapFailed(errno) => errno,
                 allocator::AllocatorError::LayoutError => -1,
             }))?;
+        let ptr = NonNull::new(allocator.ptr()).ok_or(super::MprotectError::MemoryAllocationFailed(-1))?;
+        let len = std::mem::size_of::<T>();
         Ok(Self {
-            ptr: NonNull::new(allocator.ptr()).ok_or(super::MprotectError::MemoryAllocationFailed(-1))?,
-            len: std::mem::size_of::<T>(),
-            pkey_id: None,
-            allocator,
+            ptr,
+            len,
+            tag: PkeyTag::new(ptr.as_ptr() as usize, len, access_rights),
+            allocator: ManuallyDrop::new(allocator),
             initialized: false,
         })
     }
@@ -176,10 +205,13 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
     /// 
     /// // Change to read-only
     /// region.set_access(AccessRights::READ)?;
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
     pub unsafe fn set_access(&self, access_rights: AccessRights) -> Result<(), super::MprotectError> {
+        // A tagged region may be re-tagged by another thread freeing its key; hold the
+        // registry so that re-tag cannot reapply the previous rights over these.
+        let _registry = self.tag.pkey().map(|_| registry::lock());
         let ret = unsafe {
             libc::mprotect(
                 self.ptr.as_ptr() as *mut libc::c_void,
@@ -191,6 +223,7 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
             let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
             return Err(super::MprotectError::MprotectFailed(err_no));
         }
+        self.tag.set_access_rights(access_rights);
         Ok(())
     }
 
@@ -221,6 +254,17 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
         self.len
     }
 
+    /// Returns `true` if the memory region has a length of zero bytes.
+    /// 
+    /// This is the case for zero-sized types `T`.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if the region is empty, `false` otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     /// Returns the protection key ID associated with the memory region, if any.
     /// 
     /// This method returns the ID of the protection key that has been associated with
@@ -232,7 +276,148 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
     /// - `Some(u32)`: The protection key ID if the region is associated with a pkey
     /// - `None`: If no protection key is associated with this region
     pub fn pkey(&self) -> Option<u32> {
-        self.pkey_id
+        self.tag.pkey()
+    }
+
+    /// Returns the page-level access rights last applied to the memory region.
+    /// 
+    /// This is the crate's own record of the rights set by `mprotect` or `pkey_mprotect`.
+    /// Use [`kernel_state()`](Self::kernel_state) or [`verify()`](Self::verify) to compare
+    /// it with what the kernel actually enforces.
+    /// 
+    /// # Returns
+    /// 
+    /// The tracked `AccessRights` of the region.
+    pub fn access_rights(&self) -> AccessRights {
+        self.tag.access_rights()
+    }
+
+    /// Returns the page-aligned address range `[start, end)` covered by the region.
+    pub(crate) fn page_range(&self) -> (usize, usize) {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        let start = self.ptr.as_ptr() as usize;
+        let end = start + self.len.max(1);
+        (start & !(page_size - 1), end.div_ceil(page_size) * page_size)
+    }
+
+    /// Reads the kernel's view of the memory region from `/proc/self/smaps`.
+    /// 
+    /// The returned state contains every kernel mapping that overlaps the region's
+    /// pages, with the page permissions and `ProtectionKey:` the kernel reports for them.
+    /// 
+    /// # Returns
+    /// 
+    /// - `Ok(KernelState)`: The mappings covering the region.
+    /// - `Err(MprotectError::SmapsReadFailed)`: If `/proc/self/smaps` cannot be read.
+    /// - `Err(MprotectError::RegionNotMapped)`: If no mapping overlaps the region.
+    /// 
+    /// # Example
+    /// 
+    /// ```no_run
+    /// # use mprotect_rs::{UnsafeProtectedRegion, AccessRights, allocator::Mmap};
+    /// let region = unsafe { UnsafeProtectedRegion::<Mmap, i32>::new(AccessRights::READ)? };
+    /// let state = region.kernel_state()?;
+    /// assert_eq!(state.access_rights(), AccessRights::READ);
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn kernel_state(&self) -> Result<KernelState, super::MprotectError> {
+        let (start, end) = self.page_range();
+        let mappings: Vec<_> = smaps::read_self()
+            .map_err(super::MprotectError::SmapsReadFailed)?
+            .into_iter()
+            .filter(|m| m.overlaps(start, end))
+            .collect();
+        if mappings.is_empty() {
+            return Err(super::MprotectError::RegionNotMapped);
+        }
+        Ok(KernelState { mappings })
+    }
+
+    /// Returns the access the calling thread effectively has to the memory region.
+    /// 
+    /// The page permissions reported by the kernel are combined with the current
+    /// thread's PKRU value for the protection key each mapping is tagged with.
+    /// A key with access disabled removes `READ` and `WRITE`; a key with write
+    /// disabled removes `WRITE`. Protection keys never restrict `EXEC`.
+    /// 
+    /// **Note**: PKRU is a thread-local register, so the result is only valid
+    /// for the calling thread.
+    /// 
+    /// # Returns
+    /// 
+    /// - `Ok(AccessRights)`: The rights common to every page of the region.
+    /// - `Err(MprotectError)`: If the kernel state cannot be read.
+    pub fn effective_access(&self) -> Result<AccessRights, super::MprotectError> {
+        let state = self.kernel_state()?;
+        // The kernel only reports `ProtectionKey:` when protection keys are
+        // enabled, so reading PKRU is safe whenever a key is present.
+        let pkru_value = state.mappings.iter()
+            .any(|m| m.protection_key.is_some())
+            .then(|| unsafe { pkru::rdpkru() });
+
+        Ok(state.mappings.iter().fold(AccessRights::READ_WRITE_EXEC, |acc, m| {
+            let mut rights = m.access_rights;
+            if let (Some(pkru_value), Some(key)) = (pkru_value, m.protection_key) {
+                let bits = pkru::key_bits(pkru_value, key);
+                if bits & 0b01 != 0 {
+                    rights = rights.minus(AccessRights::READ_WRITE);
+                } else if bits & 0b10 != 0 {
+                    rights = rights.minus(AccessRights::WRITE);
+                }
+            }
+            acc & rights
+        }))
+    }
+
+    /// Compares the crate's record of the region with what the kernel enforces.
+    /// 
+    /// Every mapping overlapping the region is checked against the tracked
+    /// [`access_rights()`](Self::access_rights) and [`pkey()`](Self::pkey).
+    /// An untracked key is expected to be the default key `0`. The key check is
+    /// skipped when the kernel does not report protection keys.
+    /// 
+    /// # Returns
+    /// 
+    /// - `Ok(Vec<ProtectionDrift>)`: One entry per mismatch; empty if the views agree.
+    /// - `Err(MprotectError)`: If the kernel state cannot be read.
+    /// 
+    /// # Example
+    /// 
+    /// ```no_run
+    /// # use mprotect_rs::{UnsafeProtectedRegion, AccessRights, allocator::Mmap};
+    /// let region = unsafe { UnsafeProtectedRegion::<Mmap, i32>::new(AccessRights::READ_WRITE)? };
+    /// for drift in region.verify()? {
+    ///     eprintln!("protection drift: {}", drift);
+    /// }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn verify(&self) -> Result<Vec<ProtectionDrift>, super::MprotectError> {
+        let expected_rights = self.tag.access_rights();
+        let expected_pkey = self.tag.pkey().unwrap_or(0);
+        let mut drifts = Vec::new();
+
+        for m in self.kernel_state()?.mappings {
+            if m.access_rights != expected_rights {
+                drifts.push(ProtectionDrift::AccessRights {
+                    start: m.start,
+                    end: m.end,
+                    expected: expected_rights,
+                    actual: m.access_rights,
+                });
+            }
+            if let Some(actual) = m.protection_key {
+                if actual != expected_pkey {
+                    drifts.push(ProtectionDrift::Pkey {
+                        start: m.start,
+                        end: m.end,
+                        expected: expected_pkey,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        Ok(drifts)
     }
 
     /// Returns a mutable reference to the data stored in the memory region.
@@ -256,8 +441,8 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
     /// # unsafe {
     /// let mut region = UnsafeProtectedRegion::<Mmap, i32>::new(AccessRights::READ_WRITE)?;
     /// *region.as_mut() = 42;
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
     pub unsafe fn as_mut(&mut self) -> &mut T {
         &mut *self.ptr.as_ptr()
@@ -284,12 +469,29 @@ impl<A: allocator::Allocator<T>, T> UnsafeProtectedRegion<A, T> {
     /// # unsafe {
     /// let region = UnsafeProtectedRegion::<Mmap, i32>::new(AccessRights::READ)?;
     /// let value = *region.as_ref();
-    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// # }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
     pub unsafe fn as_ref(&self) -> &T {
         &*self.ptr.as_ptr()
     }
+
+    /// Moves the value out of a region created with [`new_initialized()`](Self::new_initialized)
+    /// and frees the region.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(T)`: The value.
+    /// - `Err(MprotectError)`: If the region cannot be made readable. The value is
+    ///   dropped with the region.
+    pub(crate) fn into_inner(mut self) -> Result<T, super::MprotectError> {
+        debug_assert!(self.initialized);
+        unsafe {
+            self.set_access(AccessRights::READ)?;
+            self.initialized = false;
+            Ok(std::ptr::read(self.ptr.as_ptr()))
+        }
+    }
 }
 
 impl<A: allocator::Allocator<T>, T> Drop for UnsafeProtectedRegion<A, T> {
@@ -297,11 +499,15 @@ impl<A: allocator::Allocator<T>, T> Drop for UnsafeProtectedRegion<A, T> {
     /// 
     /// This destructor ensures proper cleanup by:
     /// - Calling the allocator's deallocation method
-    /// - Releasing the memory back to the system
+    /// - Releasing the memory back to the system, or handing it to the
+    ///   [`quarantine`](crate::quarantine) if it is enabled
     /// 
     /// **Warning**: If deallocation fails, this method will panic. Deallocation failures
     /// are rare but can occur due to memory corruption or invalid memory regions.
     fn drop(&mut self) {
+        // Forget the key association before unmapping, so a key released concurrently
+        // never re-tags an address range that may already belong to a new mapping.
+        registry::untrack(&self.tag);
         if self.initialized {
             let _ = unsafe { self.set_access(AccessRights::READ_WRITE) };
             unsafe {
@@ -309,9 +515,22 @@ impl<A: allocator::Allocator<T>, T> Drop for UnsafeProtectedRegion<A, T> {
             }
             self.initialized = false;
         }
-        let ret = unsafe { self.allocator.deallocate() };
+        let region = unsafe { ManuallyDrop::take(&mut self.allocator) };
+        let Err(region) = quarantine::admit(region) else { return };
+        let ret = unsafe { region.deallocate() };
         if let Err(e) = ret {
             panic!("Failed to deallocate memory: {:?}", e.to_string());
         }
     }
 }
+
+impl<A: allocator::Allocator<T>, T> sealed::Sealed for UnsafeProtectedRegion<A, T> {
+    /// [`crate::PKey::associate`] and [`crate::PKey::disassociate`] update the tag so that
+    /// [`pkey()`](UnsafeProtectedRegion::pkey) and [`access_rights()`](UnsafeProtectedRegion::access_rights)
+    /// reflect the key and page-level rights applied by `pkey_mprotect`.
+    fn tag(&self) -> &Arc<PkeyTag> {
+        &self.tag
+    }
+}
+
+impl<A: allocator::Allocator<T>, T> ProtectedMemory for UnsafeProtectedRegion<A, T> {}
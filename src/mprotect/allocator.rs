@@ -165,4 +165,13 @@ impl<A: Allocator<T>, T> MemoryRegion<A, T> {
     pub fn len(&self) -> usize {
         self.len
     }
+
+    /// Returns `true` if the allocated memory region has a size of zero bytes.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if the region is empty, `false` otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 }
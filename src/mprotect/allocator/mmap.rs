@@ -19,6 +19,58 @@ pub struct Mmap {
     size: usize,
 }
 
+impl Mmap {
+    /// Maps `len` bytes of anonymous memory with the protection flags `prot`.
+    ///
+    /// This is the untyped allocation path shared by [`Allocator::allocator_alloc`] and
+    /// allocators that work with raw sizes, such as [`crate::GuardedHeap`]. It never
+    /// allocates on the heap.
+    ///
+    /// # Safety
+    ///
+    /// The returned memory must be released with [`Mmap::unmap`].
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(*mut c_void)`: The page-aligned start of the mapping
+    /// - `Err(AllocatorError::MmapFailed)`: If the `mmap` system call fails
+    pub(crate) unsafe fn map(len: usize, prot: i32) -> Result<*mut libc::c_void, AllocatorError> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(super::AllocatorError::MmapFailed(err_no));
+        }
+        Ok(ptr)
+    }
+
+    /// Unmaps `len` bytes at `ptr`, previously mapped with [`Mmap::map`].
+    ///
+    /// # Safety
+    ///
+    /// The memory must not be accessed afterwards.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: On successful deallocation
+    /// - `Err(AllocatorError::MunmapFailed)`: If the `munmap` system call fails
+    pub(crate) unsafe fn unmap(ptr: *mut libc::c_void, len: usize) -> Result<(), AllocatorError> {
+        if unsafe { libc::munmap(ptr, len) } != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(super::AllocatorError::MunmapFailed(err_no));
+        }
+        Ok(())
+    }
+}
+
 impl<T> Allocator<T> for Mmap {
     /// Allocates memory using `mmap` with the specified protection flags.
     /// 
@@ -41,23 +93,8 @@ impl<T> Allocator<T> for Mmap {
         let page_size = unsafe {
             libc::sysconf(libc::_SC_PAGESIZE) as usize
         };
-        let alloc_size = ((std::mem::size_of::<T>() + page_size - 1) / page_size) * page_size;
-    
-        // Allocate anonymous memory
-        let ptr = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                alloc_size,
-                *access_rights,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-                -1,
-                0,
-            )
-        };
-        if ptr == libc::MAP_FAILED {
-            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
-            return Err(super::AllocatorError::MmapFailed(err_no));
-        }
+        let alloc_size = std::mem::size_of::<T>().div_ceil(page_size) * page_size;
+        let ptr = unsafe { Mmap::map(alloc_size, *access_rights)? };
         Ok(MemoryRegion { 
             ptr: NonNull::new(ptr as *mut T).ok_or(super::AllocatorError::MmapFailed(-1))?, 
             len: alloc_size, 
@@ -83,13 +120,6 @@ impl<T> Allocator<T> for Mmap {
             std::ptr::drop_in_place(self.ptr);
         }
         // unmap the memory
-        let ret = unsafe {
-            libc::munmap(self.ptr, self.size)
-        };
-        if ret != 0 {
-            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
-            return Err(super::AllocatorError::MunmapFailed(err_no));
-        }
-        Ok(())
+        unsafe { Mmap::unmap(self.ptr, self.size) }
     }
 }
@@ -0,0 +1,72 @@
+use crate::smaps::Mapping;
+use super::AccessRights;
+
+/// The kernel's view of a protected memory region.
+///
+/// Built from the `/proc/self/smaps` entries that overlap the region, so it
+/// reflects what is actually in force rather than what the crate has tracked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KernelState {
+    /// Kernel mappings overlapping the region, in address order.
+    pub mappings: Vec<Mapping>,
+}
+
+impl KernelState {
+    /// Returns the page-level permissions common to every mapping of the region.
+    ///
+    /// If the region spans several mappings with different permissions, only the
+    /// rights present in all of them are returned.
+    pub fn access_rights(&self) -> AccessRights {
+        self.mappings
+            .iter()
+            .fold(AccessRights::READ_WRITE_EXEC, |acc, m| acc & m.access_rights)
+    }
+
+    /// Returns the protection key the region's pages are tagged with.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(u32)`: If every mapping reports the same `ProtectionKey`.
+    /// - `None`: If the kernel does not report protection keys, or the mappings disagree.
+    pub fn pkey(&self) -> Option<u32> {
+        let first = self.mappings.first()?.protection_key?;
+        self.mappings
+            .iter()
+            .all(|m| m.protection_key == Some(first))
+            .then_some(first)
+    }
+}
+
+/// A mismatch between the rights the crate tracks for a region and the rights in force.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProtectionDrift {
+    /// A mapping of the region has different page permissions than tracked.
+    AccessRights {
+        start: usize,
+        end: usize,
+        expected: AccessRights,
+        actual: AccessRights,
+    },
+    /// A mapping of the region is tagged with a different protection key than tracked.
+    ///
+    /// An untracked key is reported as `0`, the default key.
+    Pkey {
+        start: usize,
+        end: usize,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl std::fmt::Display for ProtectionDrift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtectionDrift::AccessRights { start, end, expected, actual } => write!(
+                f, "{:#x}-{:#x}: access rights {:?} tracked, {:?} in force", start, end, expected, actual
+            ),
+            ProtectionDrift::Pkey { start, end, expected, actual } => write!(
+                f, "{:#x}-{:#x}: pkey {} tracked, pkey {} in force", start, end, expected, actual
+            ),
+        }
+    }
+}
@@ -4,21 +4,49 @@ use crate::RegionGuard;
 use crate::GuardRef;
 use crate::GuardRefMut;
 use crate::GuardError;
+use crate::TypedRegion;
 use crate::allocator;
+use crate::mprotect::access_rights::AccessRights;
+use crate::mprotect::access_rights::access_permissions::AccessPermission;
 
-use std::cell::Cell;
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
 
 mod access_rights;
 pub use access_rights::permissions as PkeyPermissions;
 pub use PkeyPermissions::{ ReadOnly, ReadWrite, NoAccess };
 
+mod revoke;
+pub use revoke::revocation_signal;
+
+mod typed;
+pub use typed::*;
+
 /// Represents possible errors when working with `PkeyGuard` and its regions.
 #[derive(Debug)]
 pub enum PkeyGuardError {
     MprotectError(super::MprotectError),
     RegionGuardError(GuardError),
     InvalidRegionError,
+    /// The guard was revoked with [`Revocation::Permanent`] and no longer grants access.
+    Revoked,
+}
+
+/// Scope of [`PkeyGuard::revoke_all_threads`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Revocation {
+    /// Denies the key in every thread running now.
+    ///
+    /// The guard keeps working: a scope that accesses its region afterwards re-applies
+    /// its own rights on that thread, and threads that use the guard for the first time
+    /// start from the guard's default rights.
+    Running,
+    /// Denies the key in every thread running now and makes `NoAccess` the guard's
+    /// rights from then on, including for threads created later.
+    ///
+    /// Scopes can no longer grant access; their accessors return [`PkeyGuardError::Revoked`].
+    Permanent,
 }
 
 /// Represents a memory region associated with a specific protection key (PKey)
@@ -40,17 +68,19 @@ pub enum PkeyGuardError {
 /// - `Rights`: Access-rights type implementing [`Access`].
 ///
 /// # Example
-/// ```rust
-/// let guard = PkeyGuard::<MyAllocator, u8>::new(ReadWrite)?;
-/// let mut region = RegionGuard::<MyAllocator, u8>::new(...)?;
+/// ```no_run
+/// # use mprotect_rs::{allocator, AccessPermissions, AssociatedRegion, PkeyGuard, RegionGuard, ReadOnly, ReadWrite};
+/// let guard = PkeyGuard::<allocator::Mmap, u8>::new(ReadWrite)?;
+/// let mut region = RegionGuard::<allocator::Mmap, u8>::new(0, AccessPermissions::ReadWrite)?;
 ///
 /// // Associate region with read-only access
 /// let associated = AssociatedRegion::<_, _, ReadOnly>::new(&mut region, &guard);
 ///
 /// // Obtain a read guard (allowed)
-/// let ref_guard = associated.ref_guard()?;
+/// let ref_guard = associated.ref_guard().unwrap();
 ///
 /// // Write access is prohibited here
+/// # Ok::<(), mprotect_rs::MprotectError>(())
 /// ```
 ///
 /// Upon drop, the original access rights from the `PkeyGuard` stack are restored.
@@ -59,9 +89,10 @@ where
     Rights: access_rights::Access,
 {
     region: *mut RegionGuard<A, T>,
-    pkey_guard: &'p PkeyGuard<A, T>,
+    pkey_guard: &'p GuardCore,
     access_rights: Rights,
-    popped: Cell<bool>,
+    /// Depth of the thread's permission stack below this scope's entry.
+    depth: usize,
 }
 
 impl<'p, A: allocator::Allocator<T>, T, Rights> AssociatedRegion<'p, A, T, Rights>
@@ -81,16 +112,21 @@ where
     ///
     /// # Returns
     /// A new [`AssociatedRegion`] representing the scoped association.
-    pub fn new(region: &mut RegionGuard<A, T>, pkey_guard: &'p PkeyGuard<A, T>) -> Self {
-        let access_rights = Rights::new();
-        // push new access rights to stack
-        pkey_guard.push_permissions(access_rights.value());
+    pub fn new(region: &'p mut RegionGuard<A, T>, pkey_guard: &'p PkeyGuard<A, T>) -> Self {
+        let core = &pkey_guard.core;
+        // push new access rights to stack; if writing them fails, the first access
+        // applies them instead
+        let depth = core.push_permissions(Rights::new().value()).unwrap_or_else(|_| core.depth());
+        Self::with_depth(region, core, depth)
+    }
 
+    /// Returns the scope for rights already pushed at `depth`.
+    fn with_depth(region: &'p mut RegionGuard<A, T>, pkey_guard: &'p GuardCore, depth: usize) -> Self {
         AssociatedRegion {
             region,
             pkey_guard,
-            access_rights,
-            popped: Cell::new(false),
+            access_rights: Rights::new(),
+            depth,
         }
     }
 
@@ -102,60 +138,148 @@ where
     /// # Errors
     /// Returns [`MprotectError`] if the hardware update fails.
     fn sync_pkey_permissions(&self) -> Result<(), super::MprotectError> {
-        if self.pkey_guard.current_access_rights.get() == self.access_rights.value() {
-            return Ok(());
-        }
-        unsafe {
-            self.pkey_guard.pkey.set_access_rights(self.access_rights.value().pkey_rights)?;
-        }
-        self.pkey_guard.current_access_rights.set(self.access_rights.value());
-        Ok(())
+        self.pkey_guard.sync_rights(self.access_rights.value())
     }
 
     /// Returns a read-only guard for the associated memory region.
     ///
+    /// The guard borrows this scope, so it cannot outlive the scope or its rights.
+    ///
     /// # Constraints
     /// This method is only available if the region's `Rights`
     /// type implements [`CanRead`].
     ///
     /// # Errors
     /// - [`PkeyGuardError::InvalidRegionError`]: If the region pointer is null.
+    /// - [`PkeyGuardError::Revoked`]: If the guard was permanently revoked.
     /// - [`PkeyGuardError::MprotectError`]: If permission synchronization fails.
-    /// - [`PkeyGuardError::RegionGuardError`]: If the underlying region read fails.
-    pub fn ref_guard(&self) -> Result<GuardRef<'p, A, T>, PkeyGuardError>
+    /// - [`PkeyGuardError::RegionGuardError`]: If the region's page rights do not allow
+    ///   reading ([`GuardError::InvalidAccessRights`]) or the underlying region read fails.
+    pub fn ref_guard(&self) -> Result<GuardRef<'_, A, T>, PkeyGuardError>
     where 
         Rights: access_rights::CanRead,
     {
         if self.region.is_null() {
             return Err(PkeyGuardError::InvalidRegionError);
         }
+        if self.pkey_guard.is_revoked() {
+            return Err(PkeyGuardError::Revoked);
+        }
         
+        // The page rights are the region's own; a pkey scope does not widen them.
+        if unsafe { !(*self.region).access_rights().has(AccessRights::READ) } {
+            return Err(PkeyGuardError::RegionGuardError(GuardError::InvalidAccessRights));
+        }
+
         self.sync_pkey_permissions().map_err(PkeyGuardError::MprotectError)?;
         unsafe { (*self.region).read().map_err(PkeyGuardError::RegionGuardError) }
     }
 
     /// Returns a mutable guard for the associated memory region.
     ///
+    /// The guard borrows this scope, so it cannot outlive the scope or its rights.
+    ///
     /// # Constraints
     /// This method is only available if the region’s `Rights`
     /// type implements [`CanWrite`].
     ///
     /// # Errors
     /// - [`PkeyGuardError::InvalidRegionError`]: If the region pointer is null.
+    /// - [`PkeyGuardError::Revoked`]: If the guard was permanently revoked.
     /// - [`PkeyGuardError::MprotectError`]: If permission synchronization fails.
-    /// - [`PkeyGuardError::RegionGuardError`]: If the underlying region write fails.
-    pub fn mut_ref_guard(&self) -> Result<GuardRefMut<'p, A, T>, PkeyGuardError>
+    /// - [`PkeyGuardError::RegionGuardError`]: If the region's page rights do not allow
+    ///   writing ([`GuardError::InvalidAccessRights`]) or the underlying region write fails.
+    pub fn mut_ref_guard(&self) -> Result<GuardRefMut<'_, A, T>, PkeyGuardError>
     where
         Rights: access_rights::CanWrite,
     {
         if self.region.is_null() {
             return Err(PkeyGuardError::InvalidRegionError);
         }
+        if self.pkey_guard.is_revoked() {
+            return Err(PkeyGuardError::Revoked);
+        }
+
+        // The page rights are the region's own; a pkey scope does not widen them.
+        if unsafe { !(*self.region).access_rights().has(AccessRights::WRITE) } {
+            return Err(PkeyGuardError::RegionGuardError(GuardError::InvalidAccessRights));
+        }
 
         self.sync_pkey_permissions().map_err(PkeyGuardError::MprotectError)?;
         unsafe { (*self.region).write().map_err(PkeyGuardError::RegionGuardError) }
     }
 }
+impl<'p, A: allocator::Allocator<T>, T, Rights> AssociatedRegion<'p, A, T, Rights>
+where
+    Rights: access_rights::Access,
+{
+    /// Changes this scope's rights to `NewRights` in place.
+    ///
+    /// The scope keeps its entry on the permission stack, so the PKRU is written once
+    /// instead of once to leave the scope and once more to enter a new one.
+    fn into_rights<NewRights: access_rights::Access>(self) -> AssociatedRegion<'p, A, T, NewRights> {
+        let this = std::mem::ManuallyDrop::new(self);
+        let access_rights = NewRights::new();
+        this.pkey_guard.replace_permissions(this.depth, access_rights.value());
+        AssociatedRegion {
+            region: this.region,
+            pkey_guard: this.pkey_guard,
+            access_rights,
+            depth: this.depth,
+        }
+    }
+
+    /// Turns a writable scope into a read-only one with a single PKRU write.
+    ///
+    /// Guards borrow the scope they were obtained from, so they must be dropped
+    /// before the scope's rights can change.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, RegionGuard, NoAccess, ReadWrite};
+    /// # let guard = PkeyGuard::<allocator::Mmap, u8>::new(NoAccess)?;
+    /// # let mut region = RegionGuard::<allocator::Mmap, u8>::new(0, AccessPermissions::ReadWrite)?;
+    /// let mut handler = guard.associate::<NoAccess>(&mut region)?;
+    /// let rw = handler.set_access_rights::<ReadWrite>()?;
+    /// *rw.mut_ref_guard().unwrap() = 1;
+    ///
+    /// let ro = rw.downgrade();
+    /// assert_eq!(*ro.ref_guard().unwrap(), 1);
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    ///
+    /// A write guard cannot outlive the writable scope:
+    ///
+    /// ```compile_fail
+    /// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, RegionGuard, NoAccess, ReadWrite};
+    /// # let guard = PkeyGuard::<allocator::Mmap, u8>::new(NoAccess)?;
+    /// # let mut region = RegionGuard::<allocator::Mmap, u8>::new(0, AccessPermissions::ReadWrite)?;
+    /// # let mut handler = guard.associate::<NoAccess>(&mut region)?;
+    /// let rw = handler.set_access_rights::<ReadWrite>()?;
+    /// let mut value = rw.mut_ref_guard().unwrap();
+    /// let ro = rw.downgrade();
+    /// *value = 2;
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn downgrade(self) -> AssociatedRegion<'p, A, T, ReadOnly>
+    where
+        Rights: access_rights::CanWrite,
+    {
+        self.into_rights()
+    }
+
+    /// Turns a read-only scope into a writable one with a single PKRU write.
+    ///
+    /// Write guards are still subject to the region's own checks; see
+    /// [`RegionGuard::upgrade`] to turn a read guard into a write guard.
+    pub fn upgrade(self) -> AssociatedRegion<'p, A, T, ReadWrite>
+    where
+        Rights: access_rights::CanRead,
+    {
+        self.into_rights()
+    }
+}
+
 impl<'p, A: allocator::Allocator<T>, T, Rights> Drop for AssociatedRegion<'p, A, T, Rights>
 where
     Rights: access_rights::Access,
@@ -163,15 +287,10 @@ where
     /// Restores the previous PKey access rights when the region goes out of scope.
     ///
     /// This ensures that any temporary permission changes made by the region
-    /// are reverted upon drop.  
-    /// The `popped` flag prevents multiple pops for nested scopes.
+    /// are reverted upon drop, including during unwinding. Entries pushed by inner
+    /// scopes that were leaked (e.g. with `mem::forget`) are discarded as well.
     fn drop(&mut self) {
-        if !self.popped.get() {
-            // pop current access rights from stack
-            self.pkey_guard.pop_permissions();
-            self.popped.set(false);
-        }
-        //println!("Dropped AssociatedRegion, reset PKey access rights to {:?}", self.pkey_guard.current_access_rights.get());
+        self.pkey_guard.pop_permissions(self.depth);
     }
 }
 
@@ -198,12 +317,16 @@ where
 /// - `Rights`: Current access-rights type bound by [`Access`] trait.
 ///
 /// # Example
-/// ```rust
+/// ```no_run
+/// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, RegionGuard, ReadOnly, ReadWrite};
+/// # let guard = PkeyGuard::<allocator::Mmap, u8>::new(ReadWrite)?;
+/// # let mut region = RegionGuard::<allocator::Mmap, u8>::new(0, AccessPermissions::ReadWrite)?;
 /// // Create a new region associated with a PKey guard
-/// let handler = guard.associate::<ReadWrite>(&mut region)?;
+/// let mut handler = guard.associate::<ReadWrite>(&mut region)?;
 ///
 /// // Temporarily change access rights to read-only
 /// let readonly_region = handler.set_access_rights::<ReadOnly>()?;
+/// # Ok::<(), mprotect_rs::MprotectError>(())
 /// ```
 ///
 /// After leaving scope, the original `ReadWrite` rights are restored automatically.
@@ -212,7 +335,7 @@ where
     Rights: access_rights::Access,
 {
     associated_region: AssociatedRegion<'p, A, T, Rights>,
-    pkey_guard: &'p PkeyGuard<A, T>,
+    pkey_guard: &'p GuardCore,
 }
 
 impl<'a, 'p, A: allocator::Allocator<T>, T, Rights> AssociatedRegionHandler<'p, A, T, Rights>
@@ -230,19 +353,28 @@ where
     ///
     /// # Returns
     /// A new handler that controls the lifetime and permissions of the association.
-    pub fn new(region: &mut RegionGuard<A, T>, pkey_guard: &'p PkeyGuard<A, T>) -> Self {
+    pub fn new(region: &'p mut RegionGuard<A, T>, pkey_guard: &'p PkeyGuard<A, T>) -> Self {
         AssociatedRegionHandler {
             associated_region: AssociatedRegion::new(region, pkey_guard),
-            pkey_guard,
+            pkey_guard: &pkey_guard.core,
         }
     }
 
+    /// Pushes `Rights` with a single PKRU write and returns the handler.
+    fn with_core(region: &'p mut RegionGuard<A, T>, pkey_guard: &'p GuardCore) -> Result<Self, super::MprotectError> {
+        let depth = pkey_guard.push_permissions(Rights::new().value())?;
+        Ok(AssociatedRegionHandler {
+            associated_region: AssociatedRegion::with_depth(region, pkey_guard, depth),
+            pkey_guard,
+        })
+    }
+
     /// Dynamically changes the access rights of the associated region.
     ///
     /// This method performs the following steps:
-    /// 1. Calls `wrpkru` to update the hardware protection key to the new rights.
-    /// 2. Pushes the new rights onto the `PkeyGuard`'s permission stack.
-    /// 3. Returns a new [`AssociatedRegion`] that reflects the updated state.
+    /// 1. Pushes the new rights onto the `PkeyGuard`'s permission stack and writes
+    ///    them to the hardware protection key with a single `wrpkru`.
+    /// 2. Returns a new [`AssociatedRegion`] that reflects the updated state.
     ///
     /// # Type Parameters
     /// - `NewRights`: The new access-rights type (e.g., `ReadOnly`, `ReadWrite`, `NoAccess`).
@@ -256,31 +388,28 @@ where
     /// Incorrect usage or mismatched region states may cause access violations.
     ///
     /// # Example
-    /// ```rust
-    /// let mut handler = PkeyGuard::associate::<ReadWrite>(&mut region)?;
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, RegionGuard, ReadOnly, ReadWrite};
+    /// # let guard = PkeyGuard::<allocator::Mmap, u8>::new(ReadWrite)?;
+    /// # let mut region = RegionGuard::<allocator::Mmap, u8>::new(0, AccessPermissions::ReadWrite)?;
+    /// let mut handler = guard.associate::<ReadWrite>(&mut region)?;
     /// let readonly = handler.set_access_rights::<ReadOnly>()?;
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
     /// ```
-    pub fn set_access_rights<NewRights: access_rights::Access>(&'a mut self) -> Result<AssociatedRegion<'a, A, T, NewRights>, super::MprotectError> 
+    pub fn set_access_rights<NewRights>(&'a mut self) -> Result<AssociatedRegion<'a, A, T, NewRights>, super::MprotectError> 
     where
         NewRights: access_rights::Access,
     {
-        // Apply new hardware access rights via PKRU
-        unsafe {
-            self.pkey_guard.pkey.set_access_rights(NewRights::new().value().pkey_rights)?;
-        }
-        println!("New PKey access rights set to {:?}", NewRights::new().value());
-
-        // Mark current region as popped so previous permissions are not restored twice
-        self.associated_region.popped.set(true);
-        // Push the new permission state
-        self.pkey_guard.push_permissions(NewRights::new().value());
+        // Push and apply the new permission state. The returned scope borrows this
+        // handler, so it is dropped first and restores the handler's rights.
+        let depth = self.pkey_guard.push_permissions(NewRights::new().value())?;
 
         // Return a new associated region scoped to the new rights
         Ok(AssociatedRegion {
             region: self.associated_region.region,
             pkey_guard: self.pkey_guard,
             access_rights: NewRights::new(),
-            popped: Cell::new(false),
+            depth,
         })
     }
 }
@@ -306,20 +435,102 @@ where
 /// use `PkeyGuard` internally to push and pop access rights safely.
 ///
 /// # Example
-/// ```rust
-/// let guard = PkeyGuard::<MyAllocator, u8>::new(ReadWrite)?;
-/// let mut region = RegionGuard::<MyAllocator, u8>::new(...)?;
+/// ```no_run
+/// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, RegionGuard, ReadOnly, ReadWrite};
+/// let guard = PkeyGuard::<allocator::Mmap, u8>::new(ReadWrite)?;
+/// let mut region = RegionGuard::<allocator::Mmap, u8>::new(0, AccessPermissions::ReadWrite)?;
 ///
 /// // Associate region and access with `ReadOnly` rights
 /// let handler = guard.associate::<ReadOnly>(&mut region)?;
+/// # Ok::<(), mprotect_rs::MprotectError>(())
 /// ```
 ///
 /// After leaving the region’s scope, previous access rights are automatically restored.
+///
+/// # Threads
+///
+/// PKRU is a per-thread register, so the permission stack is kept per thread as well,
+/// in thread-local storage keyed by the protection key. A `PkeyGuard` is `Sync`: scopes
+/// opened through it on one thread never change the rights another thread sees. A thread
+/// that uses the guard for the first time starts from the guard's default rights.
+///
+/// # Panics and unwinding
+///
+/// Scopes restore rights in `Drop`, which also runs while a panic unwinds, and restoring
+/// never panics itself. After a panic unwinds out of one or more scopes, whether or not it
+/// is caught with [`std::panic::catch_unwind`], the thread's rights for the key are those of
+/// the innermost scope that is still alive, or the guard's default rights if none is.
+/// A scope that is dropped out of order or leaked discards every scope opened after it.
 pub struct PkeyGuard<A, T> {
+    core: GuardCore,
+    _marker: std::marker::PhantomData<fn() -> (A, T)>,
+}
+
+/// A [`PkeyGuard`] that is not tied to one allocator and element type.
+///
+/// A `PkeyGuard<A, T>` can only associate `RegionGuard<A, T>`s, so a subsystem whose
+/// state is spread over several types would need one key per type. `ErasedPkeyGuard`
+/// accepts a region of any type in [`associate()`](Self::associate) and
+/// [`associate_typed()`](Self::associate_typed), and still returns handles typed after
+/// the region, so a single hardware key can protect all of that state.
+///
+/// It behaves like a `PkeyGuard` in every other respect: see [`PkeyGuard`] for the
+/// per-thread permission stack, revocation, and unwinding.
+///
+/// # Example
+/// ```no_run
+/// # use std::collections::HashMap;
+/// # use mprotect_rs::{allocator, AccessPermissions, ErasedPkeyGuard, RegionGuard, NoAccess, ReadWrite};
+/// let guard = ErasedPkeyGuard::new(NoAccess)?;
+/// let mut limits = RegionGuard::<allocator::Mmap, u64>::new(16, AccessPermissions::ReadWrite)?;
+/// let mut buffer = RegionGuard::<allocator::Mmap, [u8; 4096]>::new([0; 4096], AccessPermissions::ReadWrite)?;
+/// let mut table = RegionGuard::<allocator::Mmap, HashMap<u32, u32>>::new(HashMap::new(), AccessPermissions::ReadWrite)?;
+///
+/// let mut limits = guard.associate::<_, _, NoAccess>(&mut limits)?;
+/// let mut buffer = guard.associate::<_, _, NoAccess>(&mut buffer)?;
+/// let mut table = guard.associate::<_, _, NoAccess>(&mut table)?;
+/// {
+///     let limits = limits.set_access_rights::<ReadWrite>()?;
+///     *limits.mut_ref_guard().unwrap() += 1;
+/// }
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct ErasedPkeyGuard {
+    core: GuardCore,
+}
+
+/// Key and permission state shared by [`PkeyGuard`] and [`ErasedPkeyGuard`].
+///
+/// Scopes borrow this rather than the guard, so they do not depend on the guard's
+/// type parameters.
+struct GuardCore {
     pkey: PKey,
-    current_access_rights: Cell<RegionAccessRights>,
-    permissions_stack: RefCell<Vec<RegionAccessRights>>,
-    _marker: std::marker::PhantomData<(A, T)>,
+    id: u64,
+    default_access_rights: RegionAccessRights,
+    /// Number of revocations so far; thread state from an older epoch is re-applied.
+    epoch: AtomicU64,
+    /// Set by [`Revocation::Permanent`].
+    revoked: AtomicBool,
+}
+
+/// Source of [`PkeyGuard`] ids, which tell a reused key number apart from its previous owner.
+static NEXT_GUARD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// One thread's view of a [`PkeyGuard`]'s key.
+struct ThreadPermissions {
+    /// Id of the guard this state belongs to.
+    guard_id: u64,
+    /// Guard epoch in which `current` was last written.
+    epoch: u64,
+    /// Rights last written to this thread's PKRU, or `None` if not written yet.
+    current: Option<RegionAccessRights>,
+    /// Nested permission changes, with the guard's default rights at the bottom.
+    stack: Vec<RegionAccessRights>,
+}
+
+thread_local! {
+    /// Per-thread permission state of every live `PkeyGuard`, keyed by protection key.
+    static THREAD_PERMISSIONS: RefCell<HashMap<u32, ThreadPermissions>> = RefCell::new(HashMap::new());
 }
 
 impl<A, T> PkeyGuard<A, T> {
@@ -334,73 +545,47 @@ impl<A, T> PkeyGuard<A, T> {
     /// # Behavior
     /// - Allocates a new protection key using the underlying OS API (`pkey_alloc`).
     /// - Sets the key’s access rights to the provided default value.
-    /// - Initializes an internal stack (`permissions_stack`) to manage nested access-right changes.
+    /// - Each thread keeps its own stack of nested access-right changes, which starts
+    ///   with the default rights the first time the thread uses the guard.
     ///
     /// This stack allows temporarily changing access rights (e.g., to `ReadOnly`) and safely
     /// restoring the previous permissions when leaving a scoped region.
     pub fn new<Access: access_rights::Access>(default_access_rights: Access) -> Result<Self, super::MprotectError> {
-        let pkey = unsafe {
-            PKey::new(default_access_rights.value().pkey_rights)?
-        };
-        Ok(
-            PkeyGuard {
-                pkey,
-                // Track the current access rights applied to the key.
-                current_access_rights: Cell::new(default_access_rights.value()),
-                // Initialize the permission stack with the default rights.
-                permissions_stack: RefCell::new(vec![default_access_rights.value()]),
-                _marker: std::marker::PhantomData,
-            }
-        )
+        Ok(PkeyGuard { core: GuardCore::new(default_access_rights.value())?, _marker: std::marker::PhantomData })
     }
 
-    /// Pops (removes) the top access rights from the permission stack,
-    /// restoring the previous access state if available.
+    /// Denies this guard's key in every thread of the process.
     ///
-    /// # Behavior
-    /// - Pops the last entry from the permission stack.
-    /// - If another permission remains on the stack, the system’s pkey access
-    ///   rights are reset to that previous state.
-    ///
-    /// This method is typically called automatically by `Drop` implementations
-    /// when an associated region or handler goes out of scope.
-    fn pop_permissions(&self) -> Option<RegionAccessRights> {
-        let popped = self.permissions_stack.borrow_mut().pop();
-
-        //println!("[popped permissions: {:?}]", popped);
-
-        if let Some(top) = self.permissions_stack.borrow().last() {
-            let top = *top;
-            //println!("[Set pkey access rights from {:?} to {:?}]", self.current_access_rights.get(), top);
-            unsafe {
-                self.pkey.set_access_rights(top.pkey_rights).expect("Failed to set pkey access rights");
-            }
-            self.current_access_rights.set(top);
-        }
-
-        popped
-    }
-
-    /// Pushes a new access-right value onto the stack and applies it immediately.
+    /// PKRU is per-thread, so [`PKey::set_access_rights`] only affects the calling thread.
+    /// This method updates the calling thread directly, then interrupts every other
+    /// thread listed in `/proc/self/task` with [`revocation_signal()`] and waits until
+    /// each has rewritten the PKRU it resumes with. When it returns, no thread of the
+    /// process can access memory tagged with the key until rights are granted again.
     ///
-    /// # Parameters
-    /// - `rights`: The new access rights to apply (e.g., `ReadOnly` or `ReadWrite`).
+    /// # Arguments
+    /// - `scope`: Whether the guard may grant access again afterwards (see [`Revocation`]).
     ///
-    /// # Behavior
-    /// - The rights are pushed onto an internal stack for tracking nested changes.
-    /// - The hardware key is updated to reflect this new access state.
+    /// # Returns
+    /// - `Ok(usize)`: The number of other threads that acknowledged the revocation.
+    /// - `Err(MprotectError)`: If the threads cannot be listed or signalled, or some of
+    ///   them did not acknowledge within a second. The threads that did respond, and
+    ///   the calling thread, are revoked regardless.
     ///
-    /// This mechanism allows nested permission changes to safely revert once
-    /// a scope (e.g., `AssociatedRegion`) exits.
-    fn push_permissions(&self, rights: RegionAccessRights) {
-        self.permissions_stack.borrow_mut().push(rights);
+    /// # Example
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, PkeyGuard, ReadWrite, Revocation};
+    /// let guard = PkeyGuard::<allocator::Mmap, u8>::new(ReadWrite)?;
+    /// // ... on intrusion detection:
+    /// guard.revoke_all_threads(Revocation::Permanent)?;
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn revoke_all_threads(&self, scope: Revocation) -> Result<usize, super::MprotectError> {
+        self.core.revoke_all_threads(scope)
+    }
 
-        //println!("[pushed permissions: {:?}]", rights);
-        //println!("[Set pkey access rights from {:?} to {:?}]", self.current_access_rights.get(), rights);
-        unsafe {
-            self.pkey.set_access_rights(rights.pkey_rights).expect("Failed to set pkey access rights");
-        }
-        self.current_access_rights.set(rights);
+    /// Returns `true` if the guard was revoked with [`Revocation::Permanent`].
+    pub fn is_revoked(&self) -> bool {
+        self.core.is_revoked()
     }
 
     /// Returns a reference to the underlying `PKey` instance.
@@ -409,7 +594,7 @@ impl<A, T> PkeyGuard<A, T> {
     /// This function exposes the raw handle for advanced use cases such as
     /// associating multiple memory regions with the same pkey.
     pub fn pkey(&self) -> &PKey {
-        &self.pkey
+        &self.core.pkey
     }
 
     /// Associates this protection key with a given memory region.
@@ -427,15 +612,288 @@ impl<A, T> PkeyGuard<A, T> {
     /// # Safety
     /// - Calls into low-level `pkey_mprotect` to bind a protection key to the given memory region.
     /// - Updates the hardware key’s access rights to match the `Rights` type parameter.
-    pub fn associate<Rights>(&self, region: &mut RegionGuard<A, T>) -> Result<AssociatedRegionHandler<'_, A, T, Rights>, super::MprotectError>
+    /// - The handler borrows `region`, so the region cannot be dropped while it is alive.
+    pub fn associate<'a, Rights>(&'a self, region: &'a mut RegionGuard<A, T>) -> Result<AssociatedRegionHandler<'a, A, T, Rights>, super::MprotectError>
     where
         A: allocator::Allocator<T>,
         Rights: access_rights::Access,
+    {
+        self.core.associate(region)
+    }
+
+    /// Associates this protection key with a [`TypedRegion`] and opens a scope with
+    /// the pkey rights `Rights`.
+    ///
+    /// The region keeps its page-level rights `P`. The returned scope only offers the
+    /// accesses allowed by both layers, as computed by [`EffectiveRights<Rights, P>`](PkeyPermissions::EffectiveRights).
+    ///
+    /// # Type Parameters
+    /// - `Rights`: The pkey access rights of the scope.
+    /// - `P`: The page-level rights of the region.
+    ///
+    /// # Returns
+    /// - `Ok(TypedAssociatedRegion)`: The scope over the region.
+    /// - `Err(MprotectError)`: If `pkey_mprotect` or the hardware update fails.
+    pub fn associate_typed<'a, Rights, P>(&'a self, region: &'a mut TypedRegion<A, T, P>) -> Result<TypedAssociatedRegion<'a, A, T, Rights, P>, super::MprotectError>
+    where
+        A: allocator::Allocator<T>,
+        Rights: access_rights::Access,
+        P: AccessPermission,
+    {
+        self.core.associate_typed(region)
+    }
+}
+
+impl ErasedPkeyGuard {
+    /// Creates a new `ErasedPkeyGuard` with the given default access rights.
+    /// See [`PkeyGuard::new()`].
+    pub fn new<Access: access_rights::Access>(default_access_rights: Access) -> Result<Self, super::MprotectError> {
+        Ok(ErasedPkeyGuard { core: GuardCore::new(default_access_rights.value())? })
+    }
+
+    /// Denies this guard's key in every thread of the process.
+    /// See [`PkeyGuard::revoke_all_threads()`].
+    pub fn revoke_all_threads(&self, scope: Revocation) -> Result<usize, super::MprotectError> {
+        self.core.revoke_all_threads(scope)
+    }
+
+    /// Returns `true` if the guard was revoked with [`Revocation::Permanent`].
+    pub fn is_revoked(&self) -> bool {
+        self.core.is_revoked()
+    }
+
+    /// Returns a reference to the underlying `PKey` instance.
+    pub fn pkey(&self) -> &PKey {
+        &self.core.pkey
+    }
+
+    /// Associates this protection key with a memory region of any type.
+    ///
+    /// # Type Parameters
+    /// - `A`, `T`: The region's allocator and element type.
+    /// - `Rights`: The desired initial access rights for this region.
+    ///
+    /// # Returns
+    /// - `Ok(AssociatedRegionHandler)`: A handler typed after the region, which borrows
+    ///   the region for as long as it is alive.
+    /// - `Err(MprotectError)`: If `pkey_mprotect` or the hardware update fails.
+    ///
+    /// The region cannot be dropped while the handler is alive:
+    ///
+    /// ```compile_fail
+    /// # use mprotect_rs::{allocator, AccessPermissions, ErasedPkeyGuard, RegionGuard, NoAccess, ReadOnly};
+    /// let guard = ErasedPkeyGuard::new(NoAccess)?;
+    /// let mut region = RegionGuard::<allocator::Mmap, u64>::new(0, AccessPermissions::ReadWrite)?;
+    /// let mut handler = guard.associate::<_, _, NoAccess>(&mut region)?;
+    /// drop(region);
+    /// let scope = handler.set_access_rights::<ReadOnly>()?;
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn associate<'a, A, T, Rights>(&'a self, region: &'a mut RegionGuard<A, T>) -> Result<AssociatedRegionHandler<'a, A, T, Rights>, super::MprotectError>
+    where
+        A: allocator::Allocator<T>,
+        Rights: access_rights::Access,
+    {
+        self.core.associate(region)
+    }
+
+    /// Associates this protection key with a [`TypedRegion`] of any type and opens a
+    /// scope with the pkey rights `Rights`. See [`PkeyGuard::associate_typed()`].
+    pub fn associate_typed<'a, A, T, Rights, P>(&'a self, region: &'a mut TypedRegion<A, T, P>) -> Result<TypedAssociatedRegion<'a, A, T, Rights, P>, super::MprotectError>
+    where
+        A: allocator::Allocator<T>,
+        Rights: access_rights::Access,
+        P: AccessPermission,
+    {
+        self.core.associate_typed(region)
+    }
+}
+
+impl GuardCore {
+    fn new(default_access_rights: RegionAccessRights) -> Result<Self, super::MprotectError> {
+        let pkey = unsafe {
+            PKey::new(default_access_rights.pkey_rights)?
+        };
+        Ok(
+            GuardCore {
+                pkey,
+                id: NEXT_GUARD_ID.fetch_add(1, Ordering::Relaxed),
+                default_access_rights,
+                epoch: AtomicU64::new(0),
+                revoked: AtomicBool::new(false),
+            }
+        )
+    }
+
+    /// Runs `f` on the calling thread's permission state for this guard.
+    ///
+    /// The state is created on first use, or replaced if it was left behind by an
+    /// earlier guard that owned the same key number. Returns `None` only while the
+    /// thread's local storage is being torn down.
+    fn with_thread_permissions<R>(&self, f: impl FnOnce(&mut ThreadPermissions) -> R) -> Option<R> {
+        THREAD_PERMISSIONS.try_with(|states| {
+            let mut states = states.borrow_mut();
+            let state = states.entry(self.pkey.key()).or_insert_with(|| self.initial_thread_permissions());
+            if state.guard_id != self.id {
+                *state = self.initial_thread_permissions();
+            }
+            // A revocation since the last write means PKRU no longer matches `current`.
+            let epoch = self.epoch.load(Ordering::Acquire);
+            if state.epoch != epoch {
+                state.epoch = epoch;
+                state.current = None;
+            }
+            f(state)
+        }).ok()
+    }
+
+    fn initial_thread_permissions(&self) -> ThreadPermissions {
+        ThreadPermissions {
+            guard_id: self.id,
+            epoch: self.epoch.load(Ordering::Acquire),
+            current: None,
+            stack: vec![self.default_access_rights],
+        }
+    }
+
+    /// Removes the permission-stack entries at and above `depth`,
+    /// restoring the access state below them.
+    ///
+    /// # Behavior
+    /// - Truncates the calling thread's stack to `depth` entries, never below the
+    ///   guard's default rights.
+    /// - Resets the system’s pkey access rights to the new top of the stack.
+    ///
+    /// This method is called by `Drop` implementations when an associated region
+    /// or handler goes out of scope, including during unwinding, so it never panics.
+    fn pop_permissions(&self, depth: usize) {
+        self.with_thread_permissions(|state| {
+            state.stack.truncate(depth.max(1));
+            let top = state.stack.last().copied().unwrap_or(self.default_access_rights);
+            // On failure the rights are unknown; the next access re-applies its own.
+            state.current = self.apply_rights(top).ok().map(|()| top);
+        });
+    }
+
+    /// Replaces the permission-stack entry at `depth` with `rights` and applies them,
+    /// with a single PKRU write.
+    ///
+    /// Entries above `depth`, left by leaked inner scopes, are discarded as in `pop_permissions`.
+    fn replace_permissions(&self, depth: usize, rights: RegionAccessRights) {
+        self.with_thread_permissions(|state| {
+            state.stack.truncate(depth + 1);
+            if let Some(entry) = state.stack.get_mut(depth) {
+                *entry = rights;
+            }
+            let top = state.stack.last().copied().unwrap_or(self.default_access_rights);
+            // On failure the rights are unknown; the next access re-applies its own.
+            state.current = self.apply_rights(top).ok().map(|()| top);
+        });
+    }
+
+    /// Pushes a new access-right value onto the stack and applies it immediately.
+    ///
+    /// # Parameters
+    /// - `rights`: The new access rights to apply (e.g., `ReadOnly` or `ReadWrite`).
+    ///
+    /// # Behavior
+    /// - The rights are pushed onto an internal stack for tracking nested changes.
+    /// - The hardware key is updated to reflect this new access state.
+    ///
+    /// This mechanism allows nested permission changes to safely revert once
+    /// a scope (e.g., `AssociatedRegion`) exits.
+    ///
+    /// # Returns
+    /// - `Ok(usize)`: The depth of the stack below the new entry, to be passed to `pop_permissions`.
+    /// - `Err(MprotectError)`: If the hardware update fails. Nothing is pushed.
+    fn push_permissions(&self, rights: RegionAccessRights) -> Result<usize, super::MprotectError> {
+        self.with_thread_permissions(|state| {
+            let depth = state.stack.len();
+            if let Err(err) = self.apply_rights(rights) {
+                // The rights are unknown; the next access re-applies its own.
+                state.current = None;
+                return Err(err);
+            }
+            state.stack.push(rights);
+            state.current = Some(rights);
+            Ok(depth)
+        }).unwrap_or(Ok(1))
+    }
+
+    /// Returns the depth of the calling thread's permission stack.
+    fn depth(&self) -> usize {
+        self.with_thread_permissions(|state| state.stack.len()).unwrap_or(1)
+    }
+
+    /// Ensures the calling thread's PKRU matches `rights`, writing it only if the
+    /// last rights written on this thread differ.
+    fn sync_rights(&self, rights: RegionAccessRights) -> Result<(), super::MprotectError> {
+        self.with_thread_permissions(|state| {
+            if state.current == Some(rights) {
+                return Ok(());
+            }
+            self.apply_rights(rights)?;
+            state.current = Some(rights);
+            Ok(())
+        }).unwrap_or(Ok(()))
+    }
+
+    /// Writes `rights` to the calling thread's PKRU, or denies the key if the guard
+    /// was permanently revoked.
+    fn apply_rights(&self, rights: RegionAccessRights) -> Result<(), super::MprotectError> {
+        let pkey_rights = if self.is_revoked() { PkeyAccessRights::DisableAccess } else { rights.pkey_rights };
+        unsafe { self.pkey.set_access_rights(pkey_rights) }
+    }
+
+    fn revoke_all_threads(&self, scope: Revocation) -> Result<usize, super::MprotectError> {
+        if scope == Revocation::Permanent {
+            self.revoked.store(true, Ordering::Release);
+        }
+        let result = unsafe { revoke::set_access_all_threads(self.pkey.key(), PkeyAccessRights::DisableAccess) };
+        // Bumped afterwards, so state written by a thread during the revocation is invalidated too.
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+        result
+    }
+
+    fn is_revoked(&self) -> bool {
+        self.revoked.load(Ordering::Acquire)
+    }
+
+    fn associate<'a, A, T, Rights>(&'a self, region: &'a mut RegionGuard<A, T>) -> Result<AssociatedRegionHandler<'a, A, T, Rights>, super::MprotectError>
+    where
+        A: allocator::Allocator<T>,
+        Rights: access_rights::Access,
+    {
+        unsafe {
+            self.pkey.associate(region.get_region(), region.access_rights())?;
+        }
+        AssociatedRegionHandler::with_core(region, self)
+    }
+
+    fn associate_typed<'a, A, T, Rights, P>(&'a self, region: &'a mut TypedRegion<A, T, P>) -> Result<TypedAssociatedRegion<'a, A, T, Rights, P>, super::MprotectError>
+    where
+        A: allocator::Allocator<T>,
+        Rights: access_rights::Access,
+        P: AccessPermission,
     {
         unsafe {
             self.pkey.associate(region.get_region(), region.access_rights())?;
-            self.pkey.set_access_rights(Rights::new().value().pkey_rights)?;
         }
-        Ok(AssociatedRegionHandler::new(region, self))
+        TypedAssociatedRegion::new(region, self)
+    }
+}
+
+impl Drop for GuardCore {
+    /// Discards the calling thread's permission state for the key.
+    ///
+    /// State left on other threads is recognised by its guard id and replaced if the
+    /// key number is reused by a later guard.
+    fn drop(&mut self) {
+        let _ = THREAD_PERMISSIONS.try_with(|states| {
+            let mut states = states.borrow_mut();
+            if states.get(&self.pkey.key()).is_some_and(|state| state.guard_id == self.id) {
+                states.remove(&self.pkey.key());
+            }
+        });
     }
 }
@@ -66,3 +66,63 @@ pub mod permissions {
     impl CanRead for ReadWrite {}
     impl CanWrite for ReadWrite {}
 }
+
+/// Computes, at the type level, the rights left when a pkey scope `Self` covers pages
+/// with the page-level rights `P`.
+///
+/// Both layers must allow an access for it to succeed, so the output is the
+/// intersection of `P` with what the scope's `pkey_rights` allow. Protection keys never
+/// restrict instruction fetches, so execute permission passes through unchanged.
+pub trait Effective<P: pte::AccessPermission>: Access {
+    /// The effective page-level rights.
+    type Output: pte::AccessPermission;
+}
+
+/// The effective rights of pkey scope `K` over pages with rights `P`. See [`Effective`].
+pub type EffectiveRights<K, P> = <K as Effective<P>>::Output;
+
+use crate::mprotect::access_rights::access_permissions as pte;
+
+/// Implements [`Effective`] for a pkey rights type from its PTE-to-output table.
+macro_rules! effective {
+    ($pkey:ident { $($pte:ident => $out:ident),* $(,)? }) => {
+        $(impl Effective<pte::$pte> for permissions::$pkey { type Output = pte::$out; })*
+    };
+}
+
+/// `EnableAccessWrite` keeps the page-level rights as they are.
+macro_rules! effective_enable_access_write {
+    ($pkey:ident) => {
+        effective!($pkey {
+            NoAccess => NoAccess, ReadOnly => ReadOnly, WriteOnly => WriteOnly, ExecuteOnly => ExecuteOnly,
+            ReadWrite => ReadWrite, ReadExecute => ReadExecute, WriteExecute => WriteExecute, ReadWriteExecute => ReadWriteExecute,
+        });
+    };
+}
+
+/// `DisableWrite` removes write access.
+macro_rules! effective_disable_write {
+    ($pkey:ident) => {
+        effective!($pkey {
+            NoAccess => NoAccess, ReadOnly => ReadOnly, WriteOnly => NoAccess, ExecuteOnly => ExecuteOnly,
+            ReadWrite => ReadOnly, ReadExecute => ReadExecute, WriteExecute => ExecuteOnly, ReadWriteExecute => ReadExecute,
+        });
+    };
+}
+
+/// `DisableAccess` removes read and write access.
+macro_rules! effective_disable_access {
+    ($pkey:ident) => {
+        effective!($pkey {
+            NoAccess => NoAccess, ReadOnly => NoAccess, WriteOnly => NoAccess, ExecuteOnly => ExecuteOnly,
+            ReadWrite => NoAccess, ReadExecute => ExecuteOnly, WriteExecute => ExecuteOnly, ReadWriteExecute => ExecuteOnly,
+        });
+    };
+}
+
+effective_enable_access_write!(ReadWrite);
+effective_enable_access_write!(ReadWriteExecute);
+effective_disable_write!(ReadOnly);
+effective_disable_write!(ReadExecute);
+effective_disable_access!(NoAccess);
+effective_disable_access!(ExecuteOnly);
@@ -0,0 +1,198 @@
+//! Cross-thread PKRU rewrite used by [`super::PkeyGuard::revoke_all_threads`].
+//!
+//! Every other thread of the process is sent [`revocation_signal`]. Its handler does not
+//! execute `WRPKRU`, which would be undone on `sigreturn`; it edits the PKRU saved in the
+//! interrupted context through [`InterruptedContext`], which the kernel restores when the
+//! handler returns. The handler itself runs with [`PkruState::DENY_ALL`].
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU32, AtomicU64, AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+
+use crate::signals::{ self, InterruptedContext, PkruState };
+use crate::{ MprotectError, PkeyAccessRights };
+
+/// How long to wait for every signalled thread to run its handler.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often to look for signalled threads that exited without acknowledging.
+const RECOUNT_INTERVAL: Duration = Duration::from_millis(10);
+/// Number of acknowledgements a single revocation can record.
+const MAX_ACKS: usize = 1 << 16;
+
+/// Serialises revocations.
+static REVOCATION: Mutex<()> = Mutex::new(());
+/// Sequence number of the revocation in progress.
+///
+/// Each signal carries its revocation's number together with the key and rights, so a
+/// handler that runs after its revocation timed out neither applies stale rights nor
+/// acknowledges a later revocation.
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+/// Acknowledgements of the revocation in progress, as `sequence << 32 | tid`.
+static ACKS: [AtomicU64; MAX_ACKS] = [const { AtomicU64::new(0) }; MAX_ACKS];
+static ACK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the real-time signal used to interrupt threads during revocation.
+///
+/// The crate installs its own handler for this signal on the first revocation and
+/// keeps it installed, so the application must not use it for anything else.
+pub fn revocation_signal() -> i32 {
+    libc::SIGRTMIN() + 3
+}
+
+/// Packs a revocation into the signal value: `sequence << 32 | key << 8 | bits`.
+fn encode(sequence: u32, key: u32, access: PkeyAccessRights) -> usize {
+    ((sequence as usize) << 32) | ((key as usize) << 8) | access as usize
+}
+
+fn decode(value: usize) -> (u32, u32, PkeyAccessRights) {
+    ((value >> 32) as u32, (value >> 8) as u32 & 0xff, PkeyAccessRights::from_pkru_bits(value as u32 & 0b11))
+}
+
+/// Rewrites the PKRU value saved in the interrupted context and acknowledges.
+///
+/// Signals of an earlier revocation are ignored. A frame without PKRU state is not
+/// acknowledged, so the revocation reports it.
+fn rewrite_saved_pkru(_signal: i32, info: &libc::siginfo_t, context: &mut InterruptedContext) {
+    if info.si_code != libc::SI_QUEUE {
+        return;
+    }
+    let (sequence, key, access) = decode(unsafe { info.si_value().sival_ptr } as usize);
+    if sequence != SEQUENCE.load(Ordering::Acquire) || context.set_key_access(key, access).is_err() {
+        return;
+    }
+    let index = ACK_COUNT.fetch_add(1, Ordering::Relaxed);
+    if let Some(ack) = ACKS.get(index) {
+        let tid = unsafe { libc::gettid() } as u32;
+        ack.store((sequence as u64) << 32 | tid as u64, Ordering::Release);
+    }
+}
+
+/// Returns the threads that acknowledged revocation `sequence` so far.
+fn acknowledged(sequence: u32) -> HashSet<libc::pid_t> {
+    let count = ACK_COUNT.load(Ordering::Relaxed).min(MAX_ACKS);
+    ACKS[..count].iter()
+        .map(|ack| ack.load(Ordering::Acquire))
+        .filter(|ack| (ack >> 32) as u32 == sequence)
+        .map(|ack| ack as u32 as libc::pid_t)
+        .collect()
+}
+
+/// Layout of `siginfo_t` for a queued signal, which `libc` does not let us build.
+#[repr(C)]
+struct QueuedSignal {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    pid: libc::pid_t,
+    uid: libc::uid_t,
+    value: usize,
+    _rest: [usize; 12],
+}
+
+const _: () = assert!(std::mem::size_of::<QueuedSignal>() == std::mem::size_of::<libc::siginfo_t>());
+
+/// Sends [`revocation_signal`] carrying `value` to thread `tid`.
+unsafe fn send(pid: libc::pid_t, tid: libc::pid_t, value: usize) -> libc::c_long {
+    let info = QueuedSignal {
+        signo: revocation_signal(),
+        errno: 0,
+        code: libc::SI_QUEUE,
+        _pad: 0,
+        pid,
+        uid: libc::getuid(),
+        value,
+        _rest: [0; 12],
+    };
+    libc::syscall(libc::SYS_rt_tgsigqueueinfo, pid, tid, revocation_signal(), &info)
+}
+
+/// Returns the thread ids listed in `/proc/self/task`.
+fn thread_ids() -> Result<Vec<libc::pid_t>, MprotectError> {
+    let entries = std::fs::read_dir("/proc/self/task")
+        .map_err(|e| MprotectError::ThreadEnumerationFailed(e.raw_os_error().unwrap_or(-1)))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+/// Sets the rights of `key` to `access` in every thread of the process.
+///
+/// The calling thread is updated directly. Every other thread is signalled and the
+/// call returns once all of them have rewritten their saved PKRU. Threads are listed
+/// again until a pass finds none that has not been signalled yet, so threads spawned
+/// while the revocation is in progress are covered too. Threads that exit before
+/// running the handler are no longer waited for.
+///
+/// # Returns
+///
+/// - `Ok(usize)`: The number of other threads that were updated.
+/// - `Err(MprotectError::ThreadEnumerationFailed)`: If `/proc/self/task` cannot be read.
+/// - `Err(MprotectError::SignalSetupFailed)`: If the handler cannot be installed or a thread cannot be signalled.
+/// - `Err(MprotectError::RevocationTimedOut)`: If some threads did not run the handler in time,
+///   e.g. because they block the signal.
+pub(super) unsafe fn set_access_all_threads(key: u32, access: PkeyAccessRights) -> Result<usize, MprotectError> {
+    let _revocation = REVOCATION.lock().unwrap_or_else(|e| e.into_inner());
+
+    let sequence = SEQUENCE.load(Ordering::Relaxed).wrapping_add(1);
+    ACK_COUNT.store(0, Ordering::Relaxed);
+    SEQUENCE.store(sequence, Ordering::Release);
+    signals::install(revocation_signal(), PkruState::DENY_ALL, rewrite_saved_pkru)?;
+
+    PkruState::current().with_access(key, access).apply();
+
+    let pid = libc::getpid();
+    let value = encode(sequence, key, access);
+    let mut listed: HashSet<libc::pid_t> = HashSet::from([libc::gettid()]);
+    let mut outstanding = HashSet::new();
+    loop {
+        let pending: Vec<_> = thread_ids()?.into_iter().filter(|tid| !listed.contains(tid)).collect();
+        if pending.is_empty() {
+            break;
+        }
+        for tid in pending {
+            listed.insert(tid);
+            if send(pid, tid, value) == 0 {
+                outstanding.insert(tid);
+                continue;
+            }
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            // The thread exited after it was listed.
+            if err_no != libc::ESRCH {
+                return Err(MprotectError::SignalSetupFailed(err_no));
+            }
+        }
+    }
+
+    let mut updated = 0;
+    let deadline = Instant::now() + ACK_TIMEOUT;
+    let mut next_recount = Instant::now() + RECOUNT_INTERVAL;
+    loop {
+        let now = Instant::now();
+        // A thread that exits after it was signalled never runs the handler. Threads are
+        // listed before the acknowledgements are read, so one that acknowledged and then
+        // exited is still counted as updated.
+        let alive: Option<HashSet<_>> = if now >= next_recount || now >= deadline {
+            next_recount = now + RECOUNT_INTERVAL;
+            Some(thread_ids()?.into_iter().collect())
+        } else {
+            None
+        };
+        let acked = acknowledged(sequence);
+        outstanding.retain(|tid| {
+            if acked.contains(tid) {
+                updated += 1;
+                return false;
+            }
+            alive.as_ref().is_none_or(|alive| alive.contains(tid))
+        });
+        if outstanding.is_empty() {
+            return Ok(updated);
+        }
+        if now >= deadline {
+            return Err(MprotectError::RevocationTimedOut(outstanding.len()));
+        }
+        std::thread::yield_now();
+    }
+}
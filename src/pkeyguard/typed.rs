@@ -0,0 +1,142 @@
+use super::access_rights::{ self, Effective, EffectiveRights };
+use super::{ GuardCore, PkeyGuardError };
+use crate::mprotect::access_rights::access_permissions::{ AccessPermission, ReadAllowedTrait, WriteAllowedTrait };
+use crate::{ allocator, TypedRegion };
+
+/// A pkey scope over a [`TypedRegion`], whose effective rights are checked by the compiler.
+///
+/// An access succeeds only if both the page-level rights `P` of the region and the pkey
+/// rights `Rights` of the scope allow it. [`EffectiveRights<Rights, P>`](EffectiveRights)
+/// computes that intersection as a type, and [`ref_guard()`](Self::ref_guard) and
+/// [`mut_ref_guard()`](Self::mut_ref_guard) exist only when it allows reading or writing.
+/// Unlike [`AssociatedRegion`](super::AssociatedRegion), page rights are never widened
+/// to satisfy an access: a `ReadWrite` scope over a read-only region cannot write.
+///
+/// Created by [`PkeyGuard::associate_typed()`](super::PkeyGuard::associate_typed). The
+/// scope's rights are pushed onto the calling thread's permission stack and restored
+/// when it is dropped.
+///
+/// # Type Parameters
+/// - `'a`: Lifetime of the borrows of the guard and the region.
+/// - `A`: Allocator type managing the region.
+/// - `T`: Element type stored in the region.
+/// - `Rights`: Pkey access-rights type implementing [`Access`](access_rights::Access).
+/// - `P`: Page-level rights of the region, one of the [`AccessPermissions`](crate::AccessPermissions) markers.
+///
+/// # Example
+/// ```no_run
+/// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, ReadOnly, ReadWrite, TypedRegion};
+/// let guard = PkeyGuard::<allocator::Mmap, u32>::new(ReadWrite)?;
+/// let mut region = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::ReadWrite)?;
+///
+/// let mut scope = guard.associate_typed::<ReadWrite, _>(&mut region)?;
+/// *scope.mut_ref_guard().unwrap() = 42;
+///
+/// let readonly = scope.set_access_rights::<ReadOnly>()?;
+/// assert_eq!(*readonly.ref_guard().unwrap(), 42);
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+///
+/// Writing through a `ReadWrite` scope over read-only pages is a compile error:
+/// ```compile_fail
+/// # use mprotect_rs::{allocator, AccessPermissions, PkeyGuard, ReadWrite, TypedRegion};
+/// let guard = PkeyGuard::<allocator::Mmap, u32>::new(ReadWrite)?;
+/// let mut region = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::ReadOnly)?;
+///
+/// let mut scope = guard.associate_typed::<ReadWrite, _>(&mut region)?;
+/// *scope.mut_ref_guard().unwrap() = 42;
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct TypedAssociatedRegion<'a, A: allocator::Allocator<T>, T, Rights, P>
+where
+    Rights: access_rights::Access,
+    P: AccessPermission,
+{
+    region: &'a mut TypedRegion<A, T, P>,
+    pkey_guard: &'a GuardCore,
+    access_rights: Rights,
+    /// Depth of the thread's permission stack below this scope's entry.
+    depth: usize,
+}
+
+impl<'a, A: allocator::Allocator<T>, T, Rights, P> TypedAssociatedRegion<'a, A, T, Rights, P>
+where
+    Rights: access_rights::Access,
+    P: AccessPermission,
+{
+    /// Pushes `Rights` onto the guard's permission stack with a single PKRU write and
+    /// returns the scope.
+    pub(super) fn new(region: &'a mut TypedRegion<A, T, P>, pkey_guard: &'a GuardCore) -> Result<Self, crate::MprotectError> {
+        let access_rights = Rights::new();
+        let depth = pkey_guard.push_permissions(access_rights.value())?;
+        Ok(TypedAssociatedRegion { region, pkey_guard, access_rights, depth })
+    }
+
+    /// Checks that the guard was not revoked and brings the thread's PKRU in line with `Rights`.
+    fn enter(&self) -> Result<(), PkeyGuardError> {
+        if self.pkey_guard.is_revoked() {
+            return Err(PkeyGuardError::Revoked);
+        }
+        self.pkey_guard.sync_rights(self.access_rights.value()).map_err(PkeyGuardError::MprotectError)
+    }
+
+    /// Returns a shared reference to the value.
+    ///
+    /// # Constraints
+    /// Only available if [`EffectiveRights<Rights, P>`](EffectiveRights) allows reading.
+    ///
+    /// # Errors
+    /// - [`PkeyGuardError::Revoked`]: If the guard was permanently revoked.
+    /// - [`PkeyGuardError::MprotectError`]: If permission synchronization fails.
+    pub fn ref_guard(&self) -> Result<&T, PkeyGuardError>
+    where
+        Rights: Effective<P>,
+        EffectiveRights<Rights, P>: ReadAllowedTrait,
+    {
+        self.enter()?;
+        unsafe { Ok(self.region.get_region().as_ref()) }
+    }
+
+    /// Returns a mutable reference to the value.
+    ///
+    /// # Constraints
+    /// Only available if [`EffectiveRights<Rights, P>`](EffectiveRights) allows writing,
+    /// that is if both the pkey rights and the page-level rights do.
+    ///
+    /// # Errors
+    /// - [`PkeyGuardError::Revoked`]: If the guard was permanently revoked.
+    /// - [`PkeyGuardError::MprotectError`]: If permission synchronization fails.
+    pub fn mut_ref_guard(&mut self) -> Result<&mut T, PkeyGuardError>
+    where
+        Rights: Effective<P>,
+        EffectiveRights<Rights, P>: WriteAllowedTrait,
+    {
+        self.enter()?;
+        unsafe { Ok(self.region.get_region_mut().as_mut()) }
+    }
+
+    /// Opens a nested scope over the same region with the pkey rights `NewRights`.
+    ///
+    /// The nested scope borrows this one, so it is dropped first and restores `Rights`.
+    ///
+    /// # Returns
+    /// - `Ok(TypedAssociatedRegion)`: The nested scope.
+    /// - `Err(MprotectError)`: If the hardware update fails.
+    pub fn set_access_rights<NewRights>(&mut self) -> Result<TypedAssociatedRegion<'_, A, T, NewRights, P>, crate::MprotectError>
+    where
+        NewRights: access_rights::Access,
+    {
+        TypedAssociatedRegion::new(self.region, self.pkey_guard)
+    }
+}
+
+impl<'a, A: allocator::Allocator<T>, T, Rights, P> Drop for TypedAssociatedRegion<'a, A, T, Rights, P>
+where
+    Rights: access_rights::Access,
+    P: AccessPermission,
+{
+    /// Restores the rights below this scope on the permission stack.
+    fn drop(&mut self) {
+        self.pkey_guard.pop_permissions(self.depth);
+    }
+}
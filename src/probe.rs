@@ -0,0 +1,117 @@
+//! Host capability detection.
+//!
+//! Reports whether the CPU and kernel provide the features this crate builds on:
+//! protection keys (PKU/OSPKE) and related memory facilities (`mseal`, `memfd_secret`,
+//! soft-dirty tracking, THP).
+//!
+//! Every probe is free of side effects on the rest of the process. In particular, the
+//! number of free protection keys is not reported: the kernel does not expose it, and
+//! counting them by allocation would make `pkey_alloc` fail on other threads.
+
+use std::arch::x86_64::__cpuid_count;
+
+use crate::{ allocator, AccessRights, UnsafeProtectedRegion };
+
+/// Capabilities of the running host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The CPU implements protection keys for user pages (`CPUID.(EAX=7,ECX=0):ECX[3]`).
+    pub pku: bool,
+    /// The OS has enabled protection keys (`CPUID.(EAX=7,ECX=0):ECX[4]`).
+    pub ospke: bool,
+    /// Kernel release string as reported by `uname -r`.
+    pub kernel: String,
+    /// The `mseal` system call is available.
+    pub mseal: bool,
+    /// The `memfd_secret` system call is available and enabled.
+    pub memfd_secret: bool,
+    /// The kernel tracks soft-dirty pages in `/proc/self/pagemap`.
+    pub soft_dirty: bool,
+    /// Active transparent huge page mode (`always`, `madvise` or `never`), if THP is built in.
+    pub thp: Option<String>,
+}
+
+impl Capabilities {
+    /// Probes the running host.
+    pub fn probe() -> Self {
+        Capabilities {
+            pku: pku_supported(),
+            ospke: ospke_enabled(),
+            kernel: kernel_release(),
+            mseal: mseal_supported(),
+            memfd_secret: memfd_secret_supported(),
+            soft_dirty: soft_dirty_supported(),
+            thp: thp_mode(),
+        }
+    }
+}
+
+fn cpuid_leaf7_ecx() -> u32 {
+    let max_leaf = __cpuid_count(0, 0).eax;
+    if max_leaf < 7 {
+        return 0;
+    }
+    __cpuid_count(7, 0).ecx
+}
+
+/// Returns `true` if the CPU implements protection keys for user pages.
+pub fn pku_supported() -> bool {
+    cpuid_leaf7_ecx() & (1 << 3) != 0
+}
+
+/// Returns `true` if the operating system has enabled protection keys.
+///
+/// `RDPKRU`/`WRPKRU` raise `#UD` unless this bit is set.
+pub fn ospke_enabled() -> bool {
+    cpuid_leaf7_ecx() & (1 << 4) != 0
+}
+
+/// Returns the kernel release string, or an empty string if `uname` fails.
+pub fn kernel_release() -> String {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return String::new();
+    }
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    release.to_string_lossy().into_owned()
+}
+
+/// Returns `true` if the kernel implements the `mseal` system call.
+///
+/// Sealing an empty range is a no-op, so this probe has no side effects.
+pub fn mseal_supported() -> bool {
+    unsafe { libc::syscall(libc::SYS_mseal, 0usize, 0usize, 0usize) == 0 }
+}
+
+/// Returns `true` if `memfd_secret` is available and enabled.
+///
+/// The kernel rejects the call unless it was booted with `secretmem.enable=1`
+/// (or the equivalent default), so a successful call is required.
+pub fn memfd_secret_supported() -> bool {
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_secret, 0) };
+    if fd < 0 {
+        return false;
+    }
+    unsafe { libc::close(fd as i32) };
+    true
+}
+
+/// Returns `true` if the kernel tracks soft-dirty pages.
+///
+/// A new mapping starts out soft-dirty on kernels built with `CONFIG_MEM_SOFT_DIRTY`,
+/// so a freshly mapped and touched page is checked in `/proc/self/pagemap`.
+pub fn soft_dirty_supported() -> bool {
+    let Ok(region) = UnsafeProtectedRegion::<allocator::Mmap, u8>::new_initialized(0, AccessRights::READ) else {
+        return false;
+    };
+    let (start, _) = region.page_range();
+    crate::dirty::read_soft_dirty(start, 1).is_ok_and(|dirty| dirty[0])
+}
+
+/// Returns the active transparent huge page mode, if THP is available.
+pub fn thp_mode() -> Option<String> {
+    let enabled = std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled").ok()?;
+    let start = enabled.find('[')? + 1;
+    let end = start + enabled[start..].find(']')?;
+    Some(enabled[start..end].to_string())
+}
@@ -0,0 +1,194 @@
+//! Quarantine for dropped protected regions.
+//!
+//! Dropping an [`UnsafeProtectedRegion`](crate::UnsafeProtectedRegion) normally releases
+//! its memory at once, so the next allocation may reuse the address and a dangling
+//! pointer silently reads the new data. While the quarantine is [enabled](enable),
+//! dropped regions are made `PROT_NONE`, retagged to a dedicated "dead" protection key,
+//! and kept reserved. They are released oldest first once they exceed the size budget,
+//! so a use after drop faults reliably for as long as the budget allows. With the dead
+//! key, such faults are reported as `SEGV_PKUERR` with the dead key as `si_pkey`, which
+//! tells them apart from other protection faults.
+//!
+//! Only regions that own whole pages, such as those of [`allocator::Mmap`](crate::allocator::Mmap), are
+//! quarantined; other regions are released immediately.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use mprotect_rs::{allocator, quarantine, AccessRights, UnsafeProtectedRegion};
+//!
+//! quarantine::enable(64 << 20);
+//! let region = UnsafeProtectedRegion::<allocator::Mmap, u64>::new_initialized(7, AccessRights::READ)?;
+//! let dangling = region.ptr();
+//! drop(region);
+//! assert!(quarantine::contains(dangling as usize));
+//! // Reading through `dangling` now faults instead of returning reused memory.
+//! # Ok::<(), mprotect_rs::MprotectError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{ Mutex, MutexGuard };
+
+use crate::allocator::{ Allocator, AllocatorError, MemoryRegion };
+use crate::{ probe, PKey, PkeyAccessRights };
+
+/// A dropped region kept reserved.
+struct Entry {
+    start: usize,
+    len: usize,
+    /// The boxed `MemoryRegion`, released by `release`.
+    region: *mut (),
+    release: unsafe fn(*mut ()) -> Result<(), AllocatorError>,
+}
+
+// The region is unreachable from anywhere but the quarantine.
+unsafe impl Send for Entry {}
+
+struct State {
+    enabled: bool,
+    max_bytes: usize,
+    bytes: usize,
+    dead_pkey: Option<PKey>,
+    entries: VecDeque<Entry>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    enabled: false,
+    max_bytes: 0,
+    bytes: 0,
+    dead_pkey: None,
+    entries: VecDeque::new(),
+});
+
+fn lock() -> MutexGuard<'static, State> {
+    // Releasing regions may panic; the state stays consistent regardless.
+    STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Starts quarantining dropped regions, keeping up to `max_bytes` of them reserved.
+///
+/// On first use this allocates the dead protection key if the host supports protection
+/// keys and one is available; otherwise regions are only made `PROT_NONE`. Calling it
+/// again changes the budget and releases the regions that exceed it.
+pub fn enable(max_bytes: usize) {
+    let mut state = lock();
+    if state.dead_pkey.is_none() && probe::pku_supported() && probe::ospke_enabled() {
+        state.dead_pkey = unsafe { PKey::new(PkeyAccessRights::DisableAccess) }.ok();
+    }
+    state.enabled = true;
+    state.max_bytes = max_bytes;
+    let evicted = evict(&mut state);
+    drop(state);
+    release_all(evicted);
+}
+
+/// Stops quarantining, releases every quarantined region and frees the dead key.
+pub fn disable() {
+    let mut state = lock();
+    state.enabled = false;
+    let evicted = std::mem::take(&mut state.entries);
+    state.bytes = 0;
+    let dead_pkey = state.dead_pkey.take();
+    drop(state);
+    release_all(evicted);
+    drop(dead_pkey);
+}
+
+/// Releases every quarantined region, keeping the quarantine enabled.
+pub fn flush() {
+    let mut state = lock();
+    let evicted = std::mem::take(&mut state.entries);
+    state.bytes = 0;
+    drop(state);
+    release_all(evicted);
+}
+
+/// Returns `true` if dropped regions are quarantined.
+pub fn is_enabled() -> bool {
+    lock().enabled
+}
+
+/// Returns the protection key quarantined regions are tagged with, if any.
+pub fn dead_pkey() -> Option<u32> {
+    lock().dead_pkey.as_ref().map(PKey::key)
+}
+
+/// Returns the number of bytes currently quarantined.
+pub fn quarantined_bytes() -> usize {
+    lock().bytes
+}
+
+/// Returns `true` if `addr` lies in a quarantined region.
+///
+/// This takes a lock, so it must not be called from a signal handler.
+pub fn contains(addr: usize) -> bool {
+    lock().entries.iter().any(|entry| entry.start <= addr && addr < entry.start + entry.len)
+}
+
+/// Quarantines `region` if the quarantine is enabled and the region owns whole pages.
+///
+/// Returns the region back if it must be released by the caller instead.
+pub(crate) fn admit<A: Allocator<T>, T>(region: MemoryRegion<A, T>) -> Result<(), MemoryRegion<A, T>> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+    let (start, len) = (region.ptr() as usize, region.len());
+    let mut state = lock();
+    let whole_pages = len > 0 && start.is_multiple_of(page_size) && len.is_multiple_of(page_size);
+    if !state.enabled || !whole_pages || len > state.max_bytes {
+        return Err(region);
+    }
+    let ret = unsafe {
+        match &state.dead_pkey {
+            Some(pkey) => libc::syscall(libc::SYS_pkey_mprotect, start, len, libc::PROT_NONE, pkey.key()) as i32,
+            None => libc::mprotect(start as *mut libc::c_void, len, libc::PROT_NONE),
+        }
+    };
+    if ret != 0 {
+        return Err(region);
+    }
+    state.bytes += len;
+    state.entries.push_back(Entry {
+        start,
+        len,
+        region: Box::into_raw(Box::new(region)) as *mut (),
+        release: release::<A, T>,
+    });
+    let evicted = evict(&mut state);
+    drop(state);
+    release_all(evicted);
+    Ok(())
+}
+
+unsafe fn release<A: Allocator<T>, T>(region: *mut ()) -> Result<(), AllocatorError> {
+    let region = Box::from_raw(region as *mut MemoryRegion<A, T>);
+    region.deallocate()
+}
+
+/// Removes the oldest regions until the quarantine fits its budget.
+fn evict(state: &mut State) -> VecDeque<Entry> {
+    let mut evicted = VecDeque::new();
+    while state.bytes > state.max_bytes {
+        let Some(entry) = state.entries.pop_front() else { break };
+        state.bytes -= entry.len;
+        evicted.push_back(entry);
+    }
+    evicted
+}
+
+/// Makes evicted regions accessible with the default key again and releases them.
+///
+/// **Warning**: If deallocation fails, this function will panic, like dropping the
+/// region would have.
+fn release_all(evicted: VecDeque<Entry>) {
+    for entry in evicted {
+        unsafe {
+            // Memory returned to a heap allocator must not stay inaccessible or tagged.
+            let addr = entry.start as *mut libc::c_void;
+            if libc::syscall(libc::SYS_pkey_mprotect, addr, entry.len, libc::PROT_READ | libc::PROT_WRITE, 0) != 0 {
+                libc::mprotect(addr, entry.len, libc::PROT_READ | libc::PROT_WRITE);
+            }
+            if let Err(e) = (entry.release)(entry.region) {
+                panic!("Failed to deallocate memory: {:?}", e.to_string());
+            }
+        }
+    }
+}
@@ -1,20 +1,190 @@
 use crate::{mprotect::*, MprotectError};
 
 use std::cell::Cell;
+use std::marker::PhantomData;
 use std::rc::Rc;
 use std::ops::{ Deref, DerefMut };
 
+mod transaction;
+pub use transaction::*;
+
 /// A guard object that manages a protected memory region and its access rights.
 ///
 /// `RegionGuard` encapsulates ownership and lifetime management of a memory region
 /// allocated through a custom allocator (`allocator::Allocator<T>`).  
 /// It provides safe, reference-counted control over access permissions and 
 /// integrates with hardware memory protection mechanisms.
+///
+/// Rights granted for a guard stay in place while any guard that needs them is alive:
+/// the region counts its live read and write guards, and `WRITE` is revoked only with
+/// the last write guard, other granted rights only with the last guard of either kind.
+///
+/// # Panics and poisoning
+///
+/// Guards restore the region's page rights in `Drop`, which also runs while a panic
+/// unwinds. Like a `Mutex`, a region whose [`GuardRefMut`] is dropped by a panic is
+/// marked poisoned, since the panic may have left the value half-written: every
+/// accessor then returns [`GuardError::Poisoned`] until [`clear_poison()`](Self::clear_poison)
+/// is called.
 pub struct RegionGuard<A: allocator::Allocator<T>, T> {
     memory: UnsafeProtectedRegion<A, T>,
-    generation: Rc<Cell<u64>>,
+    state: Rc<GuardState>,
+}
+
+/// State shared between a [`RegionGuard`] and the guards it hands out.
+struct GuardState {
+    generation: Cell<u64>,
     default_access_rights: AccessRights,
-    access_rights: Rc<Cell<AccessRights>>,
+    access_rights: Cell<AccessRights>,
+    /// Number of live [`GuardRef`]s of the current generation.
+    readers: Cell<usize>,
+    /// Number of live [`GuardRefMut`]s of the current generation.
+    writers: Cell<usize>,
+    /// Number of live [`GuardRef`]s of invalidated generations.
+    ///
+    /// They no longer narrow the rights when dropped, but can still be dereferenced
+    /// until then, so they still exclude write guards.
+    stale_readers: Cell<usize>,
+    /// Number of live [`GuardRefMut`]s of invalidated generations.
+    stale_writers: Cell<usize>,
+    poisoned: Cell<bool>,
+}
+
+/// Which counter of [`GuardState`] a guard is accounted in.
+#[derive(Clone, Copy)]
+enum GuardKind {
+    Reader,
+    Writer,
+}
+
+impl GuardState {
+    /// Returns the counter a guard of `kind` and generation `gen` is accounted in.
+    fn counter(&self, gen: u64, kind: GuardKind) -> &Cell<usize> {
+        match (self.generation.get() == gen, kind) {
+            (true, GuardKind::Reader) => &self.readers,
+            (true, GuardKind::Writer) => &self.writers,
+            (false, GuardKind::Reader) => &self.stale_readers,
+            (false, GuardKind::Writer) => &self.stale_writers,
+        }
+    }
+
+    /// Number of live readers of any generation.
+    fn all_readers(&self) -> usize {
+        self.readers.get() + self.stale_readers.get()
+    }
+
+    /// Number of live writers of any generation.
+    fn all_writers(&self) -> usize {
+        self.writers.get() + self.stale_writers.get()
+    }
+
+    /// Moves the guards of the current generation to the stale counters and starts a new one.
+    fn invalidate(&self) {
+        self.generation.set(self.generation.get().wrapping_add(1));
+        self.stale_readers.set(self.stale_readers.get() + self.readers.replace(0));
+        self.stale_writers.set(self.stale_writers.get() + self.writers.replace(0));
+    }
+
+    /// Adds `rights` to the region's page rights if missing and counts a new guard of `kind`.
+    ///
+    /// Returns the generation the guard belongs to.
+    fn acquire<A: allocator::Allocator<T>, T>(&self, memory: &UnsafeProtectedRegion<A, T>, rights: AccessRights, kind: GuardKind) -> Result<u64, GuardError> {
+        if self.poisoned.get() {
+            return Err(GuardError::Poisoned);
+        }
+        // A write guard obtained through `upgrade` coexists with `&RegionGuard` borrows,
+        // and so do guards of invalidated generations.
+        let conflicting = match kind {
+            GuardKind::Reader => self.all_writers(),
+            GuardKind::Writer => self.all_readers() + self.all_writers(),
+        };
+        if conflicting > 0 {
+            return Err(GuardError::OtherGuardsAlive);
+        }
+        if !self.access_rights.get().contains(rights) {
+            let new_access = self.access_rights.get().add(rights);
+            unsafe {
+                memory.set_access(new_access).map_err(GuardError::CannotSetAccessRights)?;
+            }
+            self.access_rights.set(new_access);
+        }
+        let gen = self.generation.get();
+        let counter = self.counter(gen, kind);
+        counter.set(counter.get() + 1);
+        Ok(gen)
+    }
+
+    /// Turns a writer of generation `gen` into a reader, changing the page rights at most once.
+    ///
+    /// `READ` is granted if missing, and `WRITE` is revoked if this was the last writer.
+    fn downgrade<A: allocator::Allocator<T>, T>(&self, memory: &UnsafeProtectedRegion<A, T>, gen: u64) {
+        if self.generation.get() != gen {
+            self.stale_writers.set(self.stale_writers.get().saturating_sub(1));
+            self.stale_readers.set(self.stale_readers.get() + 1);
+            return;
+        }
+        self.writers.set(self.writers.get().saturating_sub(1));
+        self.readers.set(self.readers.get() + 1);
+        let mut new_access = self.access_rights.get().add(AccessRights::READ);
+        if self.writers.get() == 0 {
+            new_access = new_access.minus(AccessRights::WRITE).add(self.default_access_rights);
+        }
+        if new_access != self.access_rights.get() && unsafe { memory.set_access(new_access) }.is_ok() {
+            self.access_rights.set(new_access);
+        }
+    }
+
+    /// Turns the only guard of generation `gen`, a reader, into a writer, granting
+    /// `WRITE` if missing.
+    fn upgrade<A: allocator::Allocator<T>, T>(&self, memory: &UnsafeProtectedRegion<A, T>, gen: u64) -> Result<(), GuardError> {
+        if self.generation.get() != gen {
+            return Err(GuardError::InvalidGeneration);
+        }
+        if self.poisoned.get() {
+            return Err(GuardError::Poisoned);
+        }
+        if self.all_readers() != 1 || self.all_writers() != 0 {
+            return Err(GuardError::OtherGuardsAlive);
+        }
+        if !self.access_rights.get().contains(AccessRights::WRITE) {
+            let new_access = self.access_rights.get().add(AccessRights::WRITE);
+            unsafe {
+                memory.set_access(new_access).map_err(GuardError::CannotSetAccessRights)?;
+            }
+            self.access_rights.set(new_access);
+        }
+        self.readers.set(0);
+        self.writers.set(1);
+        Ok(())
+    }
+
+    /// Counts one more guard of `kind` in generation `gen`, for a guard that was split in two.
+    fn split(&self, gen: u64, kind: GuardKind) {
+        let counter = self.counter(gen, kind);
+        counter.set(counter.get() + 1);
+    }
+
+    /// Uncounts a guard of `kind` from generation `gen` and narrows the page rights
+    /// once no guard needs them.
+    ///
+    /// `WRITE` is dropped with the last writer and every other granted right with the
+    /// last guard of either kind, unless they are part of the default rights. Guards of
+    /// an invalidated generation are only uncounted and leave the rights alone.
+    fn release<A: allocator::Allocator<T>, T>(&self, memory: &UnsafeProtectedRegion<A, T>, gen: u64, kind: GuardKind) {
+        let counter = self.counter(gen, kind);
+        counter.set(counter.get().saturating_sub(1));
+        if self.generation.get() != gen || self.writers.get() > 0 {
+            return;
+        }
+        let new_access = if self.readers.get() > 0 {
+            self.access_rights.get().minus(AccessRights::WRITE).add(self.default_access_rights)
+        } else {
+            self.default_access_rights
+        };
+        if new_access != self.access_rights.get() && unsafe { memory.set_access(new_access) }.is_ok() {
+            self.access_rights.set(new_access);
+        }
+    }
 }
 
 impl<A: allocator::Allocator<T>, T> RegionGuard<A, T> {
@@ -32,53 +202,162 @@ impl<A: allocator::Allocator<T>, T> RegionGuard<A, T> {
     /// - `Ok(RegionGuard)`: On success.
     /// - `Err(MprotectError)`: If memory allocation or protection setup fails.
     pub fn new<R: AllAccessesTrait>(value: T, access_rights: R) -> Result<Self, super::MprotectError> {
-        let generation = Rc::new(Cell::new(0));
         let memory = UnsafeProtectedRegion::new_initialized(value, access_rights.value())?;
         Ok(
             RegionGuard {
                 memory,
-                generation,
-                default_access_rights: access_rights.value(),
-                access_rights: Rc::new(Cell::new(access_rights.value())),
+                state: Rc::new(GuardState {
+                    generation: Cell::new(0),
+                    default_access_rights: access_rights.value(),
+                    access_rights: Cell::new(access_rights.value()),
+                    readers: Cell::new(0),
+                    writers: Cell::new(0),
+                    stale_readers: Cell::new(0),
+                    stale_writers: Cell::new(0),
+                    poisoned: Cell::new(false),
+                }),
             }
         )
     }
 
     /// Invalidates the current generation of this region.
     ///
-    /// Used to mark existing references as outdated.
+    /// Used to mark existing references as outdated. Outstanding guards stop being
+    /// counted as readers or writers of the current generation, so they no longer change
+    /// the region's rights when dropped. References they handed out may still be alive,
+    /// though, so until they are dropped they still make [`upgrade()`](Self::upgrade)
+    /// fail, and a stale write guard still makes [`read()`](Self::read) fail. The page
+    /// rights are left as they are; see [`revoke()`](Self::revoke) to also remove
+    /// hardware access.
     pub fn invalidate(&self) {
-        let current_gen = self.generation.get();
-        self.generation.set(current_gen.wrapping_add(1));
+        self.state.invalidate();
     }
 
-    /// Grants read access and returns an immutable guard.
+    /// Invalidates the current generation and removes all access to the region's pages.
     ///
-    /// Updates protection flags if necessary before returning a reference.
+    /// [`invalidate()`](Self::invalidate) only makes outstanding guards refuse access;
+    /// a raw pointer obtained from one, e.g. through [`GuardRef::ptr`], keeps working.
+    /// `revoke` additionally sets the pages to `PROT_NONE`, so such a pointer faults.
+    /// A protection key the region is tagged with is kept. Access is granted again only
+    /// by a new [`read()`](Self::read) or [`write()`](Self::write) call, and the default
+    /// rights return once that guard is dropped.
     ///
     /// # Returns
-    /// 
-    /// - `Ok(GuardRef)`: Read access wrapper.
-    /// - `Err(GuardError)`: If access rights cannot be updated.
-    pub fn read(&self) -> Result<GuardRef<'_, A, T>, GuardError> {
-        if !self.access_rights.get().has(AccessRights::READ) {
-            self.access_rights.set(self.access_rights.get().add(AccessRights::READ));
-            unsafe {
-                self.memory.set_access(self.access_rights.get()).map_err(GuardError::CannotSetAccessRights)?;
-            }
+    ///
+    /// - `Ok(())`: On success.
+    /// - `Err(MprotectError)`: If `mprotect` fails. The generation is invalidated regardless.
+    pub fn revoke(&self) -> Result<(), MprotectError> {
+        self.invalidate();
+        unsafe { self.memory.set_access(AccessRights::NONE)? };
+        self.state.access_rights.set(AccessRights::NONE);
+        Ok(())
+    }
+
+    /// Returns `true` if a write guard of this region was dropped by a panic.
+    pub fn is_poisoned(&self) -> bool {
+        self.state.poisoned.get()
+    }
+
+    /// Clears the poisoned state, e.g. after the value has been checked or repaired.
+    pub fn clear_poison(&self) {
+        self.state.poisoned.set(false);
+    }
+
+    /// Returns the number of live read guards of the current generation.
+    pub fn readers(&self) -> usize {
+        self.state.readers.get()
+    }
+
+    /// Returns the number of live write guards of the current generation.
+    pub fn writers(&self) -> usize {
+        self.state.writers.get()
+    }
+
+    /// Turns a read guard into a write guard, granting `WRITE` with at most one
+    /// `mprotect` call.
+    ///
+    /// This is an associated function because `guard` still borrows the region. It
+    /// succeeds only if `guard` is the region's only live guard. While the returned
+    /// guard is alive, [`read()`](Self::read) and [`deref()`](Self::deref) fail with
+    /// [`GuardError::OtherGuardsAlive`]. See [`GuardRefMut::downgrade`] for the
+    /// opposite direction.
+    ///
+    /// Only guards of the whole value can be upgraded: a guard narrowed with
+    /// [`GuardRef::map`] points through a shared reference, which must not be written
+    /// through. The write guard reuses the region's own pointer.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(GuardRefMut)`: The write guard, for the same data as `guard`.
+    /// - `Err(UpgradeError)`: The unchanged read guard, with [`GuardError::OtherGuardsAlive`]
+    ///   if other guards are alive, [`GuardError::InvalidGeneration`] if `guard` was
+    ///   invalidated, [`GuardError::InvalidAccessRights`] if it does not point to the
+    ///   region's value, [`GuardError::Poisoned`] or [`GuardError::CannotSetAccessRights`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, RegionGuard};
+    /// let region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess)?;
+    /// let guard = region.read().unwrap();
+    /// if *guard == 0 {
+    ///     let mut guard = RegionGuard::upgrade(guard).unwrap();
+    ///     *guard = 1;
+    /// }
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn upgrade<'a>(guard: GuardRef<'a, A, T>) -> Result<GuardRefMut<'a, A, T>, UpgradeError<'a, A, T>> {
+        // A guard mapped to a different value of type `T` must not gain write access.
+        if !std::ptr::eq(guard.ptr, guard.mem.ptr()) {
+            return Err(UpgradeError { guard, error: GuardError::InvalidAccessRights });
+        }
+        if let Err(error) = guard.state.upgrade(guard.mem, guard.gen) {
+            return Err(UpgradeError { guard, error });
         }
+        let (_, mem, gen, state) = guard.into_parts();
+        Ok(GuardRefMut {
+            ptr: mem.ptr(),
+            mem,
+            gen,
+            state,
+            panicking: std::thread::panicking(),
+        })
+    }
 
-        let gen = self.generation.get();
+    fn read_guard(&self, rights: AccessRights) -> Result<GuardRef<'_, A, T>, GuardError> {
+        let gen = self.state.acquire(&self.memory, rights, GuardKind::Reader)?;
         Ok(GuardRef {
-            ptr: unsafe { self.memory.as_ref() },
+            ptr: self.memory.ptr(),
             mem: &self.memory,
             gen,
-            generation: Rc::clone(&self.generation),
-            default_access_rights: self.default_access_rights,
-            access_rights: Rc::clone(&self.access_rights),
+            state: Rc::clone(&self.state),
+            _marker: PhantomData,
         })
     }
 
+    fn write_guard(&mut self, rights: AccessRights) -> Result<GuardRefMut<'_, A, T>, GuardError> {
+        let gen = self.state.acquire(&self.memory, rights, GuardKind::Writer)?;
+        Ok(GuardRefMut {
+            ptr: self.memory.ptr(),
+            mem: &mut self.memory,
+            gen,
+            state: Rc::clone(&self.state),
+            panicking: std::thread::panicking(),
+        })
+    }
+
+    /// Grants read access and returns an immutable guard.
+    ///
+    /// Updates protection flags if necessary before returning a reference.
+    ///
+    /// # Returns
+    /// 
+    /// - `Ok(GuardRef)`: Read access wrapper.
+    /// - `Err(GuardError)`: If access rights cannot be updated or the region is poisoned.
+    pub fn read(&self) -> Result<GuardRef<'_, A, T>, GuardError> {
+        self.read_guard(AccessRights::READ)
+    }
+
     /// Grants write access and returns a mutable guard.
     ///
     /// Enables write permission if not already active.
@@ -86,24 +365,9 @@ impl<A: allocator::Allocator<T>, T> RegionGuard<A, T> {
     /// # Returns
     /// 
     /// - `Ok(GuardRefMut)`: Write access wrapper.
-    /// - `Err(GuardError)`: If access rights cannot be updated.
+    /// - `Err(GuardError)`: If access rights cannot be updated or the region is poisoned.
     pub fn write(&mut self) -> Result<GuardRefMut<'_, A, T>, GuardError> {
-        if !self.access_rights.get().contains(AccessRights::WRITE) {
-            self.access_rights.set(self.access_rights.get().add(AccessRights::WRITE));
-            unsafe {
-                self.memory.set_access(self.access_rights.get()).map_err(GuardError::CannotSetAccessRights)?;
-            }
-        }
-
-        let gen = self.generation.get();
-        Ok(GuardRefMut {
-            ptr: unsafe { self.memory.as_mut() as *mut T },
-            mem: &mut self.memory,
-            gen,
-            generation: Rc::clone(&self.generation),
-            default_access_rights: self.default_access_rights,
-            access_rights: Rc::clone(&self.access_rights),
-        })
+        self.write_guard(AccessRights::WRITE)
     }
 
     /// Returns a read-only guard for custom access rights.
@@ -117,24 +381,9 @@ impl<A: allocator::Allocator<T>, T> RegionGuard<A, T> {
     /// # Returns
     /// 
     /// - `Ok(GuardRef)`: Read access wrapper.
-    /// - `Err(GuardError)`: If access rights cannot be updated.
+    /// - `Err(GuardError)`: If access rights cannot be updated or the region is poisoned.
     pub fn deref<R: ReadAllowedTrait>(&self, access_rights: R) -> Result<GuardRef<'_, A, T>, GuardError> {
-        if !self.access_rights.get().contains(access_rights.value()) {
-            self.access_rights.set(self.access_rights.get().add(access_rights.value()));
-            unsafe {
-                self.memory.set_access(self.access_rights.get()).map_err(GuardError::CannotSetAccessRights)?;
-            }
-        }
-        
-        let gen = self.generation.get();
-        Ok(GuardRef {
-            ptr: unsafe { self.memory.as_ref() },
-            mem: &self.memory,
-            gen,
-            generation: Rc::clone(&self.generation),
-            default_access_rights: self.default_access_rights,
-            access_rights: Rc::clone(&self.access_rights),
-        })
+        self.read_guard(access_rights.value())
     }
 
     /// Returns a mutable guard for custom access rights.
@@ -148,24 +397,9 @@ impl<A: allocator::Allocator<T>, T> RegionGuard<A, T> {
     /// # Returns
     /// 
     /// - `Ok(GuardRefMut)`: Write access wrapper.
-    /// - `Err(GuardError)`: If access rights cannot be updated.
+    /// - `Err(GuardError)`: If access rights cannot be updated or the region is poisoned.
     pub fn deref_mut<R: WriteAllowedTrait>(&mut self, access_rights: R) -> Result<GuardRefMut<'_, A, T>, GuardError> {
-        if !self.access_rights.get().contains(access_rights.value()) {
-            self.access_rights.set(self.access_rights.get().add(access_rights.value()));
-            unsafe {
-                self.memory.set_access(self.access_rights.get()).map_err(GuardError::CannotSetAccessRights)?;
-            }
-        }
-
-        let gen = self.generation.get();
-        Ok(GuardRefMut {
-            ptr: unsafe { self.memory.as_mut() as *mut T },
-            mem: &mut self.memory,
-            gen,
-            generation: Rc::clone(&self.generation),
-            default_access_rights: self.default_access_rights,
-            access_rights: Rc::clone(&self.access_rights),
-        })
+        self.write_guard(access_rights.value())
     }
 
     /// Returns the current access rights of this region.
@@ -174,7 +408,7 @@ impl<A: allocator::Allocator<T>, T> RegionGuard<A, T> {
     /// 
     /// The current `AccessRights` flags.
     pub fn access_rights(&self) -> AccessRights {
-        self.access_rights.get()
+        self.state.access_rights.get()
     }
 
     /// Returns a reference to the underlying protected memory region.
@@ -209,6 +443,11 @@ pub enum GuardError {
     InvalidGeneration,
     InvalidAccessRights,
     CannotSetAccessRights(MprotectError),
+    /// A write guard of the region was dropped by a panic. See [`RegionGuard::clear_poison`].
+    Poisoned,
+    /// The access conflicts with other live guards of the region, e.g. a read guard
+    /// requested while an upgraded write guard is alive.
+    OtherGuardsAlive,
 }
 
 impl std::fmt::Display for GuardError {
@@ -217,37 +456,72 @@ impl std::fmt::Display for GuardError {
             GuardError::InvalidGeneration => write!(f, "Invalid generation: the guard reference is no longer valid"),
             GuardError::InvalidAccessRights => write!(f, "Invalid access rights: the memory region does not allow the requested access"),
             GuardError::CannotSetAccessRights(err) => write!(f, "Cannot set access rights: {}", err),
+            GuardError::Poisoned => write!(f, "Poisoned: a write guard was dropped by a panic"),
+            GuardError::OtherGuardsAlive => write!(f, "Other guards alive: the access conflicts with other guards of the region"),
         }
     }
 }
 
+/// A failed [`RegionGuard::upgrade`].
+///
+/// The read guard is handed back unchanged.
+pub struct UpgradeError<'a, A: allocator::Allocator<T>, T, U: ?Sized = T> {
+    /// The read guard, still valid unless it had been invalidated before.
+    pub guard: GuardRef<'a, A, T, U>,
+    /// Why the guard could not be upgraded.
+    pub error: GuardError,
+}
+
+impl<A: allocator::Allocator<T>, T, U: ?Sized> std::fmt::Debug for UpgradeError<'_, A, T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpgradeError").field("error", &self.error).finish_non_exhaustive()
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, U: ?Sized> std::fmt::Display for UpgradeError<'_, A, T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cannot upgrade the read guard: {}", self.error)
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, U: ?Sized> From<UpgradeError<'_, A, T, U>> for GuardError {
+    /// Discards the read guard and keeps the error.
+    fn from(e: UpgradeError<'_, A, T, U>) -> Self {
+        e.error
+    }
+}
+
 /// A read-only smart reference to a protected memory region.
 ///
 /// `GuardRef` provides safe, temporary access to memory controlled by `RegionGuard`.
 /// It validates the reference against a generation counter to prevent use-after-invalidate
 /// and automatically updates memory access rights when dropped.
 ///
+/// The guard dereferences to `U`, which is the whole value `T` unless the guard was
+/// narrowed to a component with [`map()`](Self::map) or a related method.
+///
 /// # Safety
 /// 
 /// - Dereferencing or using this guard after `invalidate()` is undefined behavior.
 /// - Validity should always be checked using [`is_valid()`].
-pub struct GuardRef<'a, A: allocator::Allocator<T>, T> {
-    ptr: &'a T,
+pub struct GuardRef<'a, A: allocator::Allocator<T>, T, U: ?Sized = T> {
+    /// Taken from the region's pointer rather than from a reference, so that
+    /// [`RegionGuard::upgrade`] can write through it.
+    ptr: *const U,
     mem: &'a UnsafeProtectedRegion<A, T>,
     gen: u64,
-    generation: Rc<Cell<u64>>,
-    default_access_rights: AccessRights,
-    access_rights: Rc<Cell<AccessRights>>,
+    state: Rc<GuardState>,
+    _marker: PhantomData<&'a U>,
 }
 
-impl<'a, A: allocator::Allocator<T>, T> GuardRef<'a, A, T> {
+impl<'a, A: allocator::Allocator<T>, T, U: ?Sized> GuardRef<'a, A, T, U> {
     /// Returns `true` if this guard is still valid (not invalidated).
     /// 
     /// # Returns
     /// 
     /// `true` if valid, `false` if invalidated.
     pub fn is_valid(&self) -> bool {
-        self.generation.get() == self.gen
+        self.state.generation.get() == self.gen
     }
 
     /// Executes a closure on the referenced data if still valid.
@@ -264,10 +538,10 @@ impl<'a, A: allocator::Allocator<T>, T> GuardRef<'a, A, T> {
     /// - `Err(GuardError::InvalidGeneration)`: If invalidated.
     pub fn with<F, R>(&self, f: F) -> Result<R, GuardError>
     where 
-        F: FnOnce(&T) -> R,
+        F: FnOnce(&U) -> R,
     {
         if self.is_valid() {
-            Ok(f(self.ptr))
+            Ok(f(unsafe { &*self.ptr }))
         } else {
             Err(GuardError::InvalidGeneration)
         }
@@ -282,40 +556,135 @@ impl<'a, A: allocator::Allocator<T>, T> GuardRef<'a, A, T> {
     /// # Returns
     /// 
     /// A raw pointer to the data.
-    pub unsafe fn ptr(&self) -> *const T {
-        self.ptr as *const T
+    pub unsafe fn ptr(&self) -> *const U {
+        self.ptr
+    }
+
+    /// Takes the guard apart without running its `Drop`.
+    fn into_parts(self) -> (*const U, &'a UnsafeProtectedRegion<A, T>, u64, Rc<GuardState>) {
+        let this = std::mem::ManuallyDrop::new(self);
+        (this.ptr, this.mem, this.gen, unsafe { std::ptr::read(&this.state) })
+    }
+
+    /// Makes a guard for a component of the guarded data, e.g. a field or a sub-slice.
+    ///
+    /// The new guard takes over the generation check and the drop-time rights
+    /// restoration of this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard has been invalidated. See [`try_map()`](Self::try_map).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, RegionGuard};
+    /// struct Config { limit: u32, name: [u8; 16] }
+    ///
+    /// let region = RegionGuard::<allocator::Mmap, Config>::new(Config { limit: 8, name: [0; 16] }, AccessPermissions::NoAccess)?;
+    /// let limit = region.read().unwrap().map(|config| &config.limit);
+    /// assert_eq!(*limit, 8);
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn map<V: ?Sized, F>(self, f: F) -> GuardRef<'a, A, T, V>
+    where
+        F: FnOnce(&U) -> &V,
+    {
+        if !self.is_valid() {
+            panic!("Failed to map GuardRef: invalid generation");
+        }
+        let (ptr, mem, gen, state) = self.into_parts();
+        GuardRef { ptr: f(unsafe { &*ptr }), mem, gen, state, _marker: PhantomData }
+    }
+
+    /// Like [`map()`](Self::map), but returns an error instead of panicking if the
+    /// guard has been invalidated.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(GuardRef)`: The mapped guard.
+    /// - `Err(GuardError::InvalidGeneration)`: If invalidated. The guard is dropped.
+    pub fn try_map<V: ?Sized, F>(self, f: F) -> Result<GuardRef<'a, A, T, V>, GuardError>
+    where
+        F: FnOnce(&U) -> &V,
+    {
+        if !self.is_valid() {
+            return Err(GuardError::InvalidGeneration);
+        }
+        Ok(self.map(f))
+    }
+
+    /// Makes a guard for an optional component of the guarded data.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(GuardRef)`: If `f` returns `Some`.
+    /// - `Err(Self)`: The original guard, if `f` returns `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard has been invalidated.
+    pub fn filter_map<V: ?Sized, F>(self, f: F) -> Result<GuardRef<'a, A, T, V>, Self>
+    where
+        F: FnOnce(&U) -> Option<&V>,
+    {
+        if !self.is_valid() {
+            panic!("Failed to map GuardRef: invalid generation");
+        }
+        let ptr: &'a U = unsafe { &*self.ptr };
+        match f(ptr) {
+            Some(value) => {
+                let (_, mem, gen, state) = self.into_parts();
+                Ok(GuardRef { ptr: value, mem, gen, state, _marker: PhantomData })
+            }
+            None => Err(self),
+        }
+    }
+
+    /// Splits the guard into guards for two components of the guarded data.
+    ///
+    /// Each guard counts as a reader, so the region stays readable until both are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard has been invalidated.
+    pub fn map_split<V: ?Sized, W: ?Sized, F>(self, f: F) -> (GuardRef<'a, A, T, V>, GuardRef<'a, A, T, W>)
+    where
+        F: FnOnce(&U) -> (&V, &W),
+    {
+        if !self.is_valid() {
+            panic!("Failed to split GuardRef: invalid generation");
+        }
+        let (ptr, mem, gen, state) = self.into_parts();
+        let (first, second) = f(unsafe { &*ptr });
+        state.split(gen, GuardKind::Reader);
+        (
+            GuardRef { ptr: first, mem, gen, state: Rc::clone(&state), _marker: PhantomData },
+            GuardRef { ptr: second, mem, gen, state, _marker: PhantomData },
+        )
     }
 }
 
-impl<'a, A: allocator::Allocator<T>, T> Deref for GuardRef<'a, A, T> {
-    type Target = T;
+impl<'a, A: allocator::Allocator<T>, T, U: ?Sized> Deref for GuardRef<'a, A, T, U> {
+    type Target = U;
 
     /// Dereferences the guarded reference if valid, panicking otherwise.
     fn deref(&self) -> &Self::Target {
         if self.is_valid() {
-            self.ptr
+            unsafe { &*self.ptr }
         } else {
             panic!("Failed to deref GuardRef: invalid generation");
         }
     }
 }
 
-impl<'a, A: allocator::Allocator<T>, T> Drop for GuardRef<'a, A, T> {
+impl<'a, A: allocator::Allocator<T>, T, U: ?Sized> Drop for GuardRef<'a, A, T, U> {
     /// Restores access rights when the guard is dropped.
     ///
-    /// If `READ` access was granted temporarily, it is removed
-    /// unless it was part of the default access rights.
+    /// Rights granted for reading are removed once the last read guard of the region
+    /// is dropped, unless they are part of the default access rights.
     fn drop(&mut self) {
-        if self.generation.get() == self.gen {
-            if self.default_access_rights.contains(AccessRights::READ) {
-                // The default access rights already include Read, so no need to change
-                // because dropping a read guard should not remove read access if it was there by default
-            } else if self.access_rights.get().contains(AccessRights::READ) {
-                let new_access = self.access_rights.get().minus(AccessRights::READ);
-                let _ = unsafe { self.mem.set_access(new_access) };
-                self.access_rights.set(new_access);
-            }
-        }
+        self.state.release(self.mem, self.gen, GuardKind::Reader);
     }
 }
 
@@ -323,21 +692,24 @@ impl<'a, A: allocator::Allocator<T>, T> Drop for GuardRef<'a, A, T> {
 ///
 /// `GuardRefMut` ensures safe temporary access to a memory region whose permissions
 /// are dynamically managed. When dropped, it restores access rights to their original state.
+///
+/// The guard dereferences to `U`, which is the whole value `T` unless the guard was
+/// narrowed to a component with [`map()`](Self::map) or split with [`map_split()`](Self::map_split).
 /// 
 /// # Safety
 /// 
 /// - Dereferencing or using this guard after `invalidate()` is undefined behavior.
 /// - Validity should always be checked using [`is_valid()`].
-pub struct GuardRefMut<'a, A: allocator::Allocator<T>, T> {
-    ptr: *mut T,
+pub struct GuardRefMut<'a, A: allocator::Allocator<T>, T, U: ?Sized = T> {
+    ptr: *mut U,
     mem: &'a UnsafeProtectedRegion<A, T>,
     gen: u64,
-    generation: Rc<Cell<u64>>,
-    default_access_rights: AccessRights,
-    access_rights: Rc<Cell<AccessRights>>,
+    state: Rc<GuardState>,
+    /// Whether the thread was already panicking when the guard was created.
+    panicking: bool,
 }
 
-impl<'a, A: allocator::Allocator<T>, T> GuardRefMut<'a, A, T> {
+impl<'a, A: allocator::Allocator<T>, T, U: ?Sized> GuardRefMut<'a, A, T, U> {
     /// Returns `true` if this guard is still valid.
     ///
     /// A guard becomes invalid when the region's generation counter changes.
@@ -346,7 +718,7 @@ impl<'a, A: allocator::Allocator<T>, T> GuardRefMut<'a, A, T> {
     /// 
     /// `true` if valid, `false` if invalidated.
     pub fn is_valid(&self) -> bool {
-        self.generation.get() == self.gen
+        self.state.generation.get() == self.gen
     }
 
     /// Executes the given closure if this guard is valid.
@@ -361,7 +733,7 @@ impl<'a, A: allocator::Allocator<T>, T> GuardRefMut<'a, A, T> {
     /// to the protected data if valid.
     pub fn with<F, R>(&mut self, f: F) -> Result<R, GuardError>
     where 
-        F: FnOnce(&mut T) -> R,
+        F: FnOnce(&mut U) -> R,
     {
         if self.is_valid() {
             unsafe { Ok(f(&mut *self.ptr)) }
@@ -369,10 +741,140 @@ impl<'a, A: allocator::Allocator<T>, T> GuardRefMut<'a, A, T> {
             Err(GuardError::InvalidGeneration)
         }
     }
+
+    /// Takes the guard apart without running its `Drop`.
+    fn into_parts(self) -> (*mut U, &'a UnsafeProtectedRegion<A, T>, u64, Rc<GuardState>, bool) {
+        let this = std::mem::ManuallyDrop::new(self);
+        (this.ptr, this.mem, this.gen, unsafe { std::ptr::read(&this.state) }, this.panicking)
+    }
+
+    /// Turns this write guard into a read guard for the same data.
+    ///
+    /// Unlike dropping the guard and calling [`RegionGuard::read`], this changes the
+    /// page rights at most once: `WRITE` is revoked, unless other write guards remain
+    /// or it is part of the default rights, and `READ` is granted if missing. See
+    /// [`RegionGuard::upgrade`] for the opposite direction.
+    pub fn downgrade(self) -> GuardRef<'a, A, T, U> {
+        let (ptr, mem, gen, state, _) = self.into_parts();
+        state.downgrade(mem, gen);
+        GuardRef { ptr, mem, gen, state, _marker: PhantomData }
+    }
+
+    /// Makes a guard for a component of the guarded data, e.g. a field or a sub-slice.
+    ///
+    /// The new guard takes over the generation check, the drop-time rights restoration
+    /// and the poisoning of this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard has been invalidated. See [`try_map()`](Self::try_map).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, RegionGuard};
+    /// struct Config { limit: u32, name: [u8; 16] }
+    ///
+    /// let mut region = RegionGuard::<allocator::Mmap, Config>::new(Config { limit: 8, name: [0; 16] }, AccessPermissions::NoAccess)?;
+    /// let mut name = region.write().unwrap().map(|config| &mut config.name[..4]);
+    /// name.copy_from_slice(b"main");
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn map<V: ?Sized, F>(self, f: F) -> GuardRefMut<'a, A, T, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        if !self.is_valid() {
+            panic!("Failed to map GuardRefMut: invalid generation");
+        }
+        let (ptr, mem, gen, state, panicking) = self.into_parts();
+        let ptr = f(unsafe { &mut *ptr }) as *mut V;
+        GuardRefMut { ptr, mem, gen, state, panicking }
+    }
+
+    /// Like [`map()`](Self::map), but returns an error instead of panicking if the
+    /// guard has been invalidated.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(GuardRefMut)`: The mapped guard.
+    /// - `Err(GuardError::InvalidGeneration)`: If invalidated. The guard is dropped.
+    pub fn try_map<V: ?Sized, F>(self, f: F) -> Result<GuardRefMut<'a, A, T, V>, GuardError>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        if !self.is_valid() {
+            return Err(GuardError::InvalidGeneration);
+        }
+        Ok(self.map(f))
+    }
+
+    /// Makes a guard for an optional component of the guarded data.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(GuardRefMut)`: If `f` returns `Some`.
+    /// - `Err(Self)`: The original guard, if `f` returns `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard has been invalidated.
+    pub fn filter_map<V: ?Sized, F>(self, f: F) -> Result<GuardRefMut<'a, A, T, V>, Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut V>,
+    {
+        if !self.is_valid() {
+            panic!("Failed to map GuardRefMut: invalid generation");
+        }
+        match f(unsafe { &mut *self.ptr }).map(|value| value as *mut V) {
+            Some(ptr) => {
+                let (_, mem, gen, state, panicking) = self.into_parts();
+                Ok(GuardRefMut { ptr, mem, gen, state, panicking })
+            }
+            None => Err(self),
+        }
+    }
+
+    /// Splits the guard into guards for two disjoint components of the guarded data,
+    /// e.g. two fields of a struct or the halves of a slice.
+    ///
+    /// Each guard counts as a writer, so the region stays writable until both are
+    /// dropped, and either one poisons the region if dropped by a panic.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard has been invalidated.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, RegionGuard};
+    /// let mut region = RegionGuard::<allocator::Mmap, [u8; 8]>::new([0; 8], AccessPermissions::NoAccess)?;
+    /// let (mut head, mut tail) = region.write().unwrap().map_split(|bytes| bytes.split_at_mut(4));
+    /// head.fill(1);
+    /// tail.fill(2);
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn map_split<V: ?Sized, W: ?Sized, F>(self, f: F) -> (GuardRefMut<'a, A, T, V>, GuardRefMut<'a, A, T, W>)
+    where
+        F: FnOnce(&mut U) -> (&mut V, &mut W),
+    {
+        if !self.is_valid() {
+            panic!("Failed to split GuardRefMut: invalid generation");
+        }
+        let (ptr, mem, gen, state, panicking) = self.into_parts();
+        let (first, second) = f(unsafe { &mut *ptr });
+        let (first, second) = (first as *mut V, second as *mut W);
+        state.split(gen, GuardKind::Writer);
+        (
+            GuardRefMut { ptr: first, mem, gen, state: Rc::clone(&state), panicking },
+            GuardRefMut { ptr: second, mem, gen, state, panicking },
+        )
+    }
 }
 
-impl<'a, A: allocator::Allocator<T>, T> Deref for GuardRefMut<'a, A, T> {
-    type Target = T;
+impl<'a, A: allocator::Allocator<T>, T, U: ?Sized> Deref for GuardRefMut<'a, A, T, U> {
+    type Target = U;
 
     /// Returns a shared reference to the underlying data.
     ///
@@ -388,7 +890,7 @@ impl<'a, A: allocator::Allocator<T>, T> Deref for GuardRefMut<'a, A, T> {
     }
 }
 
-impl<'a, A: allocator::Allocator<T>, T> DerefMut for GuardRefMut<'a, A, T> {
+impl<'a, A: allocator::Allocator<T>, T, U: ?Sized> DerefMut for GuardRefMut<'a, A, T, U> {
     /// Returns a mutable reference to the underlying data.
     ///
     /// # Panics
@@ -403,33 +905,17 @@ impl<'a, A: allocator::Allocator<T>, T> DerefMut for GuardRefMut<'a, A, T> {
     }
 }
 
-impl<A: allocator::Allocator<T>, T> Drop for GuardRefMut<'_, A, T> {
+impl<A: allocator::Allocator<T>, T, U: ?Sized> Drop for GuardRefMut<'_, A, T, U> {
     /// Restores the region's access rights when the guard is dropped.
     ///
-    /// If the guard temporarily granted `READ` or `WRITE` access,
-    /// these rights are revoked unless they were part of the region's
-    /// original default access rights.
+    /// `WRITE` is revoked once the last write guard is dropped, and any other granted
+    /// right once no guard of the region remains, unless they are part of the region's
+    /// default access rights. If the guard is dropped by a panic, the region is marked
+    /// poisoned.
     fn drop(&mut self) {
-        if self.is_valid() {
-            if self.default_access_rights.has(AccessRights::READ_WRITE) {
-                // The default access rights already include ReadWrite, so no need to change
-                // because dropping a write guard should not remove read/write access if it was there by default
-            } else if !self.default_access_rights.has(AccessRights::WRITE) && self.access_rights.get().has(AccessRights::WRITE) {
-                let new_access = self.access_rights.get().minus(AccessRights::WRITE);
-                let _ = unsafe { self.mem.set_access(new_access) };
-                self.access_rights.set(new_access);
-                return;
-            } else if !self.default_access_rights.has(AccessRights::READ) && self.access_rights.get().has(AccessRights::READ) {
-                let new_access = self.access_rights.get().minus(AccessRights::READ);
-                let _ = unsafe { self.mem.set_access(new_access) };
-                self.access_rights.set(new_access);
-                return;
-            } else if self.access_rights.get().has(AccessRights::READ) || self.access_rights.get().has(AccessRights::WRITE) {
-                let new_access = self.access_rights.get().minus(AccessRights::READ_WRITE);
-                let _ = unsafe { self.mem.set_access(new_access) };
-                self.access_rights.set(new_access);
-                return;
-            }
+        if !self.panicking && std::thread::panicking() {
+            self.state.poisoned.set(true);
         }
+        self.state.release(self.mem, self.gen, GuardKind::Writer);
     }
 }
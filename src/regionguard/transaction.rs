@@ -0,0 +1,123 @@
+use super::{ GuardError, GuardRefMut, RegionGuard };
+use crate::mprotect::*;
+use crate::MprotectError;
+
+/// An error returned by [`RegionGuard::transaction`].
+#[derive(Debug)]
+pub enum TransactionError<E> {
+    /// The region could not be opened for writing, e.g. because it is poisoned.
+    Guard(GuardError),
+    /// The snapshot of the current contents could not be allocated.
+    Snapshot(MprotectError),
+    /// The closure returned an error and the region was rolled back.
+    Aborted(E),
+    /// The closure returned an error, but restoring the snapshot failed. The region
+    /// keeps the closure's changes and is poisoned.
+    RollbackFailed(E, MprotectError),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for TransactionError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::Guard(err) => write!(f, "Cannot open the region for writing: {}", err),
+            TransactionError::Snapshot(err) => write!(f, "Cannot take a snapshot of the region: {}", err),
+            TransactionError::Aborted(err) => write!(f, "Transaction aborted and rolled back: {}", err),
+            TransactionError::RollbackFailed(err, rollback) => write!(f, "Transaction aborted ({}), and rolling back failed: {}", err, rollback),
+        }
+    }
+}
+
+/// The write guard of a running transaction and the snapshot to restore.
+///
+/// Dropping it with the snapshot still in place, i.e. while a panic unwinds out of the
+/// closure, rolls the region back. Fields drop after `drop` runs, so the value is
+/// restored before the write guard re-protects the region.
+struct Rollback<'a, A: allocator::Allocator<T>, T> {
+    guard: GuardRefMut<'a, A, T>,
+    snapshot: Option<UnsafeProtectedRegion<allocator::Mmap, T>>,
+}
+
+impl<A: allocator::Allocator<T>, T> Rollback<'_, A, T> {
+    fn commit(&mut self) {
+        self.snapshot = None;
+    }
+
+    fn rollback(&mut self) -> Result<(), MprotectError> {
+        let Some(snapshot) = self.snapshot.take() else {
+            return Ok(());
+        };
+        *self.guard = snapshot.into_inner()?;
+        // The value is consistent again, so dropping the guard must not poison the region.
+        self.guard.panicking = true;
+        Ok(())
+    }
+}
+
+impl<A: allocator::Allocator<T>, T> Drop for Rollback<'_, A, T> {
+    fn drop(&mut self) {
+        // On failure the guard poisons the region as it unwinds.
+        let _ = self.rollback();
+    }
+}
+
+impl<A: allocator::Allocator<T>, T: Clone> RegionGuard<A, T> {
+    /// Runs `f` on the region's value as a single all-or-nothing update.
+    ///
+    /// The current value is cloned into a scratch region that is kept inaccessible,
+    /// and `f` receives a write guard. If `f` returns `Ok`, the changes are kept and
+    /// the snapshot is discarded. If it returns `Err` or panics, the snapshot is moved
+    /// back before the region is re-protected, so the region never keeps a partial
+    /// update. A panic is rolled back and then resumes, and does not poison the region.
+    ///
+    /// # Arguments
+    ///
+    /// - `f`: The update. It receives the write guard of the region.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(R)`: The value returned by `f`, if it succeeded.
+    /// - `Err(TransactionError)`: If the transaction could not start, or `f` failed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mprotect_rs::{allocator, AccessPermissions, RegionGuard, TransactionError};
+    /// #[derive(Clone)]
+    /// struct Config { workers: u32, timeout_ms: u32 }
+    ///
+    /// let mut config = RegionGuard::<allocator::Mmap, Config>::new(Config { workers: 4, timeout_ms: 100 }, AccessPermissions::ReadOnly)?;
+    /// let result = config.transaction(|config| {
+    ///     config.workers = 8;
+    ///     if config.timeout_ms < 500 {
+    ///         return Err("timeout too short for 8 workers");
+    ///     }
+    ///     Ok(())
+    /// });
+    /// assert!(matches!(result, Err(TransactionError::Aborted(_))));
+    /// assert_eq!(config.read().unwrap().workers, 4);
+    /// # Ok::<(), mprotect_rs::MprotectError>(())
+    /// ```
+    pub fn transaction<R, E, F>(&mut self, f: F) -> Result<R, TransactionError<E>>
+    where
+        F: FnOnce(&mut GuardRefMut<'_, A, T>) -> Result<R, E>,
+    {
+        let guard = self.write().map_err(TransactionError::Guard)?;
+        let snapshot = UnsafeProtectedRegion::new_initialized((*guard).clone(), AccessRights::NONE)
+            .map_err(TransactionError::Snapshot)?;
+        let mut tx = Rollback { guard, snapshot: Some(snapshot) };
+
+        match f(&mut tx.guard) {
+            Ok(value) => {
+                tx.commit();
+                Ok(value)
+            }
+            Err(err) => match tx.rollback() {
+                Ok(()) => Err(TransactionError::Aborted(err)),
+                Err(rollback) => {
+                    tx.guard.state.poisoned.set(true);
+                    Err(TransactionError::RollbackFailed(err, rollback))
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use crate::mpk::registry::{ self, PkeyTag };
+use crate::mprotect::*;
+use crate::MprotectError;
+
+/// A committed part of a [`ReservedRegion`].
+///
+/// Pass it to [`PKey::associate`](crate::PKey::associate) to tag its pages with a
+/// protection key.
+pub struct CommittedRange {
+    offset: usize,
+    tag: Arc<PkeyTag>,
+}
+
+impl CommittedRange {
+    fn new(base: usize, offset: usize, len: usize, access_rights: AccessRights) -> Self {
+        CommittedRange { offset, tag: PkeyTag::new(base + offset, len, access_rights) }
+    }
+
+    /// Returns the offset of the range from the start of the reservation.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn len(&self) -> usize {
+        self.tag.len()
+    }
+
+    /// Returns `true` if the range covers no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.tag.len() == 0
+    }
+
+    /// Returns a pointer to the first byte of the range.
+    pub fn ptr(&self) -> *mut u8 {
+        self.tag.start() as *mut u8
+    }
+
+    /// Returns the page-level access rights last applied to the range.
+    pub fn access_rights(&self) -> AccessRights {
+        self.tag.access_rights()
+    }
+
+    /// Returns the protection key the range is associated with, if any.
+    pub fn pkey(&self) -> Option<u32> {
+        self.tag.pkey()
+    }
+
+    fn end(&self) -> usize {
+        self.offset + self.len()
+    }
+}
+
+impl sealed::Sealed for CommittedRange {
+    fn tag(&self) -> &Arc<PkeyTag> {
+        &self.tag
+    }
+}
+
+impl ProtectedMemory for CommittedRange {}
+
+/// A large range of address space whose pages are made usable on demand.
+///
+/// The whole range is mapped with `PROT_NONE` and `MAP_NORESERVE`, so it costs neither
+/// memory nor swap until parts of it are committed. [`commit()`](Self::commit) gives a
+/// page range access rights, and [`decommit()`](Self::decommit) discards its contents
+/// with `MADV_DONTNEED` and makes it inaccessible again. Committed parts can be tagged
+/// with a protection key through [`PKey::associate`](crate::PKey::associate), which makes
+/// this the building block for sandboxes whose memory grows at run time.
+///
+/// Offsets and lengths are in bytes and must be multiples of the page size.
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::{AccessRights, PKey, PkeyAccessRights, ReservedRegion};
+///
+/// // 1 GiB of address space, of which only the first 64 KiB are usable.
+/// let mut heap = ReservedRegion::new(1 << 30)?;
+/// let pkey = unsafe { PKey::new(PkeyAccessRights::EnableAccessWrite)? };
+/// unsafe {
+///     let committed = heap.commit(0, 64 * 1024, AccessRights::READ_WRITE)?;
+///     pkey.associate(committed, AccessRights::READ_WRITE)?;
+///     heap.ptr().write(1);
+///
+///     // Grow, then shrink back.
+///     heap.commit(64 * 1024, 64 * 1024, AccessRights::READ_WRITE)?;
+///     heap.decommit(64 * 1024, 64 * 1024)?;
+/// }
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct ReservedRegion {
+    ptr: NonNull<u8>,
+    len: usize,
+    /// Committed ranges, ordered by offset and never overlapping.
+    committed: Vec<CommittedRange>,
+}
+
+impl ReservedRegion {
+    /// Reserves `len` bytes of address space, rounded up to whole pages.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(ReservedRegion)`: On success. No page is committed.
+    /// - `Err(MprotectError::MemoryAllocationFailed)`: If the `mmap` system call fails.
+    pub fn new(len: usize) -> Result<Self, MprotectError> {
+        let len = len.max(1).div_ceil(page_size()) * page_size();
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(MprotectError::MemoryAllocationFailed(err_no));
+        }
+        Ok(ReservedRegion {
+            ptr: NonNull::new(ptr as *mut u8).ok_or(MprotectError::MemoryAllocationFailed(-1))?,
+            len,
+            committed: Vec::new(),
+        })
+    }
+
+    /// Returns a pointer to the start of the reservation.
+    pub fn ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the size of the reservation in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the reservation covers no bytes. This is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the committed ranges, ordered by offset.
+    pub fn committed(&self) -> &[CommittedRange] {
+        &self.committed
+    }
+
+    /// Returns the committed range that contains `offset`, if any.
+    pub fn committed_at(&self, offset: usize) -> Option<&CommittedRange> {
+        self.committed.iter().find(|range| range.offset <= offset && offset < range.end())
+    }
+
+    /// Returns the number of committed bytes.
+    pub fn committed_len(&self) -> usize {
+        self.committed.iter().map(CommittedRange::len).sum()
+    }
+
+    /// Checks that `offset..offset + len` is a non-empty, page-aligned part of the reservation.
+    fn check_range(&self, offset: usize, len: usize) -> Result<(), MprotectError> {
+        let aligned = offset.is_multiple_of(page_size()) && len.is_multiple_of(page_size());
+        let in_bounds = offset.checked_add(len).is_some_and(|end| end <= self.len);
+        if len == 0 || !aligned || !in_bounds {
+            return Err(MprotectError::InvalidReservedRange(offset, len));
+        }
+        Ok(())
+    }
+
+    /// Commits `len` bytes at `offset` with `access_rights`.
+    ///
+    /// The pages read as zero until they are written. To change the rights or the
+    /// protection key of a committed range, pass it to [`PKey::associate`](crate::PKey::associate).
+    ///
+    /// # Safety
+    ///
+    /// The caller must respect `access_rights` when accessing the committed pages.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(&CommittedRange)`: The new committed range.
+    /// - `Err(MprotectError::InvalidReservedRange)`: If the range is empty, not page-aligned,
+    ///   outside the reservation, or overlaps a committed range.
+    /// - `Err(MprotectError::MprotectFailed)`: If the `mprotect` system call fails.
+    pub unsafe fn commit(&mut self, offset: usize, len: usize, access_rights: AccessRights) -> Result<&CommittedRange, MprotectError> {
+        self.check_range(offset, len)?;
+        if self.committed.iter().any(|range| range.offset < offset + len && offset < range.end()) {
+            return Err(MprotectError::InvalidReservedRange(offset, len));
+        }
+        let ret = libc::mprotect(self.ptr().add(offset) as *mut libc::c_void, len, access_rights.to_i32());
+        if ret != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(MprotectError::MprotectFailed(err_no));
+        }
+        let index = self.committed.partition_point(|range| range.offset < offset);
+        self.committed.insert(index, CommittedRange::new(self.ptr() as usize, offset, len, access_rights));
+        Ok(&self.committed[index])
+    }
+
+    /// Decommits `len` bytes at `offset`.
+    ///
+    /// Their contents are discarded with `MADV_DONTNEED`, and they are made inaccessible
+    /// and moved back to the default protection key. Committed ranges that overlap the
+    /// range only partly are shrunk or split; uncommitted pages in it are left as they are.
+    ///
+    /// # Safety
+    ///
+    /// No reference into the decommitted pages may be used afterwards.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: On success.
+    /// - `Err(MprotectError::InvalidReservedRange)`: If the range is empty, not page-aligned,
+    ///   or outside the reservation.
+    /// - `Err(MprotectError::MadviseFailed)`: If the `madvise` system call fails.
+    /// - `Err(MprotectError::MprotectFailed)` or `Err(MprotectError::PkeyMprotectFailed)`:
+    ///   If the pages cannot be made inaccessible.
+    pub unsafe fn decommit(&mut self, offset: usize, len: usize) -> Result<(), MprotectError> {
+        self.check_range(offset, len)?;
+        let end = offset + len;
+        let addr = self.ptr().add(offset) as *mut libc::c_void;
+        if libc::madvise(addr, len, libc::MADV_DONTNEED) != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(MprotectError::MadviseFailed(err_no));
+        }
+        // Moving tagged pages to key 0 keeps a later commit from inheriting their key.
+        let tagged = self.committed.iter()
+            .any(|range| range.offset < end && offset < range.end() && range.pkey().is_some());
+        let ret = if tagged {
+            libc::syscall(libc::SYS_pkey_mprotect, addr, len, libc::PROT_NONE, 0) as i32
+        } else {
+            libc::mprotect(addr, len, libc::PROT_NONE)
+        };
+        if ret != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(if tagged { MprotectError::PkeyMprotectFailed(err_no) } else { MprotectError::MprotectFailed(err_no) });
+        }
+
+        let base = self.ptr() as usize;
+        let mut committed = Vec::with_capacity(self.committed.len() + 1);
+        for range in self.committed.drain(..) {
+            if range.end() <= offset || end <= range.offset {
+                committed.push(range);
+                continue;
+            }
+            // The parts outside the decommitted range keep their rights and key.
+            let (pkey, access_rights) = (range.pkey(), range.access_rights());
+            registry::untrack(&range.tag);
+            let pieces = [(range.offset, offset), (end, range.end())];
+            for (start, stop) in pieces.into_iter().filter(|(start, stop)| start < stop) {
+                let piece = CommittedRange::new(base, start, stop - start, access_rights);
+                if pkey.is_some() {
+                    registry::set_pkey(&piece.tag, pkey, access_rights);
+                }
+                committed.push(piece);
+            }
+        }
+        self.committed = committed;
+        Ok(())
+    }
+}
+
+impl Drop for ReservedRegion {
+    /// Forgets the key associations of the committed ranges and unmaps the reservation.
+    ///
+    /// **Warning**: If unmapping fails, this method will panic.
+    fn drop(&mut self) {
+        for range in &self.committed {
+            registry::untrack(&range.tag);
+        }
+        if unsafe { libc::munmap(self.ptr() as *mut libc::c_void, self.len) } != 0 {
+            panic!("Failed to deallocate memory: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
@@ -0,0 +1,303 @@
+//! PKRU management for signal handlers.
+//!
+//! On signal delivery Linux loads a default PKRU value before running the handler,
+//! and on return it restores the interrupted code's PKRU from the signal frame. A
+//! handler therefore runs with different rights than the code it interrupted, and
+//! any [`PKey::set_access_rights`](crate::PKey::set_access_rights) it performs is lost
+//! when it returns.
+//!
+//! This module addresses both halves:
+//!
+//! - [`install_handler`] runs a handler with a chosen [`PkruState`] instead of the
+//!   kernel default.
+//! - [`InterruptedContext`] reads and modifies the PKRU saved in the interrupted
+//!   context's XSAVE area, so a handler can grant or revoke access for the code that
+//!   resumes after it.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use mprotect_rs::PkeyAccessRights;
+//! use mprotect_rs::signals::{ self, InterruptedContext, PkruState };
+//!
+//! fn on_usr1(_signal: i32, _info: &libc::siginfo_t, context: &mut InterruptedContext) {
+//!     // Deny key 1 to the interrupted code once the handler returns.
+//!     let _ = context.set_key_access(1, PkeyAccessRights::DisableAccess);
+//! }
+//!
+//! unsafe { signals::install_handler(libc::SIGUSR1, PkruState::DENY_ALL, on_usr1)? };
+//! # Ok::<(), mprotect_rs::MprotectError>(())
+//! ```
+
+use std::fmt::Display;
+use std::sync::atomic::{ AtomicU32, AtomicUsize, Ordering };
+
+use crate::pkru;
+use crate::{ MprotectError, PkeyAccessRights };
+
+/// `FP_XSTATE_MAGIC1`: marks a signal frame FPU area that is followed by an XSAVE header.
+const FP_XSTATE_MAGIC1: u32 = 0x4650_5853;
+/// Offset of `struct _fpx_sw_bytes` in the legacy FXSAVE area.
+const SW_BYTES_OFFSET: usize = 464;
+/// Offset of the XSAVE header (`xstate_bv` is its first field).
+const XSAVE_HEADER_OFFSET: usize = 512;
+/// XSAVE state component number of PKRU.
+const XFEATURE_PKRU: u32 = 9;
+
+/// Number of signal numbers, including the unused 0.
+const NSIG: usize = 65;
+
+/// `si_code` of a `SIGSEGV` for an address not mapped to an object.
+pub const SEGV_MAPERR: i32 = 1;
+/// `si_code` of a `SIGSEGV` for invalid permissions for a mapped object.
+pub const SEGV_ACCERR: i32 = 2;
+/// `si_code` of a `SIGSEGV` for an access denied by memory protection keys.
+pub const SEGV_PKUERR: i32 = 4;
+
+/// A signal handler run through [`install_handler`].
+pub type Handler = fn(signal: i32, info: &libc::siginfo_t, context: &mut InterruptedContext);
+
+static HANDLERS: [AtomicUsize; NSIG] = [const { AtomicUsize::new(0) }; NSIG];
+static HANDLER_PKRU: [AtomicU32; NSIG] = [const { AtomicU32::new(0) }; NSIG];
+
+/// A complete PKRU value: the rights of all 16 protection keys of a thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PkruState(u32);
+
+impl PkruState {
+    /// Every key allows reads and writes.
+    pub const ALLOW_ALL: PkruState = PkruState(0);
+
+    /// Key 0 allows reads and writes, every other key denies all access.
+    ///
+    /// This is the value Linux loads for signal handlers and new processes by default.
+    pub const DENY_ALL: PkruState = PkruState(0x5555_5554);
+
+    /// Creates a state from a raw PKRU value.
+    pub const fn from_raw(pkru: u32) -> Self {
+        PkruState(pkru)
+    }
+
+    /// Returns the raw PKRU value.
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    /// Reads the calling thread's PKRU.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support protection keys.
+    pub unsafe fn current() -> Self {
+        PkruState(pkru::rdpkru())
+    }
+
+    /// Writes this state to the calling thread's PKRU.
+    ///
+    /// [`ALLOW_ALL`](Self::ALLOW_ALL) and [`DENY_ALL`](Self::DENY_ALL) are written with
+    /// the immediate [`pkru::wrpkru_const`] gate, any other state with [`pkru::wrpkru`].
+    ///
+    /// # Safety
+    ///
+    /// This changes the access rights of the calling thread for every protection key.
+    pub unsafe fn apply(self) {
+        const ALLOW_ALL: u32 = PkruState::ALLOW_ALL.0;
+        const DENY_ALL: u32 = PkruState::DENY_ALL.0;
+        match self.0 {
+            ALLOW_ALL => pkru::wrpkru_const::<ALLOW_ALL>(),
+            DENY_ALL => pkru::wrpkru_const::<DENY_ALL>(),
+            pkru => pkru::wrpkru(pkru),
+        }
+    }
+
+    /// Returns this state with the rights of `key` replaced by `access`.
+    pub const fn with_access(self, key: u32, access: PkeyAccessRights) -> Self {
+        PkruState(pkru::with_key_bits(self.0, key, access as u32))
+    }
+
+    /// Returns the rights this state grants for `key`.
+    pub const fn access(self, key: u32) -> PkeyAccessRights {
+        PkeyAccessRights::from_pkru_bits(pkru::key_bits(self.0, key))
+    }
+}
+
+impl Display for PkruState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+/// Returns the offset of PKRU in the standard-format XSAVE area, if the CPU has one.
+fn pkru_offset() -> Option<usize> {
+    // 0 means not probed yet; usize::MAX means unsupported.
+    static OFFSET: AtomicUsize = AtomicUsize::new(0);
+    let offset = match OFFSET.load(Ordering::Relaxed) {
+        0 => {
+            // CPUID leaf 0xd reports the size and offset of each XSAVE component.
+            // Some CPUs report PKRU as 8 bytes, the register followed by padding.
+            let leaf = std::arch::x86_64::__cpuid_count(0xd, XFEATURE_PKRU);
+            let offset = if leaf.eax >= 4 && leaf.ebx != 0 { leaf.ebx as usize } else { usize::MAX };
+            OFFSET.store(offset, Ordering::Relaxed);
+            offset
+        }
+        offset => offset,
+    };
+    (offset != usize::MAX).then_some(offset)
+}
+
+/// The context a signal handler interrupted, as saved in the signal frame.
+///
+/// Changes made through this type take effect when the handler returns and the
+/// kernel restores the saved state.
+pub struct InterruptedContext {
+    context: *mut libc::ucontext_t,
+}
+
+impl InterruptedContext {
+    /// Wraps the third argument of an `SA_SIGINFO` handler.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be the `ucontext_t` pointer the kernel passed to the running
+    /// handler, and must only be used while that handler runs.
+    pub unsafe fn from_raw(context: *mut libc::c_void) -> Self {
+        InterruptedContext { context: context as *mut libc::ucontext_t }
+    }
+
+    /// Returns the underlying `ucontext_t`.
+    pub fn as_raw(&self) -> *mut libc::ucontext_t {
+        self.context
+    }
+
+    /// Returns pointers to `xstate_bv` and the saved PKRU, if the frame holds PKRU state.
+    fn xsave_pkru(&self) -> Option<(*mut u64, *mut u32)> {
+        let offset = pkru_offset()?;
+        unsafe {
+            let fpstate = (*self.context).uc_mcontext.fpregs as *mut u8;
+            if fpstate.is_null() {
+                return None;
+            }
+            let magic = *(fpstate.add(SW_BYTES_OFFSET) as *const u32);
+            let xfeatures = *(fpstate.add(SW_BYTES_OFFSET + 8) as *const u64);
+            if magic != FP_XSTATE_MAGIC1 || xfeatures & (1 << XFEATURE_PKRU) == 0 {
+                return None;
+            }
+            Some((fpstate.add(XSAVE_HEADER_OFFSET) as *mut u64, fpstate.add(offset) as *mut u32))
+        }
+    }
+
+    /// Returns the PKRU the interrupted code will resume with.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(PkruState)`: The saved PKRU.
+    /// - `None`: If the signal frame holds no PKRU state.
+    pub fn pkru(&self) -> Option<PkruState> {
+        let (xstate_bv, saved) = self.xsave_pkru()?;
+        unsafe {
+            // A clear `xstate_bv` bit means PKRU is in its init state, which is 0.
+            if *xstate_bv & (1 << XFEATURE_PKRU) == 0 {
+                return Some(PkruState::ALLOW_ALL);
+            }
+            Some(PkruState(*saved))
+        }
+    }
+
+    /// Replaces the PKRU the interrupted code will resume with.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: If the saved PKRU was updated.
+    /// - `Err(MprotectError::SavedPkruUnavailable)`: If the signal frame holds no PKRU state.
+    pub fn set_pkru(&mut self, pkru: PkruState) -> Result<(), MprotectError> {
+        let (xstate_bv, saved) = self.xsave_pkru().ok_or(MprotectError::SavedPkruUnavailable)?;
+        unsafe {
+            *saved = pkru.0;
+            *xstate_bv |= 1 << XFEATURE_PKRU;
+        }
+        Ok(())
+    }
+
+    /// Changes the rights of `key` in the PKRU the interrupted code will resume with.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: If the saved PKRU was updated.
+    /// - `Err(MprotectError::SavedPkruUnavailable)`: If the signal frame holds no PKRU state.
+    pub fn set_key_access(&mut self, key: u32, access: PkeyAccessRights) -> Result<(), MprotectError> {
+        let pkru = self.pkru().ok_or(MprotectError::SavedPkruUnavailable)?;
+        self.set_pkru(pkru.with_access(key, access))
+    }
+}
+
+extern "C" fn trampoline(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    let index = signal as usize;
+    unsafe {
+        // The interrupted code may be about to read `errno`.
+        let errno = *libc::__errno_location();
+        PkruState(HANDLER_PKRU[index].load(Ordering::Acquire)).apply();
+        let handler = HANDLERS[index].load(Ordering::Acquire);
+        if handler != 0 {
+            let handler: Handler = std::mem::transmute::<usize, Handler>(handler);
+            let mut context = InterruptedContext::from_raw(context);
+            handler(signal, &*info, &mut context);
+        }
+        *libc::__errno_location() = errno;
+    }
+}
+
+/// Installs `handler` for `signal`, running it with the rights in `pkru`.
+///
+/// The handler is entered through a trampoline that writes `pkru` with
+/// [`PkruState::apply`] before calling it. The interrupted code's own PKRU is
+/// restored when the handler returns, unless the handler changes it through
+/// [`InterruptedContext`]. The handler is installed with `SA_SIGINFO | SA_RESTART`
+/// and replaces any previous handler for `signal`.
+///
+/// # Arguments
+///
+/// - `signal`: The signal to handle.
+/// - `pkru`: The PKRU value the handler runs with.
+/// - `handler`: The function to run.
+///
+/// # Safety
+///
+/// - `handler` runs in signal context and must be async-signal-safe: it must not
+///   allocate, take locks, or call functions that are not async-signal-safe.
+/// - `signal` must not be one the crate handles itself. [`revocation_signal()`](crate::revocation_signal)
+///   is rejected. Replacing the `SIGSEGV` handler breaks [`DirtyTracker`](crate::DirtyTracker)s
+///   using [`DirtyStrategy::WriteProtect`](crate::DirtyStrategy::WriteProtect) and the fault
+///   reporting of the `testing` harness.
+///
+/// # Returns
+///
+/// - `Ok(())`: If the handler was installed.
+/// - `Err(MprotectError::SignalSetupFailed)`: If `signal` is invalid or the revocation
+///   signal (`EINVAL`), or if `sigaction` fails.
+pub unsafe fn install_handler(signal: i32, pkru: PkruState, handler: Handler) -> Result<(), MprotectError> {
+    if signal == crate::revocation_signal() {
+        return Err(MprotectError::SignalSetupFailed(libc::EINVAL));
+    }
+    install(signal, pkru, handler)
+}
+
+/// Installs `handler` like [`install_handler`], including for the crate's own signals.
+pub(crate) unsafe fn install(signal: i32, pkru: PkruState, handler: Handler) -> Result<(), MprotectError> {
+    if signal <= 0 || signal as usize >= NSIG {
+        return Err(MprotectError::SignalSetupFailed(libc::EINVAL));
+    }
+    HANDLER_PKRU[signal as usize].store(pkru.0, Ordering::Release);
+    HANDLERS[signal as usize].store(handler as usize, Ordering::Release);
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = trampoline as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            let err_no = std::io::Error::last_os_error().raw_os_error().unwrap();
+            return Err(MprotectError::SignalSetupFailed(err_no));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Parser for `/proc/<pid>/smaps`.
+//!
+//! The kernel reports every mapping of a process together with its page
+//! permissions and, on kernels built with protection key support, the
+//! `ProtectionKey:` the pages are tagged with. This module turns that text into
+//! [`Mapping`] records so the crate's own view of a region can be compared with
+//! the kernel's.
+
+use crate::AccessRights;
+use crate::Errno;
+
+/// A single mapping (VMA) as reported by `/proc/<pid>/smaps`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    /// Start address of the mapping (inclusive).
+    pub start: usize,
+    /// End address of the mapping (exclusive).
+    pub end: usize,
+    /// Page-level permissions (`r`, `w`, `x`).
+    pub access_rights: AccessRights,
+    /// `true` for shared mappings (`s`), `false` for private ones (`p`).
+    pub shared: bool,
+    /// Offset into the mapped file.
+    pub offset: u64,
+    /// Device of the mapped file as `major:minor`.
+    pub device: String,
+    /// Inode of the mapped file, `0` for anonymous memory.
+    pub inode: u64,
+    /// Path or pseudo-path (`[heap]`, `[stack]`, ...) of the mapping, if any.
+    pub pathname: Option<String>,
+    /// `Size:` field in kB.
+    pub size_kb: u64,
+    /// `Rss:` field in kB.
+    pub rss_kb: u64,
+    /// `ProtectionKey:` field, `None` if the kernel does not report protection keys.
+    pub protection_key: Option<u32>,
+    /// Two-letter flags from the `VmFlags:` field.
+    pub vm_flags: Vec<String>,
+}
+
+impl Mapping {
+    /// Returns the length of the mapping in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns `true` if the mapping covers no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+
+    /// Returns `true` if the mapping overlaps `[start, end)`.
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end
+    }
+
+    /// Returns the permission string in the kernel's `rwxp` notation.
+    pub fn perms(&self) -> String {
+        let flag = |right, c| if self.access_rights.has(right) { c } else { '-' };
+        [
+            flag(AccessRights::READ, 'r'),
+            flag(AccessRights::WRITE, 'w'),
+            flag(AccessRights::EXEC, 'x'),
+            if self.shared { 's' } else { 'p' },
+        ].iter().collect()
+    }
+}
+
+/// Reads and parses `/proc/self/smaps`.
+///
+/// # Returns
+///
+/// - `Ok(Vec<Mapping>)`: The mappings of the calling process in address order.
+/// - `Err(Errno)`: If the file cannot be read.
+pub fn read_self() -> Result<Vec<Mapping>, Errno> {
+    read_path("/proc/self/smaps")
+}
+
+/// Reads and parses `/proc/<pid>/smaps`.
+///
+/// # Returns
+///
+/// - `Ok(Vec<Mapping>)`: The mappings of the process in address order.
+/// - `Err(Errno)`: If the file cannot be read (e.g. `ENOENT` or `EACCES`).
+pub fn read(pid: u32) -> Result<Vec<Mapping>, Errno> {
+    read_path(&format!("/proc/{}/smaps", pid))
+}
+
+fn read_path(path: &str) -> Result<Vec<Mapping>, Errno> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| e.raw_os_error().unwrap_or(-1))?;
+    Ok(parse(&text))
+}
+
+/// Parses the contents of an smaps file.
+///
+/// Lines that are neither a mapping header nor a recognised `Key: value` field
+/// are ignored, so the parser tolerates fields added by newer kernels.
+pub fn parse(text: &str) -> Vec<Mapping> {
+    let mut mappings: Vec<Mapping> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(mapping) = parse_header(line) {
+            mappings.push(mapping);
+            continue;
+        }
+        let Some(current) = mappings.last_mut() else { continue };
+        let Some((key, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        match key {
+            "Size" => current.size_kb = parse_kb(value),
+            "Rss" => current.rss_kb = parse_kb(value),
+            "ProtectionKey" => current.protection_key = value.parse().ok(),
+            "VmFlags" => current.vm_flags = value.split_whitespace().map(str::to_string).collect(),
+            _ => {}
+        }
+    }
+
+    mappings
+}
+
+fn parse_kb(value: &str) -> u64 {
+    value.split_whitespace().next().and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+fn parse_header(line: &str) -> Option<Mapping> {
+    // The pathname is the rest of the line and may itself contain runs of spaces.
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let start = usize::from_str_radix(start, 16).ok()?;
+    let end = usize::from_str_radix(end, 16).ok()?;
+
+    let perms = fields.next()?.as_bytes();
+    if perms.len() != 4 {
+        return None;
+    }
+    let mut access_rights = AccessRights::NONE;
+    if perms[0] == b'r' { access_rights = access_rights.add(AccessRights::READ); }
+    if perms[1] == b'w' { access_rights = access_rights.add(AccessRights::WRITE); }
+    if perms[2] == b'x' { access_rights = access_rights.add(AccessRights::EXEC); }
+
+    let offset = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let device = fields.next()?.to_string();
+    let inode = fields.next()?.parse().ok()?;
+    let pathname = fields.next().unwrap_or("").trim_start().to_string();
+
+    Some(Mapping {
+        start,
+        end,
+        access_rights,
+        shared: perms[3] == b's',
+        offset,
+        device,
+        inode,
+        pathname: if pathname.is_empty() { None } else { Some(pathname) },
+        size_kb: 0,
+        rss_kb: 0,
+        protection_key: None,
+        vm_flags: Vec::new(),
+    })
+}
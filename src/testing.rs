@@ -0,0 +1,254 @@
+//! Test support for asserting that code faults.
+//!
+//! Available with the `testing` feature, e.g. from `[dev-dependencies]`:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! mprotect-rs = { version = "0.1", features = ["testing"] }
+//! ```
+//!
+//! Memory protection is only proven by a fault, and a fault kills the process that
+//! takes it. The helpers in this module fork the calling process, run a closure in
+//! the child and report how the child ended, including the exact signal and
+//! `si_code` of a fault.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use mprotect_rs::{allocator, AccessRights, UnsafeProtectedRegion};
+//! use mprotect_rs::testing::{self, SEGV_ACCERR};
+//!
+//! testing::expect_signal(libc::SIGSEGV, SEGV_ACCERR, || unsafe {
+//!     let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ).unwrap();
+//!     *region.as_mut() = 42;
+//! });
+//! ```
+//!
+//! # Caveats
+//!
+//! The child is created with `fork`, so only the calling thread exists in it. Locks
+//! held by other threads at the time of the fork stay locked in the child; closures
+//! should avoid shared process-wide state such as unbuffered stdout. A child that does
+//! not finish within [`CHILD_TIMEOUT_SECS`] is killed with `SIGALRM`.
+
+use std::sync::atomic::{ AtomicI32, Ordering };
+
+use crate::{ GuardedHeap, HeapFault };
+
+pub use crate::signals::{ SEGV_ACCERR, SEGV_MAPERR, SEGV_PKUERR };
+
+/// Seconds a child may run before it is killed with `SIGALRM`.
+pub const CHILD_TIMEOUT_SECS: u32 = 30;
+
+/// Signals the child reports through its fault handler.
+const FAULT_SIGNALS: [i32; 4] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE];
+
+/// Byte offset of `si_pkey` in `siginfo_t` on x86-64 (after `si_addr` and the bounds padding).
+const SI_PKEY_OFFSET: usize = 32;
+
+/// Write end of the pipe the child's fault handler reports through.
+static REPORT_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// A synchronous fault taken by a child process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fault {
+    /// Signal number (e.g. `SIGSEGV`).
+    pub signal: i32,
+    /// `si_code` of the signal (e.g. [`SEGV_ACCERR`] or [`SEGV_PKUERR`]).
+    pub code: i32,
+    /// Faulting address (`si_addr`).
+    pub addr: usize,
+    /// Protection key of the faulting page, reported for [`SEGV_PKUERR`] faults.
+    pub pkey: Option<u32>,
+    /// The heap bug behind the fault, if the child's allocator is a [`GuardedHeap`].
+    pub heap: Option<HeapFault>,
+}
+
+/// How a child process ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChildOutcome {
+    /// The child took a synchronous fault.
+    Faulted(Fault),
+    /// The child exited normally with the given status. A panic exits with `101`.
+    Exited(i32),
+    /// The child was killed by a signal that was not reported as a fault.
+    Signaled(i32),
+}
+
+extern "C" fn report_fault(signal: libc::c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    unsafe {
+        let code = (*info).si_code;
+        let addr = (*info).si_addr() as usize;
+        let pkey = *((info as *const u8).add(SI_PKEY_OFFSET) as *const u32);
+        let heap = match GuardedHeap::describe_fault(addr) {
+            None => [0, 0, 0],
+            Some(HeapFault::Overflow { allocation, size }) => [1, allocation as u64, size as u64],
+            Some(HeapFault::UseAfterFree { allocation, size }) => [2, allocation as u64, size as u64],
+        };
+        let record: [u64; 7] = [signal as u64, code as u64, addr as u64, pkey as u64, heap[0], heap[1], heap[2]];
+        let fd = REPORT_FD.load(Ordering::Relaxed);
+        libc::write(fd, record.as_ptr() as *const libc::c_void, std::mem::size_of_val(&record));
+        // SA_RESETHAND restored the default action; returning re-executes the
+        // faulting instruction, which now terminates the child with the same signal.
+    }
+}
+
+unsafe fn child_main<F: FnOnce()>(report_fd: i32, f: F) -> ! {
+    REPORT_FD.store(report_fd, Ordering::Relaxed);
+    for signal in FAULT_SIGNALS {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = report_fault as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESETHAND | libc::SA_NODEFER;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+    libc::alarm(CHILD_TIMEOUT_SECS);
+
+    let status = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(()) => 0,
+        Err(_) => 101,
+    };
+    libc::_exit(status);
+}
+
+/// Runs `f` in a forked child process and reports how the child ended.
+///
+/// # Panics
+///
+/// Panics if the pipe or the child process cannot be created.
+pub fn run_in_child<F: FnOnce()>(f: F) -> ChildOutcome {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        panic!("pipe2 failed: {}", std::io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        panic!("fork failed: {}", std::io::Error::last_os_error());
+    }
+    if pid == 0 {
+        unsafe {
+            libc::close(read_fd);
+            child_main(write_fd, f);
+        }
+    }
+
+    unsafe { libc::close(write_fd) };
+    let mut record = [0u64; 7];
+    let size = std::mem::size_of_val(&record);
+    let read = unsafe { libc::read(read_fd, record.as_mut_ptr() as *mut libc::c_void, size) };
+    unsafe { libc::close(read_fd) };
+
+    let mut status = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINTR) {
+            panic!("waitpid failed: {}", err);
+        }
+    }
+
+    if read == size as isize {
+        let code = record[1] as i32;
+        return ChildOutcome::Faulted(Fault {
+            signal: record[0] as i32,
+            code,
+            addr: record[2] as usize,
+            pkey: (code == SEGV_PKUERR).then_some(record[3] as u32),
+            heap: match record[4] {
+                1 => Some(HeapFault::Overflow { allocation: record[5] as usize, size: record[6] as usize }),
+                2 => Some(HeapFault::UseAfterFree { allocation: record[5] as usize, size: record[6] as usize }),
+                _ => None,
+            },
+        });
+    }
+    if libc::WIFSIGNALED(status) {
+        ChildOutcome::Signaled(libc::WTERMSIG(status))
+    } else {
+        ChildOutcome::Exited(libc::WEXITSTATUS(status))
+    }
+}
+
+/// Runs `f` in a child process and asserts that it faults.
+///
+/// # Returns
+///
+/// The fault taken by the child.
+///
+/// # Panics
+///
+/// Panics if the child exits or is killed without faulting.
+pub fn expect_fault<F: FnOnce()>(f: F) -> Fault {
+    match run_in_child(f) {
+        ChildOutcome::Faulted(fault) => fault,
+        other => panic!("expected the child to fault, but it ended with {:?}", other),
+    }
+}
+
+/// Runs `f` in a child process and asserts that it faults with `signal` and `code`.
+///
+/// # Returns
+///
+/// The fault taken by the child.
+///
+/// # Panics
+///
+/// Panics if the child does not fault, or faults with a different signal or `si_code`.
+pub fn expect_signal<F: FnOnce()>(signal: i32, code: i32, f: F) -> Fault {
+    let fault = expect_fault(f);
+    assert_eq!(
+        (fault.signal, fault.code), (signal, code),
+        "expected signal {} with si_code {}, got {:?}", signal, code, fault
+    );
+    fault
+}
+
+/// Runs `f` in a child process and asserts that it completes without faulting or panicking.
+///
+/// # Panics
+///
+/// Panics if the child faults, panics, or is killed.
+pub fn expect_no_fault<F: FnOnce()>(f: F) {
+    match run_in_child(f) {
+        ChildOutcome::Exited(0) => {}
+        other => panic!("expected the child to complete, but it ended with {:?}", other),
+    }
+}
+
+/// Declares a `#[test]` that passes only if its body faults.
+///
+/// The body runs in a forked child via [`testing::expect_fault`](crate::testing::expect_fault).
+/// An optional `signal: ..., code: ...;` header asserts the exact signal and `si_code`.
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::{allocator, should_fault, AccessRights, UnsafeProtectedRegion};
+///
+/// should_fault! {
+///     signal: libc::SIGSEGV, code: mprotect_rs::testing::SEGV_ACCERR;
+///     fn write_to_read_only_region() {
+///         unsafe {
+///             let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ).unwrap();
+///             *region.as_mut() = 42;
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! should_fault {
+    ($(#[$meta:meta])* fn $name:ident() $body:block) => {
+        $(#[$meta])*
+        #[test]
+        fn $name() {
+            $crate::testing::expect_fault(|| $body);
+        }
+    };
+    (signal: $signal:expr, code: $code:expr; $(#[$meta:meta])* fn $name:ident() $body:block) => {
+        $(#[$meta])*
+        #[test]
+        fn $name() {
+            $crate::testing::expect_signal($signal, $code, || $body);
+        }
+    };
+}
@@ -0,0 +1,165 @@
+use crate::mprotect::*;
+use crate::MprotectError;
+
+use AccessPermissions::AccessPermission;
+
+/// A protected memory region whose page-level access rights are part of its type.
+///
+/// Where [`RegionGuard`](crate::RegionGuard) tracks rights at runtime and widens them on
+/// demand, `TypedRegion` fixes them in the type parameter `R`. Changing rights consumes the
+/// region and returns one of a different type, so [`read()`](Self::read) exists only on
+/// readable states, [`write()`](Self::write) only on writable ones, and any other access is
+/// rejected by the compiler.
+///
+/// # Type Parameters
+///
+/// - `A`: The allocator type that implements the `Allocator<T>` trait
+/// - `T`: The type of data stored in the region
+/// - `R`: The current page-level rights, one of the [`AccessPermissions`] markers
+///
+/// # Example
+///
+/// ```no_run
+/// use mprotect_rs::{allocator, AccessPermissions, TypedRegion};
+///
+/// let mut region = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::ReadWrite)?;
+/// *region.write() = 42;
+///
+/// let region = region.into_read_only()?;
+/// assert_eq!(*region.read(), 42);
+///
+/// let sealed = region.into_no_access()?;
+/// // sealed.read() does not compile: `NoAccess` does not allow reads.
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+///
+/// Writing through a read-only region is a compile error:
+///
+/// ```compile_fail
+/// use mprotect_rs::{allocator, AccessPermissions, TypedRegion};
+///
+/// let mut region = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::ReadOnly)?;
+/// *region.write() = 42;
+/// # Ok::<(), mprotect_rs::MprotectError>(())
+/// ```
+pub struct TypedRegion<A: allocator::Allocator<T>, T, R: AccessPermission> {
+    memory: UnsafeProtectedRegion<A, T>,
+    access_rights: R,
+}
+
+/// A failed rights transition of a [`TypedRegion`].
+///
+/// The region is handed back unchanged, still typed with its previous rights.
+pub struct TransitionError<A: allocator::Allocator<T>, T, R: AccessPermission> {
+    /// The region, with its rights unchanged.
+    pub region: TypedRegion<A, T, R>,
+    /// The error reported by `mprotect`.
+    pub error: MprotectError,
+}
+
+impl<A: allocator::Allocator<T>, T, R: AccessPermission> std::fmt::Debug for TransitionError<A, T, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransitionError").field("error", &self.error).finish_non_exhaustive()
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, R: AccessPermission> std::fmt::Display for TransitionError<A, T, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cannot change the region's access rights: {}", self.error)
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, R: AccessPermission> From<TransitionError<A, T, R>> for MprotectError {
+    /// Discards the region and keeps the error.
+    fn from(e: TransitionError<A, T, R>) -> Self {
+        e.error
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, R: AccessPermission> TypedRegion<A, T, R> {
+    /// Allocates a new region holding `value` with the page-level rights `R`.
+    ///
+    /// # Arguments
+    ///
+    /// - `value`: The initial value of the region.
+    /// - `access_rights`: The rights marker the region starts in.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(TypedRegion)`: On success.
+    /// - `Err(MprotectError)`: If memory allocation or protection setup fails.
+    pub fn new(value: T, access_rights: R) -> Result<Self, MprotectError> {
+        let memory = UnsafeProtectedRegion::new_initialized(value, access_rights.value())?;
+        Ok(TypedRegion { memory, access_rights })
+    }
+
+    /// Changes the page-level rights to `R2` and returns the region typed accordingly.
+    ///
+    /// # Arguments
+    ///
+    /// - `access_rights`: The rights marker to transition to.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(TypedRegion<A, T, R2>)`: On success.
+    /// - `Err(TransitionError)`: If `mprotect` fails. The error holds the unchanged region.
+    pub fn into_access<R2: AccessPermission>(self, access_rights: R2) -> Result<TypedRegion<A, T, R2>, TransitionError<A, T, R>> {
+        if let Err(error) = unsafe { self.memory.set_access(access_rights.value()) } {
+            return Err(TransitionError { region: self, error });
+        }
+        Ok(TypedRegion { memory: self.memory, access_rights })
+    }
+
+    /// Makes the region inaccessible. See [`into_access()`](Self::into_access).
+    pub fn into_no_access(self) -> Result<TypedRegion<A, T, AccessPermissions::NoAccess>, TransitionError<A, T, R>> {
+        self.into_access(AccessPermissions::NoAccess)
+    }
+
+    /// Makes the region read-only. See [`into_access()`](Self::into_access).
+    pub fn into_read_only(self) -> Result<TypedRegion<A, T, AccessPermissions::ReadOnly>, TransitionError<A, T, R>> {
+        self.into_access(AccessPermissions::ReadOnly)
+    }
+
+    /// Makes the region readable and writable. See [`into_access()`](Self::into_access).
+    pub fn into_read_write(self) -> Result<TypedRegion<A, T, AccessPermissions::ReadWrite>, TransitionError<A, T, R>> {
+        self.into_access(AccessPermissions::ReadWrite)
+    }
+
+    /// Returns the page-level rights of the region, as encoded in `R`.
+    pub fn access_rights(&self) -> AccessRights {
+        self.access_rights.value()
+    }
+
+    /// Returns a reference to the underlying protected memory region.
+    ///
+    /// # Safety
+    ///
+    /// Changing the region's rights through the returned reference breaks the
+    /// guarantee encoded in `R`.
+    pub unsafe fn get_region(&self) -> &UnsafeProtectedRegion<A, T> {
+        &self.memory
+    }
+
+    /// Returns a mutable reference to the underlying protected memory region.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`get_region()`](Self::get_region).
+    pub(crate) unsafe fn get_region_mut(&mut self) -> &mut UnsafeProtectedRegion<A, T> {
+        &mut self.memory
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, R: ReadAllowedTrait> TypedRegion<A, T, R> {
+    /// Returns a shared reference to the value. Only available on readable states.
+    pub fn read(&self) -> &T {
+        unsafe { self.memory.as_ref() }
+    }
+}
+
+impl<A: allocator::Allocator<T>, T, R: WriteAllowedTrait> TypedRegion<A, T, R> {
+    /// Returns a mutable reference to the value. Only available on writable states.
+    pub fn write(&mut self) -> &mut T {
+        unsafe { self.memory.as_mut() }
+    }
+}
@@ -0,0 +1,55 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::SEGV_ACCERR;
+
+#[test]
+fn records_are_read_back_in_order_across_pages() {
+    let mut log = AppendOnlyLog::without_sealing().unwrap();
+    let record = [7u8; 1000];
+    for i in 0..10u8 {
+        let mut record = record;
+        record[0] = i;
+        assert_eq!(log.append(&record).unwrap(), i as usize);
+    }
+    // Four 1000-byte records fit in a page.
+    assert_eq!(log.committed(), 8);
+    assert_eq!(log.len(), 10);
+    assert_eq!(log.iter().count(), 8);
+    assert!(log.iter().enumerate().all(|(i, r)| r.len() == 1000 && r[0] == i as u8));
+    assert_eq!(log.get(8), None);
+    assert_eq!(log.pending().map(|r| r[0]).collect::<Vec<_>>(), vec![8, 9]);
+
+    log.commit().unwrap();
+    assert_eq!(log.committed(), 10);
+    assert_eq!(log.get(9).map(|r| r[0]), Some(9));
+    assert_eq!(log.pending().count(), 0);
+    assert!(matches!(log.append(&[0; LOG_PAGE_SIZE + 1]), Err(MprotectError::LogRecordTooLarge(_))));
+}
+
+#[test]
+fn sealed_pages_reject_mprotect() {
+    if !probe::mseal_supported() {
+        return;
+    }
+    let mut log = AppendOnlyLog::new().unwrap();
+    assert!(log.is_sealing());
+    log.append(b"entry").unwrap();
+    log.commit().unwrap();
+
+    let page = log.get(0).unwrap().as_ptr() as *mut libc::c_void;
+    let ret = unsafe { libc::mprotect(page, LOG_PAGE_SIZE, libc::PROT_READ | libc::PROT_WRITE) };
+    assert_eq!(ret, -1);
+    assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+    assert_eq!(log.get(0), Some(&b"entry"[..]));
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// A committed record cannot be rewritten.
+    fn write_to_committed_record_faults() {
+        let mut log = AppendOnlyLog::without_sealing().unwrap();
+        log.append(b"balance=100").unwrap();
+        log.commit().unwrap();
+        let record = log.get(0).unwrap().as_ptr() as *mut u8;
+        unsafe { record.add(8).write_volatile(b'9') };
+    }
+}
@@ -0,0 +1,125 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::{ self, SEGV_ACCERR };
+
+const PAGE: usize = 4096;
+
+type Pages = [u8; 4 * PAGE];
+
+fn pages() -> UnsafeProtectedRegion<allocator::Mmap, Pages> {
+    UnsafeProtectedRegion::new_initialized([0; 4 * PAGE], AccessRights::READ_WRITE).unwrap()
+}
+
+/// Writes to pages 0 and 3, then twice to page 1, checking what each checkpoint reports.
+fn check_checkpoints(tracker: &mut DirtyTracker<allocator::Mmap, Pages>) {
+    assert_eq!(tracker.page_count(), 4);
+    assert!(tracker.checkpoint().unwrap().is_empty());
+
+    unsafe {
+        tracker.region_mut().as_mut()[0] = 1;
+        tracker.region_mut().as_mut()[3 * PAGE + 10] = 2;
+    }
+    assert_eq!(tracker.dirty_pages().unwrap(), vec![0, 3]);
+    assert_eq!(tracker.checkpoint().unwrap(), vec![0, 3]);
+
+    unsafe {
+        tracker.region_mut().as_mut()[PAGE] = 3;
+        tracker.region_mut().as_mut()[PAGE + 1] = 4;
+    }
+    assert_eq!(tracker.checkpoint().unwrap(), vec![1]);
+    assert!(tracker.checkpoint().unwrap().is_empty());
+    assert_eq!(unsafe { tracker.region().as_ref()[3 * PAGE + 10] }, 2);
+}
+
+#[test]
+fn write_protect_tracker_reports_pages_written_since_checkpoint() {
+    testing::expect_no_fault(|| {
+        let mut tracker = DirtyTracker::new(pages(), DirtyStrategy::WriteProtect).unwrap();
+        assert_eq!(tracker.region().access_rights(), AccessRights::READ);
+        check_checkpoints(&mut tracker);
+
+        let mut region = tracker.into_inner().unwrap();
+        assert_eq!(region.access_rights(), AccessRights::READ_WRITE);
+        unsafe { region.as_mut()[2 * PAGE] = 5 };
+    });
+}
+
+#[test]
+fn soft_dirty_tracker_reports_pages_written_since_checkpoint() {
+    if !probe::soft_dirty_supported() {
+        let result = DirtyTracker::new(pages(), DirtyStrategy::SoftDirty);
+        assert!(matches!(result, Err(MprotectError::SoftDirtyUnsupported)));
+        return;
+    }
+    let mut tracker = DirtyTracker::new(pages(), DirtyStrategy::SoftDirty).unwrap();
+    let mut other = DirtyTracker::new(pages(), DirtyStrategy::SoftDirty).unwrap();
+    unsafe { other.region_mut().as_mut()[2 * PAGE] = 1 };
+    check_checkpoints(&mut tracker);
+    // This tracker's checkpoints reset the kernel's bits, but the other tracker's
+    // are saved first.
+    assert_eq!(other.dirty_pages().unwrap(), vec![2]);
+    assert_eq!(other.checkpoint().unwrap(), vec![2]);
+    assert!(other.dirty_pages().unwrap().is_empty());
+}
+
+/// Page that `recover_lazy_page` makes accessible.
+static LAZY_PAGE: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+extern "C" fn recover_lazy_page(_signal: i32, _info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    let page = LAZY_PAGE.load(std::sync::atomic::Ordering::Relaxed) as *mut libc::c_void;
+    unsafe { libc::mprotect(page, PAGE, libc::PROT_READ | libc::PROT_WRITE) };
+}
+
+#[test]
+fn unrelated_faults_are_chained_without_disabling_tracking() {
+    testing::expect_no_fault(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = recover_lazy_page as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigaction(libc::SIGSEGV, &action, std::ptr::null_mut());
+
+        let lazy = UnsafeProtectedRegion::<allocator::Mmap, [u8; PAGE]>::new_initialized([0; PAGE], AccessRights::NONE).unwrap();
+        LAZY_PAGE.store(lazy.ptr() as usize, std::sync::atomic::Ordering::Relaxed);
+        let mut tracker = DirtyTracker::new(pages(), DirtyStrategy::WriteProtect).unwrap();
+
+        // Handled by the previous handler, which makes the page accessible.
+        (lazy.ptr() as *mut u8).write_volatile(1);
+        // Still handled by the tracker.
+        tracker.region_mut().as_mut()[PAGE] = 1;
+        assert_eq!(tracker.checkpoint().unwrap(), vec![1]);
+        tracker.region_mut().as_mut()[2 * PAGE] = 1;
+        assert_eq!(tracker.checkpoint().unwrap(), vec![2]);
+    });
+}
+
+#[test]
+fn replaced_fault_handler_is_reinstalled_by_new_trackers() {
+    testing::expect_no_fault(|| unsafe {
+        let mut first = DirtyTracker::new(pages(), DirtyStrategy::WriteProtect).unwrap();
+
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = recover_lazy_page as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigaction(libc::SIGSEGV, &action, std::ptr::null_mut());
+
+        let lazy = UnsafeProtectedRegion::<allocator::Mmap, [u8; PAGE]>::new_initialized([0; PAGE], AccessRights::NONE).unwrap();
+        LAZY_PAGE.store(lazy.ptr() as usize, std::sync::atomic::Ordering::Relaxed);
+        let mut second = DirtyTracker::new(pages(), DirtyStrategy::WriteProtect).unwrap();
+
+        // Both trackers record writes again, and the replacing handler is chained.
+        first.region_mut().as_mut()[PAGE] = 1;
+        assert_eq!(first.checkpoint().unwrap(), vec![1]);
+        second.region_mut().as_mut()[3 * PAGE] = 1;
+        assert_eq!(second.checkpoint().unwrap(), vec![3]);
+        (lazy.ptr() as *mut u8).write_volatile(1);
+    });
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Tracking does not make a read-only region writable.
+    fn write_to_tracked_read_only_region_faults() {
+        let region = UnsafeProtectedRegion::<allocator::Mmap, Pages>::new_initialized([0; 4 * PAGE], AccessRights::READ).unwrap();
+        let mut tracker = DirtyTracker::new(region, DirtyStrategy::WriteProtect).unwrap();
+        unsafe { tracker.region_mut().as_mut()[0] = 1 };
+    }
+}
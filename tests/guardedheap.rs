@@ -0,0 +1,75 @@
+use std::alloc::{ GlobalAlloc, Layout };
+use std::time::Duration;
+
+use mprotect_rs::*;
+use mprotect_rs::testing::{ self, SEGV_ACCERR };
+
+#[global_allocator]
+static HEAP: GuardedHeap = GuardedHeap::new();
+
+#[test]
+fn allocations_end_at_a_guard_page() {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+    for (size, align) in [(1, 1), (100, 8), (4096, 16), (5000, 4096)] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { HEAP.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!((ptr as usize).is_multiple_of(align));
+        // The allocation ends at most `align - 1` bytes before the page boundary.
+        assert!((ptr as usize + size).next_multiple_of(page_size) - (ptr as usize + size) < align);
+        unsafe {
+            ptr.write_bytes(0xa5, size);
+            HEAP.dealloc(ptr, layout);
+        }
+    }
+    assert!(unsafe { HEAP.alloc(Layout::from_size_align(8, page_size * 2).unwrap()) }.is_null());
+
+    let mut values: Vec<u64> = (0..10).collect();
+    values.extend(10..10_000);
+    assert_eq!(values.iter().sum::<u64>(), 9_999 * 10_000 / 2);
+}
+
+#[test]
+fn heap_overflow_is_reported() {
+    let fault = testing::expect_signal(libc::SIGSEGV, SEGV_ACCERR, || {
+        let mut buffer = Vec::<u8>::with_capacity(100);
+        unsafe { buffer.as_mut_ptr().add(100).write_volatile(1) };
+    });
+    assert!(matches!(fault.heap, Some(HeapFault::Overflow { size: 100, .. })), "{:?}", fault);
+}
+
+#[test]
+fn use_after_free_is_reported() {
+    let fault = testing::expect_signal(libc::SIGSEGV, SEGV_ACCERR, || {
+        let boxed = Box::new(42u64);
+        let dangling = &*boxed as *const u64;
+        drop(boxed);
+        unsafe { dangling.read_volatile() };
+    });
+    assert_eq!(fault.heap.map(|heap| matches!(heap, HeapFault::UseAfterFree { size: 8, .. })), Some(true), "{:?}", fault);
+}
+
+#[test]
+fn quarantine_is_bounded() {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+    let layout = Layout::from_size_align(page_size, 1).unwrap();
+
+    let heap = GuardedHeap::new().with_quarantine_size(4 * page_size);
+    for _ in 0..3 {
+        unsafe { heap.dealloc(heap.alloc(layout), layout) };
+    }
+    // Each allocation is one data page and one guard page.
+    assert_eq!(heap.quarantined_bytes(), 4 * page_size);
+
+    let heap = GuardedHeap::new().with_quarantine_time(Duration::ZERO);
+    unsafe { heap.dealloc(heap.alloc(layout), layout) };
+    std::thread::sleep(Duration::from_millis(1));
+    unsafe { heap.dealloc(heap.alloc(layout), layout) };
+    assert_eq!(heap.quarantined_bytes(), 2 * page_size);
+    heap.flush_quarantine();
+    assert_eq!(heap.quarantined_bytes(), 0);
+
+    let heap = GuardedHeap::new().with_quarantine_size(0);
+    unsafe { heap.dealloc(heap.alloc(layout), layout) };
+    assert_eq!(heap.quarantined_bytes(), 0);
+}
@@ -0,0 +1,83 @@
+use mprotect_rs::*;
+use mprotect_rs::signals::PkruState;
+use mprotect_rs::testing::{ SEGV_ACCERR, SEGV_PKUERR };
+
+const PAGE: usize = 4096;
+
+fn pool() -> LinearMemoryPool {
+    LinearMemoryPool::new(LinearMemoryConfig { slots: 4, max_memory_size: 4 * PAGE, guard_size: PAGE, stripes: 2 }).unwrap()
+}
+
+#[test]
+fn slots_are_striped_round_robin() {
+    let pool = pool();
+    assert_eq!(pool.stripes(), 2);
+    assert_eq!(pool.stride(), 5 * PAGE);
+    assert_eq!(pool.effective_guard_size(), 6 * PAGE);
+
+    let memories: Vec<_> = (0..4).map(|_| pool.allocate(PAGE).unwrap()).collect();
+    assert!(matches!(pool.allocate(PAGE), Err(MprotectError::PoolExhausted)));
+    assert_ne!(memories[0].pkey(), memories[1].pkey());
+    assert_eq!(memories[0].pkey(), memories[2].pkey());
+    assert_eq!(memories[1].ptr() as usize - memories[0].ptr() as usize, pool.stride());
+    drop(memories);
+    assert_eq!(pool.available(), 4);
+}
+
+#[test]
+fn activated_memory_grows_and_is_reset_when_reallocated() {
+    let pool = pool();
+    let mut memory = pool.allocate(PAGE).unwrap();
+    let key = memory.pkey().unwrap();
+    memory.grow(3 * PAGE).unwrap();
+    assert_eq!(memory.len(), 4 * PAGE);
+    assert!(matches!(memory.grow(1), Err(MprotectError::MemoryLimitExceeded(len)) if len == 5 * PAGE));
+
+    {
+        let _active = memory.activate();
+        assert_eq!(unsafe { PkruState::current() }.access(key), PkeyAccessRights::EnableAccessWrite);
+        unsafe { memory.ptr().add(4 * PAGE - 1).write(7) };
+    }
+    assert_eq!(unsafe { PkruState::current() }.access(key), PkeyAccessRights::DisableAccess);
+
+    drop(memory);
+    let memory = pool.allocate(4 * PAGE).unwrap();
+    assert_eq!(memory.slot(), 0);
+    let _active = memory.activate();
+    assert_eq!(unsafe { memory.ptr().add(4 * PAGE - 1).read() }, 0);
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_PKUERR;
+    /// An overrun into the neighbouring slot hits a key that is not activated.
+    fn access_to_neighbouring_memory_faults() {
+        let pool = pool();
+        let first = pool.allocate(PAGE).unwrap();
+        let second = pool.allocate(PAGE).unwrap();
+        let _active = first.activate();
+        unsafe { second.ptr().read_volatile() };
+    }
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Pages past the current size of a memory are not committed.
+    fn access_past_memory_size_faults() {
+        let pool = pool();
+        let memory = pool.allocate(PAGE).unwrap();
+        let _active = memory.activate();
+        unsafe { memory.ptr().add(PAGE).write(1) };
+    }
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// The reservation's tail guards the last slot as far as the other stripes guard the rest.
+    fn overrun_from_the_last_slot_faults() {
+        let pool = pool();
+        let memories: Vec<_> = (0..4).map(|_| pool.allocate(4 * PAGE).unwrap()).collect();
+        let last = &memories[3];
+        let _active = last.activate();
+        unsafe { last.ptr().add(4 * PAGE + pool.effective_guard_size() - 1).write_volatile(1) };
+    }
+}
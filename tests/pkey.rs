@@ -0,0 +1,182 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::{ self, SEGV_ACCERR, SEGV_PKUERR };
+
+#[test]
+fn mmap_region_is_accessible_through_enabled_pkey() {
+    testing::expect_no_fault(|| unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+
+        *region.as_mut() = 42;
+        assert_eq!(*region.as_ref(), 42);
+        assert_eq!(region.pkey(), Some(pkey.key()));
+    });
+}
+
+#[test]
+fn disable_write_still_allows_reads() {
+    testing::expect_no_fault(|| unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+        *region.as_mut() = 42;
+
+        pkey.set_access_rights(PkeyAccessRights::DisableWrite).unwrap();
+        assert_eq!(pkey.get_access_rights(), PkeyAccessRights::DisableWrite);
+        assert_eq!(*region.as_ref(), 42);
+    });
+}
+
+#[test]
+fn jmalloc_region_is_accessible_through_enabled_pkey() {
+    testing::expect_no_fault(|| unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let mut region = UnsafeProtectedRegion::<allocator::Jmalloc, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+
+        *region.as_mut() = 100;
+        assert_eq!(*region.as_ref(), 100);
+    });
+}
+
+#[test]
+fn region_can_be_moved_to_another_pkey() {
+    testing::expect_no_fault(|| unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let other = PKey::new(PkeyAccessRights::DisableWrite).unwrap();
+        let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+        *region.as_mut() = 42;
+
+        other.associate(&region, AccessRights::READ).unwrap();
+        assert_eq!(region.pkey(), Some(other.key()));
+        assert_eq!(*region.as_ref(), 42);
+    });
+}
+
+#[test]
+fn write_through_read_only_pte_faults() {
+    let fault = testing::expect_signal(libc::SIGSEGV, SEGV_ACCERR, || unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ).unwrap();
+
+        *region.as_mut() = 84;
+    });
+    assert_eq!(fault.pkey, None);
+}
+
+#[test]
+fn write_through_disable_write_pkey_faults() {
+    let fault = testing::expect_signal(libc::SIGSEGV, SEGV_PKUERR, || unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+        pkey.set_access_rights(PkeyAccessRights::DisableWrite).unwrap();
+
+        *region.as_mut() = 84;
+    });
+    assert!(fault.pkey.is_some_and(|key| key != 0));
+}
+
+#[test]
+fn read_through_disable_access_pkey_faults() {
+    let fault = testing::expect_signal(libc::SIGSEGV, SEGV_PKUERR, || unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+        pkey.set_access_rights(PkeyAccessRights::DisableAccess).unwrap();
+
+        std::ptr::read_volatile(region.ptr());
+    });
+    assert!(fault.pkey.is_some_and(|key| key != 0));
+}
+
+#[test]
+fn shared_pkey_is_freed_by_last_handle() {
+    testing::expect_no_fault(|| unsafe {
+        let region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        let shared = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap().into_shared();
+        let key = shared.key();
+        let other = shared.clone();
+        assert_eq!(other.handle_count(), 2);
+
+        drop(shared);
+        other.associate(&region, AccessRights::READ_WRITE).unwrap();
+        other.disassociate(&region, AccessRights::READ_WRITE).unwrap();
+
+        other.free().unwrap();
+        let ret = libc::syscall(libc::SYS_pkey_mprotect, region.ptr(), region.len(), libc::PROT_READ, key);
+        assert_eq!(ret, -1, "pkey {} is still allocated after the last handle was freed", key);
+    });
+}
+
+#[test]
+fn dropping_pkey_retags_regions_to_default_key() {
+    testing::expect_no_fault(|| unsafe {
+        let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+        assert_eq!(pkey.associated_regions(), 1);
+        drop(pkey);
+
+        assert_eq!(region.pkey(), None);
+        assert_eq!(region.access_rights(), AccessRights::READ_WRITE);
+        assert!(region.verify().unwrap().is_empty());
+
+        // The next key is likely the same number; its rights must not reach the region.
+        let _next = PKey::new(PkeyAccessRights::DisableAccess).unwrap();
+        *region.as_mut() = 42;
+        assert_eq!(*region.as_ref(), 42);
+    });
+}
+
+#[test]
+fn dropped_region_is_no_longer_tracked_by_pkey() {
+    testing::expect_no_fault(|| unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        let kept = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+        pkey.associate(&kept, AccessRights::READ).unwrap();
+        assert_eq!(pkey.associated_regions(), 2);
+
+        drop(region);
+        assert_eq!(pkey.associated_regions(), 1);
+        pkey.disassociate(&kept, AccessRights::READ).unwrap();
+        assert_eq!(pkey.associated_regions(), 0);
+        pkey.free().unwrap();
+    });
+}
+
+#[test]
+fn reallocating_a_key_with_stale_pages_is_detected() {
+    testing::expect_no_fault(|| unsafe {
+        let region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+
+        // Free the key behind the crate's back, leaving the region tagged with it.
+        let key = pkey.key();
+        std::mem::forget(pkey);
+        assert_eq!(libc::syscall(libc::SYS_pkey_free, key), 0);
+
+        PKey::set_stale_key_check(true);
+        match PKey::new(PkeyAccessRights::EnableAccessWrite) {
+            Err(MprotectError::StalePkeyReuse(stale)) => assert_eq!(stale, key),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("stale key {} was handed out again", key),
+        }
+
+        // Once the stale pages are gone, the key is handed out after every other one.
+        drop(region);
+        let mut keys = Vec::new();
+        while let Ok(pkey) = PKey::new(PkeyAccessRights::DisableWrite) {
+            keys.push(pkey);
+        }
+        let reclaimed = keys.last().unwrap();
+        assert_eq!(reclaimed.key(), key);
+        assert_eq!(reclaimed.get_access_rights(), PkeyAccessRights::DisableWrite);
+    });
+}
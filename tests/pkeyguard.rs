@@ -0,0 +1,344 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::{ self, SEGV_PKUERR };
+
+#[test]
+fn scoped_rights_allow_reads_and_writes() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+        let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+
+        {
+            let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+            let mut value = rw.mut_ref_guard().unwrap();
+            *value = 42;
+            *value = 84;
+            drop(value);
+            assert_eq!(*rw.ref_guard().unwrap(), 84);
+        }
+        {
+            let ro = associated.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+            assert_eq!(*ro.ref_guard().unwrap(), 84);
+        }
+    });
+}
+
+#[test]
+fn nested_scopes_over_two_regions() {
+    testing::expect_no_fault(|| {
+        let mut first = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let mut second = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+        let mut assoc_first = pkey.associate::<PkeyPermissions::NoAccess>(&mut first).unwrap();
+        let mut assoc_second = pkey.associate::<PkeyPermissions::NoAccess>(&mut second).unwrap();
+
+        {
+            let rw = assoc_first.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+            let mut value = rw.mut_ref_guard().unwrap();
+            {
+                let rw2 = assoc_second.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+                assert_eq!(*rw2.mut_ref_guard().unwrap(), 0);
+            }
+            {
+                let ro2 = assoc_second.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+                assert_eq!(*ro2.ref_guard().unwrap(), 0);
+            }
+            *value = 168;
+        }
+        {
+            let rw = assoc_first.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+            assert_eq!(*rw.ref_guard().unwrap(), 168);
+        }
+    });
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_PKUERR;
+    /// The borrow checker forbids writing through a read guard; bypassing it with
+    /// unsafe code is caught by the protection key.
+    fn unsafe_write_in_read_only_scope_faults() {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+        let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+
+        {
+            let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+            *rw.mut_ref_guard().unwrap() = 123;
+        }
+        {
+            let ro = associated.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+            let value = ro.ref_guard().unwrap();
+            assert_eq!(*value, 123);
+            unsafe { (value.ptr() as *mut u32).write_volatile(789) };
+        }
+    }
+}
+
+#[test]
+fn scopes_on_one_thread_do_not_affect_another() {
+    testing::expect_no_fault(|| {
+        let pkey = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::NoAccess).unwrap();
+        let barrier = std::sync::Barrier::new(2);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+                let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+                let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+                *rw.mut_ref_guard().unwrap() = 1;
+                barrier.wait();
+                // The other thread now holds a read-only scope on the same key.
+                barrier.wait();
+                assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::EnableAccessWrite);
+                *rw.mut_ref_guard().unwrap() = 2;
+            });
+            s.spawn(|| {
+                let mut region = RegionGuard::<allocator::Mmap, u32>::new(7, AccessPermissions::ReadWrite).unwrap();
+                barrier.wait();
+                let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+                assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableAccess);
+                let ro = associated.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+                assert_eq!(*ro.ref_guard().unwrap(), 7);
+                barrier.wait();
+                assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableWrite);
+            });
+        });
+    });
+}
+
+#[test]
+fn revocation_reaches_other_threads() {
+    testing::expect_no_fault(|| {
+        let pkey = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::ReadWrite).unwrap();
+        let barrier = std::sync::Barrier::new(2);
+
+        std::thread::scope(|s| {
+            let worker = s.spawn(|| {
+                unsafe { pkey.pkey().set_access_rights(PkeyAccessRights::EnableAccessWrite).unwrap() };
+                barrier.wait();
+                // Revoked while blocked here.
+                barrier.wait();
+                unsafe { pkey.pkey().get_access_rights() }
+            });
+            barrier.wait();
+            assert!(pkey.revoke_all_threads(Revocation::Running).unwrap() >= 1);
+            assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableAccess);
+            barrier.wait();
+            assert_eq!(worker.join().unwrap(), PkeyAccessRights::DisableAccess);
+        });
+        assert!(!pkey.is_revoked());
+    });
+}
+
+#[test]
+fn late_handler_of_a_timed_out_revocation_is_ignored() {
+    testing::expect_no_fault(|| {
+        let first = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::ReadWrite).unwrap();
+        let second = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::ReadWrite).unwrap();
+        let barrier = std::sync::Barrier::new(2);
+
+        std::thread::scope(|s| {
+            let worker = s.spawn(|| unsafe {
+                let mut blocked: libc::sigset_t = std::mem::zeroed();
+                libc::sigemptyset(&mut blocked);
+                libc::sigaddset(&mut blocked, revocation_signal());
+                libc::pthread_sigmask(libc::SIG_BLOCK, &blocked, std::ptr::null_mut());
+                barrier.wait();
+                // The first revocation timed out; let both signals in during the second.
+                barrier.wait();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                libc::pthread_sigmask(libc::SIG_UNBLOCK, &blocked, std::ptr::null_mut());
+                (first.pkey().get_access_rights(), second.pkey().get_access_rights())
+            });
+            barrier.wait();
+            assert!(matches!(first.revoke_all_threads(Revocation::Running), Err(MprotectError::RevocationTimedOut(1))));
+            barrier.wait();
+            assert_eq!(second.revoke_all_threads(Revocation::Running).unwrap(), 1);
+            let (first_rights, second_rights) = worker.join().unwrap();
+            assert_eq!(first_rights, PkeyAccessRights::EnableAccessWrite);
+            assert_eq!(second_rights, PkeyAccessRights::DisableAccess);
+        });
+    });
+}
+
+#[test]
+fn threads_exiting_during_revocation_are_not_waited_for() {
+    testing::expect_no_fault(|| {
+        let pkey = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::ReadWrite).unwrap();
+        for _ in 0..20 {
+            let workers: Vec<_> = (0..4).map(|_| std::thread::spawn(std::thread::yield_now)).collect();
+            let started = std::time::Instant::now();
+            let updated = pkey.revoke_all_threads(Revocation::Running).unwrap();
+            assert!(updated <= 4 && started.elapsed() < std::time::Duration::from_millis(500));
+            workers.into_iter().for_each(|worker| worker.join().unwrap());
+        }
+    });
+}
+
+#[test]
+fn permanent_revocation_refuses_new_scopes() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+        pkey.revoke_all_threads(Revocation::Permanent).unwrap();
+
+        let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+        let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+        assert!(matches!(rw.mut_ref_guard(), Err(PkeyGuardError::Revoked)));
+        assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableAccess);
+
+        let later = std::thread::scope(|s| s.spawn(|| unsafe { pkey.pkey().get_access_rights() }).join().unwrap());
+        assert_eq!(later, PkeyAccessRights::DisableAccess);
+    });
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_PKUERR;
+    /// A raw pointer obtained inside a read-write scope on another thread stops working
+    /// once the key is revoked.
+    fn revoked_thread_faults_on_access() {
+        let pkey = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::NoAccess).unwrap();
+        let barrier = std::sync::Barrier::new(2);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+                let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+                let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+                let ptr = &mut *rw.mut_ref_guard().unwrap() as *mut u32;
+                unsafe { ptr.write_volatile(1) };
+                barrier.wait();
+                barrier.wait();
+                unsafe { ptr.write_volatile(2) };
+            });
+            barrier.wait();
+            pkey.revoke_all_threads(Revocation::Running).unwrap();
+            barrier.wait();
+        });
+    }
+}
+
+#[test]
+fn rights_are_restored_after_a_caught_panic() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+        let mut associated = pkey.associate::<PkeyPermissions::ReadOnly>(&mut region).unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+            let mut value = rw.mut_ref_guard().unwrap();
+            *value = 1;
+            panic!("inside a read-write scope");
+        }));
+        assert!(result.is_err());
+
+        // The innermost live scope is the read-only handler.
+        assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableWrite);
+        drop(associated);
+        assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableAccess);
+        assert!(region.is_poisoned());
+    });
+}
+
+#[test]
+fn dropping_a_handler_discards_leaked_scopes() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let pkey = PkeyGuard::new(PkeyPermissions::ReadOnly).unwrap();
+        let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+
+        std::mem::forget(associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap());
+        assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::EnableAccessWrite);
+        {
+            let ro = associated.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+            assert_eq!(*ro.ref_guard().unwrap(), 0);
+        }
+
+        drop(associated);
+        assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableWrite);
+    });
+}
+
+#[test]
+fn pkey_scope_does_not_widen_page_rights() {
+    let mut region = RegionGuard::<allocator::Mmap, u32>::new(7, AccessPermissions::ReadOnly).unwrap();
+    let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+    let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+
+    {
+        let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+        assert!(matches!(
+            rw.mut_ref_guard(),
+            Err(PkeyGuardError::RegionGuardError(GuardError::InvalidAccessRights))
+        ));
+        assert_eq!(*rw.ref_guard().unwrap(), 7);
+    }
+    drop(associated);
+    assert_eq!(region.access_rights(), AccessRights::READ);
+}
+
+#[test]
+fn typed_scope_accesses_follow_effective_rights() {
+    let pkey = PkeyGuard::<allocator::Mmap, u32>::new(PkeyPermissions::NoAccess).unwrap();
+
+    let mut writable = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::ReadWrite).unwrap();
+    {
+        let mut rw = pkey.associate_typed::<PkeyPermissions::ReadWrite, _>(&mut writable).unwrap();
+        *rw.mut_ref_guard().unwrap() = 42;
+        let ro = rw.set_access_rights::<PkeyPermissions::ReadOnly>().unwrap();
+        assert_eq!(*ro.ref_guard().unwrap(), 42);
+    }
+
+    // `ReadWrite` over read-only pages is effectively read-only: reads work and
+    // `mut_ref_guard` does not exist for this type.
+    let mut readable = writable.into_read_only().unwrap();
+    let rw = pkey.associate_typed::<PkeyPermissions::ReadWrite, _>(&mut readable).unwrap();
+    assert_eq!(*rw.ref_guard().unwrap(), 42);
+}
+
+#[test]
+fn erased_guard_protects_regions_of_different_types() {
+    let pkey = ErasedPkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+    let mut counter = RegionGuard::<allocator::Mmap, u64>::new(1, AccessPermissions::ReadWrite).unwrap();
+    let mut buffer = RegionGuard::<allocator::Mmap, [u8; 16]>::new([0; 16], AccessPermissions::ReadWrite).unwrap();
+    let mut typed = TypedRegion::<allocator::Mmap, u16, _>::new(3, AccessPermissions::ReadWrite).unwrap();
+
+    {
+        let mut counter = pkey.associate::<_, _, PkeyPermissions::NoAccess>(&mut counter).unwrap();
+        let mut buffer = pkey.associate::<_, _, PkeyPermissions::NoAccess>(&mut buffer).unwrap();
+
+        let rw = counter.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+        *rw.mut_ref_guard().unwrap() += 1;
+        drop(rw);
+        let rw = buffer.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+        rw.mut_ref_guard().unwrap()[0] = 0xaa;
+    }
+    {
+        let mut scope = pkey.associate_typed::<_, _, PkeyPermissions::ReadWrite, _>(&mut typed).unwrap();
+        *scope.mut_ref_guard().unwrap() += 1;
+    }
+
+    let key = pkey.pkey().key();
+    assert_eq!(unsafe { counter.get_region() }.pkey(), Some(key));
+    assert_eq!(unsafe { buffer.get_region() }.pkey(), Some(key));
+    assert_eq!(unsafe { typed.get_region() }.pkey(), Some(key));
+}
+
+#[test]
+fn scopes_downgrade_and_upgrade_in_place() {
+    let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+    let pkey = PkeyGuard::new(PkeyPermissions::NoAccess).unwrap();
+    let mut associated = pkey.associate::<PkeyPermissions::NoAccess>(&mut region).unwrap();
+
+    {
+        let rw = associated.set_access_rights::<PkeyPermissions::ReadWrite>().unwrap();
+        *rw.mut_ref_guard().unwrap() = 9;
+        let ro = rw.downgrade();
+        assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableWrite);
+        assert_eq!(*ro.ref_guard().unwrap(), 9);
+        let rw = ro.upgrade();
+        *rw.mut_ref_guard().unwrap() = 10;
+    }
+    assert_eq!(unsafe { pkey.pkey().get_access_rights() }, PkeyAccessRights::DisableAccess);
+}
@@ -0,0 +1,70 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::{ self, SEGV_ACCERR, SEGV_PKUERR };
+
+// Every test runs in a child process, since the quarantine is process-wide.
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn dropped_region() -> usize {
+    let region = UnsafeProtectedRegion::<allocator::Mmap, u64>::new_initialized(7, AccessRights::READ_WRITE).unwrap();
+    region.ptr() as usize
+}
+
+#[test]
+fn dropped_regions_are_retagged_until_the_budget_is_exceeded() {
+    testing::expect_no_fault(|| {
+        quarantine::enable(2 * page_size());
+        assert!(quarantine::is_enabled());
+        let regions: Vec<usize> = (0..3).map(|_| dropped_region()).collect();
+
+        assert_eq!(quarantine::quarantined_bytes(), 2 * page_size());
+        assert!(!quarantine::contains(regions[0]));
+        assert!(quarantine::contains(regions[1]) && quarantine::contains(regions[2]));
+
+        let mapping = smaps::read_self().unwrap().into_iter().find(|m| m.overlaps(regions[2], regions[2] + 1)).unwrap();
+        assert_eq!(mapping.access_rights, AccessRights::NONE);
+        if let Some(dead_pkey) = quarantine::dead_pkey() {
+            assert_eq!(mapping.protection_key, Some(dead_pkey));
+        }
+
+        quarantine::flush();
+        assert_eq!(quarantine::quarantined_bytes(), 0);
+        assert!(!quarantine::contains(regions[2]));
+    });
+}
+
+#[test]
+fn disabled_quarantine_releases_immediately() {
+    testing::expect_no_fault(|| {
+        quarantine::enable(16 * page_size());
+        let quarantined = dropped_region();
+        assert!(quarantine::contains(quarantined));
+
+        quarantine::disable();
+        assert!(!quarantine::is_enabled());
+        assert_eq!(quarantine::dead_pkey(), None);
+        assert!(!quarantine::contains(quarantined));
+
+        let released = dropped_region();
+        assert!(!quarantine::contains(released));
+        assert_eq!(quarantine::quarantined_bytes(), 0);
+    });
+}
+
+#[test]
+fn use_after_drop_faults() {
+    let fault = testing::expect_fault(|| {
+        quarantine::enable(16 * page_size());
+        let dangling = dropped_region() as *const u64;
+        let _reused = dropped_region();
+        unsafe { dangling.read_volatile() };
+    });
+    assert_eq!(fault.signal, libc::SIGSEGV);
+    // The dead key is checked before the page protection.
+    match fault.pkey {
+        Some(_) => assert_eq!(fault.code, SEGV_PKUERR),
+        None => assert_eq!(fault.code, SEGV_ACCERR),
+    }
+}
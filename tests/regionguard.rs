@@ -0,0 +1,340 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::{ self, SEGV_ACCERR };
+
+#[test]
+fn read_and_write_guards_grant_access_to_no_access_region() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+
+        assert_eq!(*region.read().unwrap(), 0);
+        *region.write().unwrap() = 42;
+        assert_eq!(*region.read().unwrap(), 42);
+
+        let read_guard = region.read().unwrap();
+        assert_eq!(*read_guard, 42);
+        drop(read_guard);
+
+        let mut write_guard = region.write().unwrap();
+        *write_guard = 84;
+        *write_guard = 168;
+        assert_eq!(*write_guard, 168);
+    });
+}
+
+#[test]
+fn with_gives_closure_access() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+
+        assert_eq!(region.read().unwrap().with(|v| *v).unwrap(), 0);
+        region.write().unwrap().with(|v| *v = 256).unwrap();
+        assert_eq!(region.read().unwrap().with(|v| *v).unwrap(), 256);
+    });
+}
+
+#[test]
+fn deref_with_explicit_permissions() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+
+        assert_eq!(*region.deref(AccessPermissions::ReadOnly).unwrap(), 0);
+        *region.deref_mut(AccessPermissions::ReadWrite).unwrap() = 512;
+        assert_eq!(*region.deref(AccessPermissions::ReadOnly).unwrap(), 512);
+    });
+}
+
+#[test]
+fn invalidated_guard_rejects_access() {
+    testing::expect_no_fault(|| {
+        let region = RegionGuard::<allocator::Jmalloc, u32>::new(7, AccessPermissions::ReadWrite).unwrap();
+        let guard = region.read().unwrap();
+        region.invalidate();
+
+        assert!(!guard.is_valid());
+        assert!(matches!(guard.with(|v| *v), Err(GuardError::InvalidGeneration)));
+    });
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Rights granted by a write guard are revoked when it drops, so a pointer
+    /// kept past the guard faults.
+    fn write_after_guard_drop_faults() {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+        let ptr = {
+            let mut guard = region.write().unwrap();
+            &mut *guard as *mut u32
+        };
+        unsafe { ptr.write_volatile(1) };
+    }
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    fn write_through_read_guard_faults() {
+        let region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadOnly).unwrap();
+        let guard = region.read().unwrap();
+        unsafe { (guard.ptr() as *mut u32).write_volatile(1) };
+    }
+}
+
+#[test]
+fn write_guard_dropped_by_panic_poisons_region() {
+    testing::expect_no_fault(|| {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut guard = region.write().unwrap();
+            *guard = 5;
+            panic!("half-way through an update");
+        }));
+        assert!(result.is_err());
+
+        assert!(region.is_poisoned());
+        assert_eq!(region.access_rights(), AccessRights::NONE);
+        assert!(matches!(region.read(), Err(GuardError::Poisoned)));
+        assert!(matches!(region.write(), Err(GuardError::Poisoned)));
+
+        region.clear_poison();
+        assert_eq!(*region.read().unwrap(), 5);
+    });
+}
+
+#[test]
+fn read_guard_dropped_by_panic_does_not_poison_region() {
+    testing::expect_no_fault(|| {
+        let region = RegionGuard::<allocator::Mmap, u32>::new(3, AccessPermissions::NoAccess).unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let guard = region.read().unwrap();
+            assert_eq!(*guard, 3);
+            panic!("while reading");
+        }));
+        assert!(result.is_err());
+
+        assert!(!region.is_poisoned());
+        assert_eq!(region.access_rights(), AccessRights::NONE);
+        assert_eq!(*region.read().unwrap(), 3);
+    });
+}
+
+#[test]
+fn read_access_lasts_until_the_last_read_guard_drops() {
+    let region = RegionGuard::<allocator::Mmap, u32>::new(9, AccessPermissions::NoAccess).unwrap();
+
+    let first = region.read().unwrap();
+    let second = region.deref(AccessPermissions::ReadOnly).unwrap();
+    assert_eq!(region.readers(), 2);
+
+    drop(first);
+    assert_eq!(region.access_rights(), AccessRights::READ);
+    assert_eq!(*second, 9);
+
+    drop(second);
+    assert_eq!(region.readers(), 0);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+}
+
+#[test]
+fn invalidated_guards_do_not_release_rights_of_newer_ones() {
+    let mut region = RegionGuard::<allocator::Mmap, u32>::new(1, AccessPermissions::NoAccess).unwrap();
+
+    let stale = region.read().unwrap();
+    region.invalidate();
+    assert_eq!(region.readers(), 0);
+
+    let fresh = region.read().unwrap();
+    let older = region.read().unwrap();
+    drop(stale);
+    assert_eq!(region.readers(), 2);
+    assert_eq!(*fresh, 1);
+
+    region.invalidate();
+    drop(older);
+    let newest = region.read().unwrap();
+    drop(fresh);
+    assert_eq!(*newest, 1);
+    drop(newest);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+
+    *region.write().unwrap() = 2;
+    assert_eq!(region.writers(), 0);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+    assert_eq!(*region.read().unwrap(), 2);
+}
+
+#[test]
+fn invalidated_guards_still_exclude_writers() {
+    let region = RegionGuard::<allocator::Mmap, u32>::new(1, AccessPermissions::NoAccess).unwrap();
+
+    let stale = region.read().unwrap();
+    let value = &*stale;
+    region.invalidate();
+    let Err(refused) = RegionGuard::upgrade(region.read().unwrap()) else { panic!("upgraded beside a live stale reader") };
+    assert!(matches!(refused.error, GuardError::OtherGuardsAlive));
+    assert_eq!(*value, 1);
+    drop(stale);
+
+    let mut writer = RegionGuard::upgrade(refused.guard).unwrap();
+    region.revoke().unwrap();
+    assert!(matches!(region.read(), Err(GuardError::OtherGuardsAlive)));
+    assert!(matches!(writer.with(|v| *v = 2), Err(GuardError::InvalidGeneration)));
+    drop(writer);
+    assert_eq!(*region.read().unwrap(), 1);
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Dropping the last of several read guards revokes `READ`.
+    fn read_after_last_read_guard_drops_faults() {
+        let region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+        let first = region.read().unwrap();
+        let second = region.read().unwrap();
+        let ptr = unsafe { second.ptr() };
+        drop(second);
+        drop(first);
+        unsafe { ptr.read_volatile() };
+    }
+}
+
+#[test]
+fn revoked_region_is_accessible_again_through_new_guards() {
+    let mut region = RegionGuard::<allocator::Mmap, u32>::new(4, AccessPermissions::ReadWrite).unwrap();
+    let guard = region.read().unwrap();
+
+    region.revoke().unwrap();
+    assert!(!guard.is_valid());
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+    drop(guard);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+
+    *region.write().unwrap() = 5;
+    assert_eq!(region.access_rights(), AccessRights::READ_WRITE);
+    assert_eq!(*region.read().unwrap(), 5);
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// A pointer copied out of a guard stops working once the region is revoked,
+    /// even though the region's default rights allow writing.
+    fn write_through_pointer_after_revoke_faults() {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::ReadWrite).unwrap();
+        let ptr = {
+            let mut guard = region.write().unwrap();
+            &mut *guard as *mut u32
+        };
+        region.revoke().unwrap();
+        unsafe { ptr.write_volatile(1) };
+    }
+}
+
+#[test]
+fn mapped_guards_keep_rights_of_the_original() {
+    let mut region = RegionGuard::<allocator::Mmap, (u32, [u8; 8])>::new((1, [0; 8]), AccessPermissions::NoAccess).unwrap();
+
+    let mut bytes = region.write().unwrap().map(|value| &mut value.1[2..6]);
+    bytes.fill(7);
+    assert!(bytes.is_valid());
+    drop(bytes);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+
+    let first = region.read().unwrap().map(|value| &value.0);
+    assert_eq!(*first, 1);
+    let missing = region.read().unwrap().filter_map(|value| value.1.iter().find(|&&b| b == 9));
+    let guard = missing.err().unwrap();
+    assert_eq!(guard.1[2], 7);
+    drop(guard);
+    assert_eq!(*first, 1);
+    drop(first);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+
+    let stale = region.read().unwrap();
+    region.invalidate();
+    assert!(matches!(stale.try_map(|value| &value.0), Err(GuardError::InvalidGeneration)));
+}
+
+#[test]
+fn split_write_guards_each_keep_the_region_writable() {
+    let mut region = RegionGuard::<allocator::Mmap, [u32; 4]>::new([0; 4], AccessPermissions::NoAccess).unwrap();
+
+    let (mut head, mut tail) = region.write().unwrap().map_split(|values| values.split_at_mut(2));
+    head[0] = 1;
+    drop(head);
+    tail[1] = 4;
+    drop(tail);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+    assert_eq!(*region.read().unwrap(), [1, 0, 0, 4]);
+}
+
+#[test]
+fn downgrade_and_upgrade_switch_rights_in_place() {
+    let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+
+    let mut writer = region.write().unwrap();
+    *writer = 3;
+    let reader = writer.downgrade();
+    assert_eq!(*reader, 3);
+    let mut writer = RegionGuard::upgrade(reader).unwrap();
+    *writer = 4;
+    drop(writer);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+
+    let first = region.read().unwrap();
+    let second = region.read().unwrap();
+    let Err(refused) = RegionGuard::upgrade(first) else { panic!("upgraded with another reader alive") };
+    assert!(matches!(refused.error, GuardError::OtherGuardsAlive));
+    drop(second);
+
+    let mut writer = RegionGuard::upgrade(refused.guard).unwrap();
+    assert!(matches!(region.read(), Err(GuardError::OtherGuardsAlive)));
+    *writer += 1;
+    drop(writer);
+    assert_eq!(*region.read().unwrap(), 5);
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Downgrading the last write guard revokes `WRITE`.
+    fn write_after_downgrade_faults() {
+        let mut region = RegionGuard::<allocator::Mmap, u32>::new(0, AccessPermissions::NoAccess).unwrap();
+        let reader = region.write().unwrap().downgrade();
+        unsafe { (reader.ptr() as *mut u32).write_volatile(1) };
+    }
+}
+
+#[test]
+fn transaction_commits_on_ok_and_rolls_back_on_err() {
+    let mut region = RegionGuard::<allocator::Mmap, Vec<u32>>::new(vec![1, 2], AccessPermissions::NoAccess).unwrap();
+
+    let len = region.transaction(|values| {
+        values.push(3);
+        Ok::<_, ()>(values.len())
+    }).unwrap();
+    assert_eq!(len, 3);
+
+    let result = region.transaction(|values| -> Result<(), _> {
+        values.clear();
+        values.push(9);
+        Err("rejected")
+    });
+    assert!(matches!(result, Err(TransactionError::<&str>::Aborted("rejected"))));
+    assert_eq!(*region.read().unwrap(), vec![1, 2, 3]);
+    assert_eq!(region.access_rights(), AccessRights::NONE);
+}
+
+#[test]
+fn transaction_rolls_back_on_panic() {
+    let mut region = RegionGuard::<allocator::Mmap, [u32; 4]>::new([5; 4], AccessPermissions::ReadOnly).unwrap();
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = region.transaction(|values| -> Result<(), ()> {
+            values[0] = 0;
+            panic!("half-way through an update");
+        });
+    }));
+    assert!(result.is_err());
+
+    assert!(!region.is_poisoned());
+    assert_eq!(region.access_rights(), AccessRights::READ);
+    assert_eq!(*region.read().unwrap(), [5; 4]);
+}
@@ -0,0 +1,109 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::{ SEGV_ACCERR, SEGV_PKUERR };
+
+const PAGE: usize = 4096;
+
+#[test]
+fn committed_pages_are_usable_and_decommit_splits_ranges() {
+    unsafe {
+        let mut region = ReservedRegion::new(1 << 30).unwrap();
+        assert_eq!(region.len(), 1 << 30);
+        assert_eq!(region.committed_len(), 0);
+
+        region.commit(2 * PAGE, 3 * PAGE, AccessRights::READ_WRITE).unwrap();
+        let base = region.ptr();
+        base.add(2 * PAGE).write(1);
+        base.add(3 * PAGE).write(2);
+        base.add(4 * PAGE).write(3);
+
+        region.decommit(3 * PAGE, PAGE).unwrap();
+        let ranges: Vec<_> = region.committed().iter().map(|r| (r.offset(), r.len())).collect();
+        assert_eq!(ranges, vec![(2 * PAGE, PAGE), (4 * PAGE, PAGE)]);
+        assert_eq!(region.committed_len(), 2 * PAGE);
+        assert_eq!(base.add(2 * PAGE).read(), 1);
+        assert_eq!(base.add(4 * PAGE).read(), 3);
+
+        // Recommitted pages start out zeroed.
+        region.commit(3 * PAGE, PAGE, AccessRights::READ).unwrap();
+        assert_eq!(base.add(3 * PAGE).read(), 0);
+        assert_eq!(region.committed_at(3 * PAGE + 100).unwrap().access_rights(), AccessRights::READ);
+    }
+}
+
+#[test]
+fn invalid_ranges_are_rejected() {
+    unsafe {
+        let mut region = ReservedRegion::new(16 * PAGE).unwrap();
+        region.commit(0, 2 * PAGE, AccessRights::READ_WRITE).unwrap();
+
+        for (offset, len) in [(PAGE, PAGE), (100, PAGE), (2 * PAGE, 100), (2 * PAGE, 0), (15 * PAGE, 2 * PAGE), (usize::MAX - PAGE + 1, PAGE)] {
+            let result = region.commit(offset, len, AccessRights::READ_WRITE);
+            assert!(matches!(result, Err(MprotectError::InvalidReservedRange(o, l)) if (o, l) == (offset, len)));
+        }
+        assert!(matches!(region.decommit(16 * PAGE, PAGE), Err(MprotectError::InvalidReservedRange(..))));
+        assert_eq!(region.committed().len(), 1);
+    }
+}
+
+#[test]
+fn committed_ranges_can_be_associated_with_a_pkey() {
+    unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let mut region = ReservedRegion::new(16 * PAGE).unwrap();
+        let committed = region.commit(0, 4 * PAGE, AccessRights::READ_WRITE).unwrap();
+        pkey.associate(committed, AccessRights::READ_WRITE).unwrap();
+        assert_eq!(pkey.associated_regions(), 1);
+
+        // Both pieces left by a decommit keep the key.
+        region.decommit(PAGE, PAGE).unwrap();
+        assert!(region.committed().iter().all(|r| r.pkey() == Some(pkey.key())));
+        assert_eq!(pkey.associated_regions(), 2);
+
+        region.commit(PAGE, PAGE, AccessRights::READ_WRITE).unwrap();
+        assert_eq!(region.committed_at(PAGE).unwrap().pkey(), None);
+        let start = region.ptr() as usize + PAGE;
+        let mapping = smaps::read_self().unwrap().into_iter().find(|m| m.overlaps(start, start + PAGE)).unwrap();
+        assert!(matches!(mapping.protection_key, None | Some(0)));
+        drop(region);
+        assert_eq!(pkey.associated_regions(), 0);
+    }
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Uncommitted pages of a reservation are inaccessible.
+    fn access_to_uncommitted_page_faults() {
+        let mut region = ReservedRegion::new(16 * PAGE).unwrap();
+        unsafe {
+            region.commit(0, PAGE, AccessRights::READ_WRITE).unwrap();
+            region.ptr().add(PAGE).write(1);
+        }
+    }
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// Decommitted pages are inaccessible again.
+    fn access_to_decommitted_page_faults() {
+        let mut region = ReservedRegion::new(16 * PAGE).unwrap();
+        unsafe {
+            region.commit(0, 2 * PAGE, AccessRights::READ_WRITE).unwrap();
+            region.decommit(0, PAGE).unwrap();
+            region.ptr().write(1);
+        }
+    }
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_PKUERR;
+    /// A committed range tagged with a key follows the key's rights.
+    fn access_to_committed_range_with_disabled_pkey_faults() {
+        unsafe {
+            let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+            let mut region = ReservedRegion::new(16 * PAGE).unwrap();
+            pkey.associate(region.commit(0, PAGE, AccessRights::READ_WRITE).unwrap(), AccessRights::READ_WRITE).unwrap();
+            pkey.set_access_rights(PkeyAccessRights::DisableAccess).unwrap();
+            region.ptr().read_volatile();
+        }
+    }
+}
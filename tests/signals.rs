@@ -0,0 +1,120 @@
+use mprotect_rs::*;
+use mprotect_rs::signals::{ self, InterruptedContext, PkruState };
+use mprotect_rs::testing::{ self, SEGV_PKUERR };
+
+use std::sync::atomic::{ AtomicU32, AtomicUsize, Ordering };
+
+static KEY: AtomicU32 = AtomicU32::new(0);
+static ADDR: AtomicUsize = AtomicUsize::new(0);
+static SEEN: AtomicU32 = AtomicU32::new(0);
+
+fn read_region(_signal: i32, _info: &libc::siginfo_t, _context: &mut InterruptedContext) {
+    let value = unsafe { std::ptr::read_volatile(ADDR.load(Ordering::Relaxed) as *const u32) };
+    SEEN.store(value, Ordering::Relaxed);
+}
+
+fn grant_key(_signal: i32, _info: &libc::siginfo_t, context: &mut InterruptedContext) {
+    context.set_key_access(KEY.load(Ordering::Relaxed), PkeyAccessRights::EnableAccessWrite).unwrap();
+}
+
+fn revoke_key(_signal: i32, _info: &libc::siginfo_t, context: &mut InterruptedContext) {
+    context.set_key_access(KEY.load(Ordering::Relaxed), PkeyAccessRights::DisableAccess).unwrap();
+}
+
+/// Allocates a key and a region tagged with it holding `value`, then denies the key.
+unsafe fn denied_region(value: u32) -> (PKey, UnsafeProtectedRegion<allocator::Mmap, u32>) {
+    let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+    let mut region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+    pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+    *region.as_mut() = value;
+    pkey.set_access_rights(PkeyAccessRights::DisableAccess).unwrap();
+    KEY.store(pkey.key(), Ordering::Relaxed);
+    ADDR.store(region.ptr() as usize, Ordering::Relaxed);
+    (pkey, region)
+}
+
+#[test]
+fn pkru_state_edits_single_keys() {
+    let state = PkruState::DENY_ALL.with_access(3, PkeyAccessRights::DisableWrite);
+    assert_eq!(state.access(0), PkeyAccessRights::EnableAccessWrite);
+    assert_eq!(state.access(1), PkeyAccessRights::DisableAccess);
+    assert_eq!(state.access(3), PkeyAccessRights::DisableWrite);
+    assert_eq!(state.with_access(3, PkeyAccessRights::DisableAccess), PkruState::DENY_ALL);
+}
+
+#[test]
+fn handler_runs_with_chosen_pkru() {
+    testing::expect_no_fault(|| unsafe {
+        let (pkey, _region) = denied_region(42);
+        let handler_pkru = PkruState::DENY_ALL.with_access(pkey.key(), PkeyAccessRights::DisableWrite);
+        signals::install_handler(libc::SIGUSR1, handler_pkru, read_region).unwrap();
+
+        libc::raise(libc::SIGUSR1);
+        assert_eq!(SEEN.load(Ordering::Relaxed), 42);
+        // The interrupted code resumes with its own rights.
+        assert_eq!(pkey.get_access_rights(), PkeyAccessRights::DisableAccess);
+    });
+}
+
+#[test]
+fn handler_grants_access_to_resumed_code() {
+    testing::expect_no_fault(|| unsafe {
+        let (pkey, region) = denied_region(7);
+        signals::install_handler(libc::SIGUSR1, PkruState::DENY_ALL, grant_key).unwrap();
+
+        libc::raise(libc::SIGUSR1);
+        assert_eq!(pkey.get_access_rights(), PkeyAccessRights::EnableAccessWrite);
+        assert_eq!(std::ptr::read_volatile(region.ptr()), 7);
+    });
+}
+
+#[test]
+fn set_access_rights_in_handler_is_lost() {
+    fn set_in_handler(_signal: i32, _info: &libc::siginfo_t, _context: &mut InterruptedContext) {
+        unsafe { PkruState::ALLOW_ALL.apply() };
+    }
+    testing::expect_no_fault(|| unsafe {
+        let (pkey, _region) = denied_region(0);
+        signals::install_handler(libc::SIGUSR1, PkruState::DENY_ALL, set_in_handler).unwrap();
+
+        libc::raise(libc::SIGUSR1);
+        assert_eq!(pkey.get_access_rights(), PkeyAccessRights::DisableAccess);
+    });
+}
+
+#[test]
+fn revocation_signal_is_reserved() {
+    fn ignore(_signal: i32, _info: &libc::siginfo_t, _context: &mut InterruptedContext) {}
+    let result = unsafe { signals::install_handler(revocation_signal(), PkruState::DENY_ALL, ignore) };
+    assert!(matches!(result, Err(MprotectError::SignalSetupFailed(libc::EINVAL))));
+}
+
+#[test]
+fn handler_preserves_errno_of_resumed_code() {
+    fn clobber_errno(_signal: i32, _info: &libc::siginfo_t, _context: &mut InterruptedContext) {
+        unsafe { libc::close(-1) };
+    }
+    testing::expect_no_fault(|| unsafe {
+        signals::install_handler(libc::SIGUSR1, PkruState::DENY_ALL, clobber_errno).unwrap();
+        *libc::__errno_location() = libc::EAGAIN;
+        libc::raise(libc::SIGUSR1);
+        assert_eq!(*libc::__errno_location(), libc::EAGAIN);
+    });
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_PKUERR;
+    fn handler_revokes_access_of_resumed_code() {
+        unsafe {
+            let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+            let region = UnsafeProtectedRegion::<allocator::Mmap, u32>::new(AccessRights::READ_WRITE).unwrap();
+            pkey.associate(&region, AccessRights::READ_WRITE).unwrap();
+            KEY.store(pkey.key(), Ordering::Relaxed);
+            signals::install_handler(libc::SIGUSR1, PkruState::DENY_ALL, revoke_key).unwrap();
+
+            std::ptr::read_volatile(region.ptr());
+            libc::raise(libc::SIGUSR1);
+            std::ptr::read_volatile(region.ptr());
+        }
+    }
+}
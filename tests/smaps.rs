@@ -0,0 +1,99 @@
+use mprotect_rs::*;
+
+const SMAPS: &str = "\
+7f3a1c000000-7f3a1c002000 rw-p 00000000 00:00 0
+Size:                  8 kB
+Rss:                   4 kB
+ProtectionKey:         3
+VmFlags: rd wr mr mw me ac
+7f3a1d000000-7f3a1d001000 r-xs 0001a000 fd:01 1048577                    /opt/my app/lib data.so
+Size:                  4 kB
+Rss:                   4 kB
+THPeligible:           0
+ProtectionKey:         0
+VmFlags: rd ex sh mr mw me
+";
+
+#[test]
+fn parse_reads_headers_and_fields() {
+    let mappings = smaps::parse(SMAPS);
+    assert_eq!(mappings.len(), 2);
+
+    let anonymous = &mappings[0];
+    assert_eq!((anonymous.start, anonymous.end), (0x7f3a1c000000, 0x7f3a1c002000));
+    assert_eq!(anonymous.len(), 0x2000);
+    assert_eq!(anonymous.access_rights, AccessRights::READ_WRITE);
+    assert_eq!(anonymous.perms(), "rw-p");
+    assert_eq!((anonymous.device.as_str(), anonymous.inode), ("00:00", 0));
+    assert_eq!(anonymous.pathname, None);
+    assert_eq!((anonymous.size_kb, anonymous.rss_kb), (8, 4));
+    assert_eq!(anonymous.protection_key, Some(3));
+    assert_eq!(anonymous.vm_flags, ["rd", "wr", "mr", "mw", "me", "ac"]);
+
+    let file = &mappings[1];
+    assert!(file.shared);
+    assert_eq!(file.access_rights, AccessRights::READ.add(AccessRights::EXEC));
+    assert_eq!(file.offset, 0x1a000);
+    assert_eq!(file.inode, 1048577);
+    assert_eq!(file.pathname.as_deref(), Some("/opt/my app/lib data.so"));
+    assert_eq!(file.protection_key, Some(0));
+    assert!(file.vm_flags.iter().any(|flag| flag == "sh"));
+}
+
+#[test]
+fn parse_without_protection_keys() {
+    let text = "00400000-00401000 r--p 00000000 08:01 42 /bin/true\nSize: 4 kB\n";
+    let mappings = smaps::parse(text);
+    assert_eq!(mappings.len(), 1);
+    assert_eq!(mappings[0].protection_key, None);
+    assert!(mappings[0].vm_flags.is_empty());
+}
+
+#[test]
+fn parse_keeps_runs_of_spaces_in_pathnames() {
+    let text = "\
+00400000-00401000 rw-p 00000000 08:01 42                         /tmp/a  b
+7f3a1c000000-7f3a1c002000 rw-p 00000000 00:00 0                          [anon: x  y]
+";
+    let pathnames: Vec<_> = smaps::parse(text).into_iter().map(|mapping| mapping.pathname).collect();
+    assert_eq!(pathnames, [Some("/tmp/a  b".to_string()), Some("[anon: x  y]".to_string())]);
+}
+
+#[test]
+fn verify_reports_rights_changed_behind_the_crates_back() {
+    unsafe {
+        let region = UnsafeProtectedRegion::<allocator::Mmap, u64>::new(AccessRights::READ_WRITE).unwrap();
+        assert!(region.verify().unwrap().is_empty());
+        assert_eq!(region.effective_access().unwrap(), AccessRights::READ_WRITE);
+
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        assert_eq!(libc::mprotect(region.ptr() as *mut libc::c_void, page_size, libc::PROT_READ), 0);
+
+        let drifts = region.verify().unwrap();
+        assert!(matches!(
+            drifts.as_slice(),
+            [ProtectionDrift::AccessRights { expected: AccessRights::READ_WRITE, actual: AccessRights::READ, .. }]
+        ), "{:?}", drifts);
+        assert_eq!(region.effective_access().unwrap(), AccessRights::READ);
+        assert_eq!(region.kernel_state().unwrap().access_rights(), AccessRights::READ);
+    }
+}
+
+#[test]
+fn verify_reports_pkey_changed_behind_the_crates_back() {
+    if !probe::pku_supported() || !probe::ospke_enabled() {
+        return;
+    }
+    unsafe {
+        let pkey = PKey::new(PkeyAccessRights::EnableAccessWrite).unwrap();
+        let region = UnsafeProtectedRegion::<allocator::Mmap, u64>::new(AccessRights::READ_WRITE).unwrap();
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let ret = libc::syscall(libc::SYS_pkey_mprotect, region.ptr(), page_size, libc::PROT_READ | libc::PROT_WRITE, pkey.key());
+        assert_eq!(ret, 0);
+
+        let drifts = region.verify().unwrap();
+        assert!(matches!(drifts.as_slice(), [ProtectionDrift::Pkey { expected: 0, actual, .. }] if *actual == pkey.key()), "{:?}", drifts);
+        assert_eq!(region.kernel_state().unwrap().pkey(), Some(pkey.key()));
+        libc::syscall(libc::SYS_pkey_mprotect, region.ptr(), page_size, libc::PROT_READ | libc::PROT_WRITE, 0);
+    }
+}
@@ -0,0 +1,41 @@
+use mprotect_rs::*;
+use mprotect_rs::testing::SEGV_ACCERR;
+
+#[test]
+fn transitions_change_page_rights() {
+    let mut region = TypedRegion::<allocator::Mmap, u32, _>::new(1, AccessPermissions::ReadWrite).unwrap();
+    assert_eq!(region.access_rights(), AccessRights::READ_WRITE);
+    *region.write() += 1;
+
+    let region = region.into_read_only().unwrap();
+    assert_eq!(region.access_rights(), AccessRights::READ);
+    assert_eq!(unsafe { region.get_region() }.kernel_state().unwrap().access_rights(), AccessRights::READ);
+    assert_eq!(*region.read(), 2);
+
+    let region = region.into_no_access().unwrap();
+    assert_eq!(unsafe { region.get_region() }.kernel_state().unwrap().access_rights(), AccessRights::NONE);
+
+    let mut region = region.into_read_write().unwrap();
+    *region.write() = 3;
+    assert_eq!(*region.read(), 3);
+}
+
+#[test]
+fn write_only_state_allows_writes() {
+    let region = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::NoAccess).unwrap();
+    let mut region = region.into_access(AccessPermissions::WriteOnly).unwrap();
+    *region.write() = 9;
+    let region = region.into_read_only().unwrap();
+    assert_eq!(*region.read(), 9);
+}
+
+should_fault! {
+    signal: libc::SIGSEGV, code: SEGV_ACCERR;
+    /// The type system forbids writing to a read-only region; bypassing it with a raw
+    /// pointer is caught by the page permissions.
+    fn raw_write_to_read_only_region_faults() {
+        let region = TypedRegion::<allocator::Mmap, u32, _>::new(0, AccessPermissions::ReadOnly).unwrap();
+        let ptr = region.read() as *const u32 as *mut u32;
+        unsafe { ptr.write_volatile(1) };
+    }
+}